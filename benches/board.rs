@@ -0,0 +1,132 @@
+// Compares the bitboard with the grid of colours the board used to be, on what the AI does most:
+// testing where pieces fit, clearing lines and counting holes.
+//     cargo bench --bench board
+extern crate tetris;
+
+use tetris::board::Board;
+use tetris::pieces::PieceSet;
+use tetris::tetrimino::Tetrimino;
+
+use std::hint::black_box;
+use std::time::Instant;
+
+const ITERATIONS: u32 = 20_000;
+
+// the way pieces were tested against the grid of colours
+fn grid_test_position(piece: &Tetrimino, game_map: &[Vec<u8>], state: usize, x: isize, y: usize) -> bool {
+    for decal_y in 0..4 {
+        for decal_x in 0..4 {
+            let x = x + decal_x;
+            if piece.states[state][decal_y][decal_x as usize] != 0
+                && (y + decal_y >= game_map.len() || x < 0 || x as usize >= game_map[y + decal_y].len() ||
+                    game_map[y + decal_y][x as usize] != 0) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+// the way full lines were removed from the grid of colours
+fn grid_clear_lines(game_map: &mut Vec<Vec<u8>>) -> u32 {
+    let height = game_map.len();
+    let mut y = 0;
+    while y < game_map.len() {
+        if game_map[y].iter().all(|case| *case != 0) {
+            game_map.remove(y);
+        } else {
+            y += 1;
+        }
+    }
+    let cleared = height - game_map.len();
+    while game_map.len() < height {
+        game_map.insert(0, vec![0; 10]);
+    }
+    cleared as u32
+}
+
+fn grid_holes(game_map: &[Vec<u8>]) -> u32 {
+    let mut holes = 0;
+    for x in 0..game_map[0].len() {
+        let mut seen_block = false;
+        for line in game_map {
+            if line[x] != 0 {
+                seen_block = true;
+            } else if seen_block {
+                holes += 1;
+            }
+        }
+    }
+    holes
+}
+
+// a messy stack with a few full lines in it
+fn sample_colours() -> Vec<Vec<u8>> {
+    let mut colours = vec![vec![0; 10]; 16];
+    for (y, line) in colours.iter_mut().enumerate().skip(6) {
+        for (x, case) in line.iter_mut().enumerate() {
+            if y % 4 == 0 || (x * 7 + y * 3) % 5 != 0 {
+                *case = 1 + (x % 7) as u8;
+            }
+        }
+    }
+    colours
+}
+
+fn bench<F: FnMut() -> u32>(name: &str, mut f: F) -> f64 {
+    let start = Instant::now();
+    let mut total = 0;
+    for _ in 0..ITERATIONS {
+        total += black_box(f());
+    }
+    black_box(total);
+    let nanos = start.elapsed().as_nanos() as f64 / ITERATIONS as f64;
+    println!("{:<32} {:>10.0} ns", name, nanos);
+    nanos
+}
+
+// how many times the grid takes as long as the bitboard
+fn compare(name: &str, grid: f64, bitboard: f64) {
+    println!("{:<32} {:>10.1}x\n", name, grid / bitboard);
+}
+
+pub fn main() {
+    let colours = sample_colours();
+    let board = Board::from_colours(colours.clone()).unwrap();
+    let piece = PieceSet::standard().create(7);
+
+    // every position and state of the piece
+    let grid = bench("fits, grid", || {
+        let mut fits = 0;
+        for state in 0..piece.states.len() {
+            for y in 0..colours.len() {
+                for x in -3..10 {
+                    fits += grid_test_position(&piece, &colours, state, x, y) as u32;
+                }
+            }
+        }
+        fits
+    });
+    let bits = bench("fits, bitboard", || {
+        let mut fits = 0;
+        for state in 0..piece.states.len() {
+            for y in 0..board.height() {
+                for x in -3..10 {
+                    fits += piece.test_position(&board, state, x, y) as u32;
+                }
+            }
+        }
+        fits
+    });
+    compare("fits", grid, bits);
+
+    let grid = bench("clear lines, grid", || grid_clear_lines(&mut colours.clone()));
+    let bits = bench("clear lines, bitboard", || board.bits.clone().clear_lines());
+    let full = bench("clear lines, bitboard + colours", || board.clone().clear_lines());
+    compare("clear lines", grid, bits);
+    compare("clear lines with colours", grid, full);
+
+    let grid = bench("holes, grid", || grid_holes(&colours));
+    let bits = bench("holes, bitboard", || board.bits.holes());
+    compare("holes", grid, bits);
+}
@@ -0,0 +1,66 @@
+// A very simple bot speaking the Tetris Bot Protocol, to try out `tetris --bot`:
+//     cargo build --example mock_tbp_bot && cargo run -- --bot target/debug/examples/mock_tbp_bot
+// It doesn't look at the board at all and suggests every location of the current piece, lowest first,
+// leaving it to the front-end to pick the first one that can be reached.
+
+#[macro_use]
+extern crate serde_json;
+
+use serde_json::Value;
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+
+const ORIENTATIONS: [&str; 4] = ["north", "east", "south", "west"];
+
+fn send(message: &Value) {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let _ = writeln!(stdout, "{}", message);
+    let _ = stdout.flush();
+}
+
+pub fn main() {
+    let mut queue: VecDeque<String> = VecDeque::new();
+    send(&json!({"type": "info", "name": "mock", "version": "0.1", "author": "", "features": []}));
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let message: Value = match line.ok().and_then(|line| serde_json::from_str(&line).ok()) {
+            Some(message) => message,
+            None => break
+        };
+        match message["type"].as_str() {
+            Some("rules") => send(&json!({"type": "ready"})),
+            Some("start") => {
+                queue = message["queue"].as_array().map(|pieces| {
+                    pieces.iter().filter_map(|piece| piece.as_str().map(|piece| piece.to_string())).collect()
+                }).unwrap_or_default();
+            }
+            Some("new_piece") => {
+                if let Some(piece) = message["piece"].as_str() {
+                    queue.push_back(piece.to_string());
+                }
+            }
+            Some("suggest") => {
+                let mut moves = Vec::new();
+                if let Some(piece) = queue.front() {
+                    for y in 0..40 {
+                        for orientation in ORIENTATIONS.iter() {
+                            for x in 0..10 {
+                                moves.push(json!({"location": {"type": piece, "orientation": orientation, "x": x, "y": y},
+                                    "spin": "none"}));
+                            }
+                        }
+                    }
+                }
+                send(&json!({"type": "suggestion", "moves": moves}));
+            }
+            Some("play") => {
+                queue.pop_front();
+            }
+            Some("quit") => break,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use tetris::Tetris;
+use tetrimino::Tetrimino;
+use board::Board;
+use input::Action;
+use highscore::{read_from_file, write_to_file};
+use pathfind::{cells_at, find_path, next_position, reachable, Position};
+
+use std::collections::{HashSet, VecDeque};
+use std::io;
+
+// frames the AI waits between two inputs, so its moves can be followed on screen
+const AI_INPUT_DELAY: u32 = 3;
+// weights the in-game AI plays with instead of the default ones, as written by the tuner
+pub const WEIGHTS_FILE: &str = "weights.txt";
+pub const WEIGHT_NAMES: [&str; 8] = ["landing_height", "lines_cleared", "aggregate_height", "holes", "bumpiness", "wells",
+    "row_transitions", "column_transitions"];
+
+// how much each feature of the board after a placement counts towards its score, in the style of
+// Dellacherie's and El-Tetris's evaluators
+#[derive(Clone, Copy, Debug)]
+pub struct Weights {
+    pub landing_height: f64,
+    pub lines_cleared: f64,
+    pub aggregate_height: f64,
+    pub holes: f64,
+    pub bumpiness: f64,
+    pub wells: f64,
+    pub row_transitions: f64,
+    pub column_transitions: f64,
+}
+
+impl Default for Weights {
+    // El-Tetris's weights, which don't look at the aggregate height or the bumpiness
+    fn default() -> Weights {
+        Weights {
+            landing_height: -4.500158825082766,
+            lines_cleared: 3.4181268101392694,
+            aggregate_height: 0.0,
+            holes: -7.899265427351652,
+            bumpiness: 0.0,
+            wells: -3.3855972247263626,
+            row_transitions: -3.2178882868487753,
+            column_transitions: -9.348695305445199,
+        }
+    }
+}
+
+impl Weights {
+    // in the same order as WEIGHT_NAMES
+    pub fn to_array(&self) -> [f64; 8] {
+        [self.landing_height, self.lines_cleared, self.aggregate_height, self.holes, self.bumpiness, self.wells,
+            self.row_transitions, self.column_transitions]
+    }
+
+    pub fn from_array(values: [f64; 8]) -> Weights {
+        Weights {
+            landing_height: values[0],
+            lines_cleared: values[1],
+            aggregate_height: values[2],
+            holes: values[3],
+            bumpiness: values[4],
+            wells: values[5],
+            row_transitions: values[6],
+            column_transitions: values[7],
+        }
+    }
+
+    // one "name value" line per weight
+    pub fn save(&self, file_name: &str) -> io::Result<()> {
+        let content = WEIGHT_NAMES.iter().zip(self.to_array().iter())
+            .map(|(name, value)| format!("{} {}\n", name, value))
+            .collect::<String>();
+        write_to_file(&content, file_name)
+    }
+
+    pub fn load(file_name: &str) -> Option<Weights> {
+        Weights::parse(&read_from_file(file_name).ok()?)
+    }
+
+    // weights missing from the file keep their default value, and ones this version doesn't know are left out
+    pub fn parse(content: &str) -> Option<Weights> {
+        let mut values = Weights::default().to_array();
+        for line in content.lines() {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            if let [name, value] = words.as_slice() {
+                if let Some(index) = WEIGHT_NAMES.iter().position(|weight| weight == name) {
+                    // "NaN" and "inf" parse as numbers, but would make every placement score the same
+                    values[index] = value.parse::<f64>().ok().filter(|value| value.is_finite())?;
+                }
+            }
+        }
+        Some(Weights::from_array(values))
+    }
+}
+
+pub struct Features {
+    pub landing_height: f64,
+    pub lines_cleared: u32,
+    pub aggregate_height: u32,
+    pub holes: u32,
+    pub bumpiness: u32,
+    pub wells: u32,
+    pub row_transitions: u32,
+    pub column_transitions: u32,
+}
+
+impl Features {
+    pub fn score(&self, weights: &Weights) -> f64 {
+        weights.landing_height * self.landing_height +
+            weights.lines_cleared * self.lines_cleared as f64 +
+            weights.aggregate_height * self.aggregate_height as f64 +
+            weights.holes * self.holes as f64 +
+            weights.bumpiness * self.bumpiness as f64 +
+            weights.wells * self.wells as f64 +
+            weights.row_transitions * self.row_transitions as f64 +
+            weights.column_transitions * self.column_transitions as f64
+    }
+}
+
+// a place the piece can be dropped to, and the inputs that take it there
+pub struct Placement {
+    pub piece: Tetrimino,
+    pub actions: Vec<Action>,
+    pub score: f64,
+}
+
+pub fn piece_cells(piece: &Tetrimino) -> HashSet<(isize, usize)> {
+    cells_at(piece, (piece.x, piece.y, piece.current_state))
+}
+
+// copies the piece into the map and removes the completed lines, returning how many there were
+pub fn place_piece(game_map: &Board, piece: &Tetrimino) -> (Board, u32) {
+    let mut game_map = game_map.clone();
+    game_map.place(piece);
+    let lines_cleared = game_map.clear_lines();
+    (game_map, lines_cleared)
+}
+
+// only looks at which cells are filled, so the colours of the board aren't copied
+pub fn evaluate(game_map: &Board, piece: &Tetrimino) -> Features {
+    let masks = &piece.masks[piece.current_state as usize];
+    let mut after = game_map.bits.clone();
+    after.place(masks, piece.x, piece.y);
+    let lines_cleared = after.clear_lines();
+
+    let height = game_map.height();
+    let num_cells = masks.iter().map(|mask| mask.count_ones()).sum::<u32>();
+    let landing_height = masks.iter().enumerate()
+        .filter(|&(_, mask)| *mask != 0)
+        .map(|(line_num, mask)| ((height - piece.y - line_num) as u32 * mask.count_ones()) as f64)
+        .sum::<f64>() / num_cells as f64;
+
+    let heights = after.column_heights();
+    let aggregate_height = heights.iter().sum();
+    let bumpiness = heights.windows(2).map(|pair| (pair[0] as i32 - pair[1] as i32).unsigned_abs()).sum();
+
+    Features {
+        landing_height,
+        lines_cleared,
+        aggregate_height,
+        holes: after.holes(),
+        bumpiness,
+        wells: after.wells(),
+        row_transitions: after.row_transitions(),
+        column_transitions: after.column_transitions(),
+    }
+}
+
+// every placement the piece can reach, including the ones under overhangs, with the inputs that take it there
+pub fn placements(game_map: &Board, piece: &Tetrimino) -> Vec<(Tetrimino, Vec<Action>)> {
+    reachable(game_map, piece).into_iter().map(|path| (path.piece, path.actions)).collect()
+}
+
+// the pieces that can be played this turn with the inputs that bring each one in: the current piece and,
+// if holding is allowed, the held piece or the next one when nothing is held yet
+pub fn playable_pieces(tetris: &Tetris) -> Vec<(Tetrimino, Vec<Action>)> {
+    let mut pieces = Vec::new();
+    if let Some(ref piece) = tetris.current_piece {
+        pieces.push((piece.clone(), Vec::new()));
+        if tetris.can_hold {
+            let kind = match tetris.held_piece {
+                Some(ref held) => Some(held.kind),
+                None => tetris.next_pieces.front().cloned()
+            };
+            if let Some(kind) = kind {
+                let swapped = tetris.spawn_tetrimino(kind);
+                if swapped.test_current_position(&tetris.game_map) {
+                    pieces.push((swapped, vec![Action::Hold]));
+                }
+            }
+        }
+    }
+    pieces
+}
+
+pub fn best_placement(tetris: &Tetris, weights: &Weights) -> Option<Placement> {
+    let mut best: Option<Placement> = None;
+    for (piece, prefix) in playable_pieces(tetris) {
+        for (placed, actions) in placements(&tetris.game_map, &piece) {
+            let score = evaluate(&tetris.game_map, &placed).score(weights);
+            if best.as_ref().is_none_or(|best| score > best.score) {
+                let mut path = prefix.clone();
+                path.extend(actions);
+                best = Some(Placement {piece: placed, actions: path, score});
+            }
+        }
+    }
+    best
+}
+
+pub trait Bot {
+    // the inputs that take the current piece to where the bot wants it, ending with a hard drop
+    fn plan(&mut self, tetris: &Tetris) -> Vec<Action>;
+
+    // what the bot has had to say since it was last asked, like why the built-in AI is playing for it, for the
+    // front-end to show however suits it
+    fn take_messages(&mut self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+pub struct HeuristicBot {
+    pub weights: Weights,
+}
+
+impl HeuristicBot {
+    pub fn new(weights: Weights) -> HeuristicBot {
+        HeuristicBot {weights}
+    }
+}
+
+impl Bot for HeuristicBot {
+    fn plan(&mut self, tetris: &Tetris) -> Vec<Action> {
+        best_placement(tetris, &self.weights).map(|placement| placement.actions).unwrap_or_default()
+    }
+}
+
+// where the inputs leave the piece once it locks, leaving gravity out
+fn drop_target(tetris: &Tetris, actions: &[Action]) -> Option<Tetrimino> {
+    let mut game = tetris.clone();
+    for action in actions {
+        game.gravity_frames = 0;
+        game.step(&[*action]);
+        if game.num_pieces != tetris.num_pieces {
+            return game.locked_piece
+        }
+    }
+    None
+}
+
+// plays the game by feeding a bot's inputs to `Tetris::step` one at a time, like a player would
+pub struct BotPlayer {
+    pub bot: Box<dyn Bot>,
+    pub input_delay: u32,
+    plan: VecDeque<Action>,
+    // the piece the plan was made for, counted by `Tetris::num_pieces`
+    planned_piece: u32,
+    // where the plan drops the piece, so another way there can be found if gravity moves it off course
+    target: Option<Tetrimino>,
+    // where the last input should have left the piece, when it is still the same piece
+    expected: Option<Position>,
+    wait: u32,
+}
+
+impl BotPlayer {
+    pub fn new(bot: Box<dyn Bot>) -> BotPlayer {
+        BotPlayer {
+            bot,
+            input_delay: AI_INPUT_DELAY,
+            plan: VecDeque::new(),
+            planned_piece: 0,
+            target: None,
+            expected: None,
+            wait: 0,
+        }
+    }
+
+    // forgets the plan, for when the bot starts on another game
+    pub fn reset(&mut self) {
+        self.plan.clear();
+        self.expected = None;
+        self.wait = 0;
+    }
+
+    pub fn take_messages(&mut self) -> Vec<String> {
+        self.bot.take_messages()
+    }
+
+    // the inputs to make on the coming frame
+    pub fn next_actions(&mut self, tetris: &Tetris) -> Vec<Action> {
+        // gravity got there first and locked the piece somewhere else
+        if self.planned_piece != tetris.num_pieces {
+            self.plan.clear();
+        }
+        let piece = match tetris.current_piece {
+            Some(ref piece) => piece,
+            None => return Vec::new()
+        };
+        if self.wait > 0 {
+            self.wait -= 1;
+            return Vec::new()
+        }
+
+        let position = (piece.x, piece.y, piece.current_state);
+        if self.plan.is_empty() {
+            let plan = self.bot.plan(tetris);
+            self.target = drop_target(tetris, &plan);
+            self.plan = plan.into_iter().collect();
+            self.planned_piece = tetris.num_pieces;
+        } else if self.expected.is_some_and(|expected| expected != position) {
+            // the piece fell since the last input, which matters when it has to be slid under something
+            if let Some(path) = self.target.as_ref().and_then(|target| find_path(&tetris.game_map, piece, target)) {
+                self.plan = path.actions.into_iter().collect();
+            }
+        }
+
+        self.wait = self.input_delay;
+        let action = self.plan.pop_front();
+        self.expected = match action {
+            Some(Action::Hold) | Some(Action::HardDrop) | None => None,
+            Some(action) => Some(next_position(&tetris.game_map, piece, position, action).unwrap_or(position)),
+        };
+        action.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weights() {
+        let weights = Weights::parse("holes -2.5\nwells 1\nsomething_new 3\n\nlines_cleared 0.25\n").unwrap();
+        let mut expected = Weights::default().to_array();
+        expected[3] = -2.5;
+        expected[5] = 1.0;
+        expected[1] = 0.25;
+        assert_eq!(weights.to_array(), expected);
+
+        let all = WEIGHT_NAMES.iter().enumerate().map(|(index, name)| format!("{} {}\n", name, index)).collect::<String>();
+        assert_eq!(Weights::parse(&all).unwrap().to_array(), [0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0]);
+        for value in &["NaN", "inf", "-inf", "lots"] {
+            assert!(Weights::parse(&format!("holes {}\n", value)).is_none(), "{}", value);
+        }
+    }
+}
@@ -0,0 +1,493 @@
+// Runs many games with a bot and no window, and reports how well it did:
+//     tetris-sim [--games N] [--seed S] [--threads N] [--max-pieces N] [--mode marathon]
+//                [--board classic|guideline|<width>x<height>[+<hidden lines>]] [--lock-out full|partial]
+//                [--pieces <file>] [--bot "<command>" | --weights <file>] [--format csv|json] [--output <file>]
+// The stats of each game are written as CSV or JSON to the output file, or to stdout, and a summary of all
+// of them is printed to stderr.
+//
+// Or tunes the weights of the built-in AI:
+//     tetris-sim tune [--population N] [--generations N] [--games N] [--max-pieces N] [--seed S] [--threads N]
+//                     [--board ...] [--lock-out ...] [--pieces <file>] [--checkpoint <file>] [--output <file>]
+// The population is saved to the checkpoint file after every generation, and a run picks up from it if it
+// already exists. The best weights so far are written to the output file, which the game loads from weights.txt.
+//
+// Or plays a versus match over the network with the built-in AI, to try out the protocol between two processes:
+//     tetris-sim host [--port N] [--rounds N] [--garbage-delay N] [--seed S] [--board ...] [--lock-out ...]
+//                     [--pieces <file>] [--weights <file>]
+//     tetris-sim join <address> [--weights <file>]
+//
+// Or runs the server of a battle royale, and plays in one with a number of bots:
+//     tetris-sim royale-server [--port N] [--players N] [--margin <seconds>] [--garbage-delay N] [--seed S]
+//                              [--board ...] [--lock-out ...] [--pieces <file>]
+//     tetris-sim royale <address> [--bots N] [--targeting random|attackers|kos|badges] [--input-delay N] [--weights <file>]
+//
+// Or checks that a replay plays out to the result stored in it, exiting with a non-zero status if it doesn't:
+//     tetris-sim verify <file>
+extern crate tetris;
+#[macro_use]
+extern crate serde_json;
+
+use tetris::ai::{Bot, BotPlayer, HeuristicBot, Weights, WEIGHTS_FILE};
+use tetris::garbage::DEFAULT_GARBAGE_DELAY;
+use tetris::net::{Session, DEFAULT_PORT};
+use tetris::randomizer::random_seed;
+use tetris::replay::{verify_file, Mode};
+use tetris::royale::{Targeting, TARGETINGS};
+use tetris::royale_net::{RoyaleClient, RoyaleServer, DEFAULT_MARGIN, DEFAULT_ROYALE_PORT};
+use tetris::sim::{run_games, summarize, GameStats};
+use tetris::pieces::PieceSet;
+use tetris::tbp::TbpBot;
+use tetris::tetris::{BoardSettings, FPS};
+use tetris::tune::{TuneSettings, Tuner};
+use tetris::versus::RoundResult;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Write};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "Usage: tetris-sim [--games N] [--seed S] [--threads N] [--max-pieces N] [--mode marathon] \
+    [--board classic|guideline|<width>x<height>[+<hidden lines>]] [--lock-out full|partial] [--pieces <file>] \
+    [--bot \"<command>\" | --weights <file>] [--format csv|json] [--output <file>]
+       tetris-sim tune [--population N] [--generations N] [--games N] [--max-pieces N] [--seed S] [--threads N] \
+    [--board ...] [--lock-out full|partial] [--pieces <file>] [--checkpoint <file>] [--output <file>]
+       tetris-sim host [--port N] [--rounds N] [--garbage-delay N] [--seed S] [--board ...] [--lock-out ...] \
+    [--pieces <file>] [--weights <file>]
+       tetris-sim join <address> [--weights <file>]
+       tetris-sim royale-server [--port N] [--players N] [--margin <seconds>] [--garbage-delay N] [--seed S] [--board ...] \
+    [--lock-out ...] [--pieces <file>]
+       tetris-sim royale <address> [--bots N] [--targeting random|attackers|kos|badges] [--input-delay N] [--weights <file>]
+       tetris-sim verify <file>";
+const CHECKPOINT_FILE: &str = "tune.txt";
+
+type Stat = fn(&GameStats) -> f64;
+
+struct Options {
+    games: u64,
+    seed: u64,
+    threads: usize,
+    max_pieces: u32,
+    mode: Mode,
+    board: BoardSettings,
+    pieces: Arc<PieceSet>,
+    bot_command: Option<String>,
+    weights: Option<String>,
+    json: bool,
+    output: Option<String>,
+    // only used when tuning
+    population: usize,
+    generations: u32,
+    checkpoint: String,
+    // only used when hosting a versus match or a battle royale
+    port: u16,
+    rounds: u32,
+    garbage_delay: u32,
+    players: usize,
+    margin: Duration,
+    // only used by the bots of a battle royale, which all target the same way if one is given
+    bots: usize,
+    targeting: Option<Targeting>,
+    input_delay: Option<u32>,
+}
+
+fn parse_args(args: &[String], command: Option<&str>) -> Result<Options, String> {
+    let tuning = command == Some("tune");
+    let hosting = command == Some("host") || command == Some("royale-server");
+    let royale = command == Some("royale");
+    let mut options = Options {
+        games: if tuning { 10 } else { 100 },
+        seed: random_seed(),
+        threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        max_pieces: if tuning { 500 } else { 10000 },
+        mode: Mode::Marathon,
+        board: BoardSettings::default(),
+        pieces: Arc::new(PieceSet::standard()),
+        bot_command: None,
+        weights: None,
+        json: false,
+        output: None,
+        population: 30,
+        generations: 20,
+        checkpoint: CHECKPOINT_FILE.to_string(),
+        port: if command == Some("royale-server") { DEFAULT_ROYALE_PORT } else { DEFAULT_PORT },
+        rounds: 3,
+        garbage_delay: DEFAULT_GARBAGE_DELAY,
+        players: 8,
+        margin: DEFAULT_MARGIN,
+        bots: 1,
+        targeting: None,
+        input_delay: None,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or(format!("{} needs a value", arg))?;
+        let number = || value.parse::<u64>().map_err(|_| format!("{} needs a number, not {}", arg, value));
+        match arg.as_str() {
+            "--games" => options.games = number()?,
+            "--seed" => options.seed = number()?,
+            "--threads" => options.threads = number()? as usize,
+            "--max-pieces" => options.max_pieces = number()? as u32,
+            "--mode" => options.mode = Mode::from_name(value).ok_or(format!("unknown mode {}", value))?,
+            // the lock out rule is kept whichever order the two come in
+            "--board" => {
+                let partial_lock_out = options.board.partial_lock_out;
+                options.board = BoardSettings::parse(value).ok_or(format!("unknown board {}", value))?;
+                options.board.partial_lock_out = partial_lock_out;
+            }
+            "--lock-out" => options.board.partial_lock_out = match value.as_str() {
+                "full" => false,
+                "partial" => true,
+                _ => return Err(format!("unknown lock out rule {}", value))
+            },
+            "--pieces" => options.pieces = Arc::new(PieceSet::load(value)?),
+            "--bot" => options.bot_command = Some(value.clone()),
+            "--weights" => options.weights = Some(value.clone()),
+            "--format" => options.json = match value.as_str() {
+                "csv" => false,
+                "json" => true,
+                _ => return Err(format!("unknown format {}", value))
+            },
+            "--output" => options.output = Some(value.clone()),
+            "--population" if tuning => options.population = number()?.max(2) as usize,
+            "--generations" if tuning => options.generations = number()? as u32,
+            "--checkpoint" if tuning => options.checkpoint = value.clone(),
+            "--port" if hosting => options.port = value.parse().map_err(|_| format!("{} isn't a port", value))?,
+            "--rounds" if hosting => options.rounds = number()?.max(1) as u32,
+            "--garbage-delay" if hosting => options.garbage_delay = number()? as u32,
+            "--players" if hosting => options.players = number()? as usize,
+            "--margin" if hosting => options.margin = Duration::from_secs(number()?),
+            "--bots" if royale => options.bots = number()?.max(1) as usize,
+            "--targeting" if royale => options.targeting = Some(Targeting::from_name(value)
+                .ok_or(format!("unknown targeting {}", value))?),
+            "--input-delay" if royale => options.input_delay = Some(number()? as u32),
+            _ => return Err(format!("unknown option {}", arg))
+        }
+    }
+    Ok(options)
+}
+
+fn game_to_json(game: &GameStats) -> serde_json::Value {
+    json!({
+        "seed": game.seed,
+        "score": game.score,
+        "lines": game.lines,
+        "pieces": game.pieces,
+        "max_height": game.max_height,
+        "tetrises": game.tetrises,
+        "tetris_rate": game.tetris_rate(),
+        "frames": game.frames,
+        "game_over": game.game_over,
+    })
+}
+
+fn write_games(w: &mut dyn Write, games: &[GameStats], json: bool) -> io::Result<()> {
+    if json {
+        let games = games.iter().map(game_to_json).collect::<Vec<_>>();
+        return writeln!(w, "{}", serde_json::to_string_pretty(&games).unwrap())
+    }
+    writeln!(w, "seed,score,lines,pieces,max_height,tetrises,tetris_rate,frames,game_over")?;
+    for game in games {
+        writeln!(w, "{},{},{},{},{},{},{:.4},{},{}", game.seed, game.score, game.lines, game.pieces, game.max_height,
+            game.tetrises, game.tetris_rate(), game.frames, game.game_over)?;
+    }
+    Ok(())
+}
+
+fn print_summary(games: &[GameStats]) {
+    let stats: [(&str, Stat); 5] = [
+        ("score", |game| game.score as f64),
+        ("lines", |game| game.lines as f64),
+        ("pieces", |game| game.pieces as f64),
+        ("max height", |game| game.max_height as f64),
+        ("tetris rate", GameStats::tetris_rate),
+    ];
+    let lost = games.iter().filter(|game| game.game_over).count();
+    eprintln!("{} games, {} lost before the piece limit", games.len(), lost);
+    eprintln!("{:>12} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}", "", "mean", "median", "p10", "p90", "min", "max");
+    for (name, stat) in stats.iter() {
+        let summary = summarize(&games.iter().map(stat).collect::<Vec<_>>());
+        eprintln!("{:>12} {:>10.2} {:>10.2} {:>10.2} {:>10.2} {:>10.2} {:>10.2}", name, summary.mean, summary.median,
+            summary.p10, summary.p90, summary.min, summary.max);
+    }
+}
+
+fn tune(options: Options) {
+    let output = options.output.unwrap_or_else(|| WEIGHTS_FILE.to_string());
+    let settings = TuneSettings {
+        population: options.population,
+        games: options.games as u32,
+        max_pieces: options.max_pieces,
+        threads: options.threads,
+        seed: options.seed,
+        board: options.board,
+        pieces: options.pieces.clone(),
+    };
+    let mut tuner = match Tuner::load_checkpoint(&options.checkpoint, settings.clone()) {
+        Some(tuner) => {
+            eprintln!("Resuming from generation {} of {}", tuner.generation, options.checkpoint);
+            tuner
+        }
+        None => {
+            eprintln!("Tuning from seed {} with a population of {}", options.seed, options.population);
+            Tuner::new(settings)
+        }
+    };
+
+    loop {
+        let best = tuner.best();
+        let mean = tuner.population.iter().map(|individual| individual.fitness).sum::<f64>() / tuner.population.len() as f64;
+        eprintln!("Generation {}: best score {:.1}, mean {:.1}", tuner.generation, best.fitness, mean);
+        if let Err(e) = tuner.save_checkpoint(&options.checkpoint).and_then(|_| best.weights.save(&output)) {
+            eprintln!("Failed to save the tuner's progress: {}", e);
+            process::exit(1);
+        }
+        if tuner.generation >= options.generations {
+            break
+        }
+        tuner.next_generation();
+    }
+    println!("{:?}", tuner.best().weights);
+}
+
+fn load_weights(options: &Options) -> Weights {
+    match options.weights {
+        Some(ref file_name) => match Weights::load(file_name) {
+            Some(weights) => weights,
+            None => {
+                eprintln!("Failed to load the weights from {}", file_name);
+                process::exit(1);
+            }
+        },
+        None => Weights::default()
+    }
+}
+
+// plays the match as fast as the other side keeps up, printing how each round went
+fn play_online(mut session: Session, weights: Weights) {
+    eprintln!("Playing as player {} on the {} board, best of {}", session.local + 1, session.versus.settings.name(),
+        session.versus.rounds_to_win * 2 - 1);
+    let mut bot = BotPlayer::new(Box::new(HeuristicBot::new(weights)));
+    let mut round = 0;
+    let mut actions = Vec::new();
+    while !session.finished {
+        if let Err(e) = session.poll() {
+            eprintln!("The match ended early: {}", e);
+            process::exit(1);
+        }
+        if actions.is_empty() {
+            actions = bot.next_actions(&session.versus.games[session.local]);
+        }
+        if session.send_inputs(&actions) {
+            actions.clear();
+        }
+        let mut stepped = false;
+        while session.step().unwrap_or_else(|e| {
+            eprintln!("The match ended early: {}", e);
+            process::exit(1);
+        }) {
+            stepped = true;
+            if let Some(result) = session.result.filter(|_| session.versus.round != round) {
+                round = session.versus.round;
+                let winner = match result {
+                    RoundResult::Winner(player) => format!("player {} won", player + 1),
+                    RoundResult::Draw => "it was a draw".to_string(),
+                };
+                eprintln!("Round {}: {}, sent {:?}, frame {}, latency {:?}", round, winner, session.versus.sent,
+                    session.frame, session.latency);
+                bot.reset();
+            }
+        }
+        if !stepped {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+    let winner = session.versus.winner().map_or("nobody".to_string(), |player| format!("player {}", player + 1));
+    println!("{} won the match {:?}", winner, session.versus.wins);
+}
+
+// runs the server of a battle royale until the match is over, printing how it goes
+fn serve_royale(options: &Options) {
+    eprintln!("Waiting for {} players on port {}", options.players, options.port);
+    let mut log = |line: String| eprintln!("{}", line);
+    let mut server = match RoyaleServer::host(options.port, options.players, options.seed, options.garbage_delay,
+        options.board, &options.pieces, &mut log) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    server.margin = options.margin;
+    eprintln!("The match has started");
+    server.run(&mut log);
+    let mut places = server.royale.players.iter().enumerate().collect::<Vec<_>>();
+    places.sort_by_key(|(_, player)| player.place);
+    for (index, player) in places {
+        println!("{}. player {}: {} KOs, {} badges, sent {} lines", player.place.unwrap_or(0), index + 1, player.kos,
+            player.badges, player.sent);
+    }
+}
+
+// plays in a battle royale with the built-in AI, in real time like a player would, returning where it finished
+fn play_royale(mut client: RoyaleClient, weights: Weights, input_delay: Option<u32>) -> Result<Option<usize>, String> {
+    let mut bot = BotPlayer::new(Box::new(HeuristicBot::new(weights)));
+    if let Some(delay) = input_delay {
+        bot.input_delay = delay;
+    }
+    let frame_time = Duration::from_secs(1) / FPS;
+    let mut last_frame = Instant::now();
+    while !client.finished {
+        client.poll()?;
+        while last_frame.elapsed() >= frame_time {
+            last_frame += frame_time;
+            if client.started && !client.game.game_over {
+                let actions = bot.next_actions(&client.game);
+                client.step(&actions)?;
+            }
+        }
+        // a player that is out only waits to hear where they finished
+        if let Some(remaining) = frame_time.checked_sub(last_frame.elapsed()) {
+            thread::sleep(remaining);
+        }
+    }
+    Ok(client.players[client.local].place)
+}
+
+// joins a battle royale with a number of bots, each on a thread of its own and targeting its own way unless told
+fn join_royale(address: &str, options: &Options) {
+    let weights = load_weights(options);
+    let bots = (0..options.bots).map(|bot| {
+        let targeting = options.targeting.unwrap_or(TARGETINGS[bot % TARGETINGS.len()]);
+        let address = address.to_string();
+        let input_delay = options.input_delay;
+        thread::spawn(move || {
+            let client = RoyaleClient::join(&address, targeting)?;
+            eprintln!("Bot {} is player {}, targeting {}", bot + 1, client.local + 1, targeting.name());
+            let local = client.local;
+            play_royale(client, weights, input_delay).map(|place| (local, place))
+        })
+    }).collect::<Vec<_>>();
+
+    let mut failed = false;
+    for (bot, handle) in bots.into_iter().enumerate() {
+        match handle.join().unwrap_or_else(|_| Err("the bot crashed".to_string())) {
+            Ok((local, place)) => println!("Bot {} (player {}) finished {}", bot + 1, local + 1,
+                place.map_or("nowhere".to_string(), |place| format!("in place {}", place))),
+            Err(e) => {
+                eprintln!("Bot {}: {}", bot + 1, e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+pub fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if args.first().map(|arg| arg.as_str()) == Some("verify") {
+        match args.get(1..) {
+            Some([file_name]) => {
+                let (report, status) = verify_file(file_name);
+                println!("{}", report);
+                process::exit(status);
+            }
+            _ => {
+                eprintln!("verify needs the file of a replay\n{}", USAGE);
+                process::exit(2);
+            }
+        }
+    }
+    let command = args.first().map(|arg| arg.as_str())
+        .filter(|command| ["tune", "host", "join", "royale-server", "royale"].contains(command));
+    // joining takes the address first
+    let skip = match command {
+        Some("join") | Some("royale") => 2,
+        Some(_) => 1,
+        None => 0
+    };
+    let options = match parse_args(args.get(skip..).unwrap_or(&[]), command) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    match command {
+        Some("tune") => return tune(options),
+        Some("royale-server") => return serve_royale(&options),
+        Some("royale") => return match args.get(1) {
+            Some(address) if address.contains(':') => join_royale(address, &options),
+            Some(address) => join_royale(&format!("{}:{}", address, DEFAULT_ROYALE_PORT), &options),
+            None => {
+                eprintln!("royale needs the address of the server\n{}", USAGE);
+                process::exit(2);
+            }
+        },
+        Some("host") | Some("join") => {
+            let session = if command == Some("host") {
+                eprintln!("Waiting for a player on port {}", options.port);
+                Session::host(options.port, options.seed, options.rounds, options.garbage_delay, options.board,
+                    options.pieces.clone())
+            } else {
+                match args.get(1) {
+                    Some(address) => Session::join(address),
+                    None => Err("join needs the address of the host".to_string())
+                }
+            };
+            return match session {
+                Ok(session) => play_online(session, load_weights(&options)),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        _ => {}
+    }
+
+    let weights = load_weights(&options);
+    let seeds = (0..options.games).map(|game| options.seed.wrapping_add(game)).collect::<Vec<_>>();
+    let bot_command = options.bot_command.clone();
+    let make_bot = || -> Box<dyn Bot> {
+        match bot_command {
+            Some(ref command) => match TbpBot::spawn(command) {
+                Ok(mut bot) => {
+                    // the games run as fast as they can, so they wait for the bot rather than leaving it behind
+                    bot.wait_for_moves = true;
+                    Box::new(bot)
+                }
+                Err(e) => {
+                    eprintln!("Bot {}: {}", command, e);
+                    process::exit(1);
+                }
+            },
+            None => Box::new(HeuristicBot::new(weights))
+        }
+    };
+    eprintln!("Playing {} {} games on the {} board from seed {} on {} threads", options.games, options.mode.name(),
+        options.board.name(), options.seed, options.threads);
+    let games = run_games(options.mode, options.board, &options.pieces, &seeds, options.threads, options.max_pieces,
+        make_bot);
+    // the stats may be going to stdout, so what the bot said goes to stderr
+    for game in &games {
+        for message in &game.bot_messages {
+            eprintln!("Game {}: {}", game.seed, message);
+        }
+    }
+
+    let result = match options.output {
+        Some(ref file_name) => File::create(file_name).and_then(|mut file| write_games(&mut file, &games, options.json)),
+        None => write_games(&mut io::stdout(), &games, options.json)
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to write the stats: {}", e);
+        process::exit(1);
+    }
+    print_summary(&games);
+}
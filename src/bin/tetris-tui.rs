@@ -0,0 +1,473 @@
+// Plays the game in a terminal, for machines without SDL or over SSH:
+//     tetris-tui [--board classic|guideline|<width>x<height>[+<hidden lines>]] [--lock-out full|partial]
+//                [--pieces <file>] [--bot "<bot command>"]
+// The board is drawn with half blocks in the 256 terminal colours, two lines of cells to a line of the terminal, and
+// made as big as the terminal allows. The game is the same one the window plays, with the same keys, saves and replays.
+extern crate crossterm;
+extern crate tetris;
+
+use tetris::ai::BotPlayer;
+use tetris::finesse::FinesseTracker;
+use tetris::hud::{hud_line, Hud};
+use tetris::highscore::{game_summary, player_name, record_highscores};
+use tetris::input::Action;
+use tetris::pieces::PieceSet;
+use tetris::randomizer::random_seed;
+use tetris::replay::{save_replay, Mode, Replay};
+use tetris::save::{delete_save, load_game, save_game};
+use tetris::tbp::create_bot;
+use tetris::tetris::{BoardSettings, Tetris, FPS};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Color, Colors, Print, ResetColor, SetColors};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Stdout, Write};
+use std::process;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "Usage: tetris-tui [--board classic|guideline|<width>x<height>[+<hidden lines>]] \
+    [--lock-out full|partial] [--pieces <file>] [--bot \"<bot command>\"]";
+const CONTROLS_FILE: &str = "controls.txt";
+// the colours of the textures in assets/, and of garbage, as the nearest of the 256 terminal colours
+const COLOURS: [u8; 8] = [148, 75, 125, 130, 221, 247, 24, 242];
+const EMPTY_COLOUR: u8 = 234;
+// the columns the side panel takes up, right of the board
+const PANEL_WIDTH: u16 = 26;
+const UPPER_HALF: char = '\u{2580}';
+const LOWER_HALF: char = '\u{2584}';
+
+// puts the terminal back the way it was however the game ends, even on a panic
+struct Screen {
+    stdout: Stdout,
+    // the lines as they were last drawn, so only the ones that change are written again
+    drawn: Vec<String>,
+}
+
+impl Screen {
+    fn new() -> io::Result<Screen> {
+        terminal::enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+        Ok(Screen {stdout, drawn: Vec::new()})
+    }
+
+    // writes the lines that have changed since they were last drawn
+    fn draw(&mut self, lines: &[String]) -> io::Result<()> {
+        if lines.len() != self.drawn.len() {
+            queue!(self.stdout, ResetColor, Clear(ClearType::All))?;
+            self.drawn.clear();
+        }
+        for (y, line) in lines.iter().enumerate() {
+            if self.drawn.get(y) != Some(line) {
+                queue!(self.stdout, MoveTo(0, y as u16), Print(line), ResetColor, Clear(ClearType::UntilNewLine))?;
+            }
+        }
+        self.drawn = lines.to_vec();
+        self.stdout.flush()
+    }
+
+    // the next frame has to be drawn in full, after the terminal was resized
+    fn redraw(&mut self) {
+        self.drawn.clear();
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(self.stdout, ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+// the key names of the controls file that a terminal can tell apart
+fn key_from_name(name: &str) -> Option<KeyCode> {
+    let key = match name {
+        "Left" => KeyCode::Left,
+        "Right" => KeyCode::Right,
+        "Up" => KeyCode::Up,
+        "Down" => KeyCode::Down,
+        "Space" => KeyCode::Char(' '),
+        "Return" => KeyCode::Enter,
+        "Escape" => KeyCode::Esc,
+        "Tab" => KeyCode::Tab,
+        "Backspace" => KeyCode::Backspace,
+        _ if name.chars().count() == 1 => KeyCode::Char(name.chars().next()?.to_ascii_lowercase()),
+        _ => return None
+    };
+    Some(key)
+}
+
+// the same keys as the window, changed by the "key" lines of the controls file
+fn load_bindings() -> Vec<(KeyCode, Action)> {
+    let mut bindings = vec![(KeyCode::Left, Action::MoveLeft),
+        (KeyCode::Right, Action::MoveRight),
+        (KeyCode::Down, Action::SoftDrop),
+        (KeyCode::Char(' '), Action::HardDrop),
+        (KeyCode::Up, Action::RotateClockwise),
+        (KeyCode::Char('z'), Action::RotateCounterClockwise),
+        (KeyCode::Char('c'), Action::Hold),
+        (KeyCode::Char('p'), Action::Pause),
+        (KeyCode::Esc, Action::Quit),
+        (KeyCode::Char('a'), Action::ToggleAi)];
+    let mut content = String::new();
+    if File::open(CONTROLS_FILE).and_then(|mut f| f.read_to_string(&mut content)).is_err() {
+        return bindings
+    }
+    let mut rebound = Vec::new();
+    for line in content.lines() {
+        if let ["key", name, action] = line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            if let (Some(key), Some(action)) = (key_from_name(name), Action::from_name(action)) {
+                if !rebound.contains(&action) {
+                    bindings.retain(|&(_, bound)| bound != action);
+                    rebound.push(action);
+                }
+                bindings.push((key, action));
+            }
+        }
+    }
+    bindings
+}
+
+// the key pressed, if a key was pressed; letters are the same whether shift is held or not, Ctrl+C quits, and the
+// terminal being resized comes back as Null for the screen to be drawn again in full
+fn read_key(timeout: Duration) -> io::Result<Option<KeyCode>> {
+    if !event::poll(timeout)? {
+        return Ok(None)
+    }
+    match event::read()? {
+        Event::Key(key) if key.kind == KeyEventKind::Release => Ok(None),
+        Event::Key(key) if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') => {
+            Ok(Some(KeyCode::Esc))
+        }
+        Event::Key(key) => Ok(Some(match key.code {
+            KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+            code => code
+        })),
+        Event::Resize(..) => Ok(Some(KeyCode::Null)),
+        _ => Ok(None)
+    }
+}
+
+// a grid of coloured cells, drawn as half blocks, two lines of cells to a line of text
+struct Canvas {
+    cells: Vec<Vec<Option<u8>>>,
+}
+
+impl Canvas {
+    // the height is rounded up to whole lines of text
+    fn new(width: usize, height: usize) -> Canvas {
+        Canvas {cells: vec![vec![None; width]; height + height % 2]}
+    }
+
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, colour: u8) {
+        for line in self.cells.iter_mut().skip(y).take(height) {
+            for cell in line.iter_mut().skip(x).take(width) {
+                *cell = Some(colour);
+            }
+        }
+    }
+
+    fn lines(&self) -> Vec<String> {
+        self.cells.chunks(2).map(|pair| {
+            let mut line = String::new();
+            let mut colours = None;
+            for (&upper, &lower) in pair[0].iter().zip(&pair[1]) {
+                // the half that is coloured is drawn in the foreground, the other one in the background
+                let (block, foreground, background) = match (upper, lower) {
+                    (Some(upper), lower) => (UPPER_HALF, upper, lower),
+                    (None, Some(lower)) => (LOWER_HALF, lower, None),
+                    (None, None) => (' ', 0, None),
+                };
+                let cell_colours = Colors::new(Color::AnsiValue(foreground), background.map_or(Color::Reset, Color::AnsiValue));
+                if colours != Some(cell_colours) {
+                    line += &SetColors(cell_colours).to_string();
+                    colours = Some(cell_colours);
+                }
+                line.push(block);
+            }
+            line + &ResetColor.to_string()
+        }).collect()
+    }
+}
+
+fn colour(case: u8) -> u8 {
+    COLOURS.get(case as usize - 1).cloned().unwrap_or(COLOURS[0])
+}
+
+// how many columns and half lines each cell of the board takes up for the board to fit in the terminal
+fn board_scale(settings: &BoardSettings) -> usize {
+    let (columns, lines) = terminal::size().unwrap_or((80, 24));
+    let by_width = (columns.saturating_sub(PANEL_WIDTH + 2) as usize) / settings.width;
+    let by_height = (lines as usize * 2) / settings.height;
+    by_width.min(by_height).clamp(1, 4)
+}
+
+// the cells of the piece with its box at the column and line, leaving out the ones above the top
+fn draw_piece_cells(canvas: &mut Canvas, state: &[Vec<u8>], x: isize, y: isize, (width, height): (usize, usize)) {
+    for (line_num, line) in state.iter().enumerate() {
+        for (case_num, case) in line.iter().enumerate() {
+            let (cx, cy) = (x + case_num as isize, y + line_num as isize);
+            if *case != 0 && cx >= 0 && cy >= 0 {
+                canvas.fill(cx as usize * width, cy as usize * height, width, height, colour(*case));
+            }
+        }
+    }
+}
+
+// the board with the falling piece on the left, the held and coming pieces and the numbers of the game on the right
+fn render(tetris: &Tetris, hud: &Hud, finesse: &FinesseTracker, ai: bool, bot_message: Option<&str>, paused: bool)
+    -> Vec<String> {
+    let settings = &tetris.settings;
+    let scale = board_scale(settings);
+    let (board_width, board_height) = (settings.width * scale, settings.height * scale);
+    let mut canvas = Canvas::new(board_width, board_height);
+    canvas.fill(0, 0, board_width, board_height, EMPTY_COLOUR);
+    for (line_num, line) in tetris.game_map.iter().skip(settings.hidden).enumerate() {
+        for (case_num, case) in line.iter().enumerate() {
+            if *case != 0 {
+                canvas.fill(case_num * scale, line_num * scale, scale, scale, colour(*case));
+            }
+        }
+    }
+    if let Some(ref piece) = tetris.current_piece {
+        draw_piece_cells(&mut canvas, &piece.states[piece.current_state as usize], piece.x,
+            piece.y as isize - settings.hidden as isize, (scale, scale));
+    }
+    let mut lines = canvas.lines();
+
+    // the side panel, next to the board
+    let mut panel = hud.lines(tetris);
+    panel.push(hud_line("Faults", &finesse.faults.to_string()));
+    panel.push(String::new());
+    panel.push("Hold:".to_string());
+    panel.extend(small_pieces(tetris, tetris.held_piece.iter().map(|piece| piece.kind).collect()));
+    panel.push("Next:".to_string());
+    panel.extend(small_pieces(tetris, tetris.next_pieces.iter().cloned().collect()));
+    panel.push(String::new());
+    if ai {
+        panel.push("AI playing".to_string());
+    }
+    if let Some(message) = bot_message {
+        panel.extend(wrap(message, PANEL_WIDTH as usize));
+    }
+    if paused {
+        panel.push("Paused".to_string());
+    }
+    while lines.len() < panel.len() {
+        lines.push(" ".repeat(board_width));
+    }
+    for (line, text) in lines.iter_mut().zip(panel) {
+        *line += "  ";
+        *line += &text;
+    }
+    lines
+}
+
+// the pieces one above another, each cell two columns wide and a half line high, which looks square
+fn small_pieces(tetris: &Tetris, kinds: Vec<u8>) -> Vec<String> {
+    let mut lines = Vec::new();
+    for kind in kinds {
+        let piece = tetris.pieces.create(kind);
+        // the empty lines of the piece's box are left out
+        let state = piece.states[0].iter().filter(|line| line.iter().any(|case| *case != 0)).cloned().collect::<Vec<_>>();
+        let mut canvas = Canvas::new(state.iter().map(|line| line.len()).max().unwrap_or(0) * 2, state.len());
+        draw_piece_cells(&mut canvas, &state, 0, 0, (2, 1));
+        lines.extend(canvas.lines());
+    }
+    lines
+}
+
+// the text split into lines no wider than `width` between its words, the words that are wider being cut
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        let word = word.chars().take(width).collect::<String>();
+        match lines.last_mut() {
+            Some(line) if line.chars().count() + 1 + word.chars().count() <= width => {
+                line.push(' ');
+                line.push_str(&word);
+            }
+            _ => lines.push(word)
+        }
+    }
+    lines
+}
+
+// asks whether to resume the suspended game, returning None if the player quits
+fn ask_to_continue(screen: &mut Screen, tetris: &Tetris) -> io::Result<Option<bool>> {
+    screen.draw(&["Saved game found".to_string(), format!("Score: {}  Level: {}", tetris.score, tetris.current_level),
+        "Continue? (Y/N)".to_string()])?;
+    loop {
+        match read_key(Duration::from_secs(1))? {
+            Some(KeyCode::Char('y')) | Some(KeyCode::Enter) => return Ok(Some(true)),
+            Some(KeyCode::Char('n')) => return Ok(Some(false)),
+            Some(KeyCode::Esc) => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
+// how the game ended, for what is printed once the terminal is back to normal
+enum Ending {
+    GameOver(Box<Tetris>, FinesseTracker, Option<Replay>),
+    Saved(bool),
+    Left,
+}
+
+fn play(screen: &mut Screen, settings: BoardSettings, pieces: Arc<PieceSet>, bot_command: Option<&str>) -> io::Result<Ending> {
+    let bindings = load_bindings();
+    let hud = Hud::load();
+    let mut tetris = Tetris::with_settings(random_seed(), settings, pieces.clone());
+    let mut replay = Some(Replay::new(Mode::Marathon, settings, pieces, tetris.seed, &player_name()));
+    if let Some(saved) = load_game() {
+        match ask_to_continue(screen, &saved.tetris)? {
+            Some(true) => {
+                tetris = saved.tetris;
+                replay = saved.replay;
+            }
+            Some(false) => delete_save(),
+            None => return Ok(Ending::Left)
+        }
+    }
+
+    let mut finesse = FinesseTracker::new(&tetris);
+    let mut paused = false;
+    let mut ai: Option<BotPlayer> = None;
+    // the last thing the bot had to say, shown in the side panel while it plays
+    let mut bot_message = None;
+    if bot_command.is_some() {
+        let (bot, message) = create_bot(bot_command, true);
+        ai = Some(BotPlayer::new(bot));
+        bot_message = message;
+    }
+    let mut actions = Vec::new();
+    let frame_time = Duration::from_secs(1) / FPS;
+    let mut last_frame = Instant::now();
+
+    loop {
+        let wait = frame_time.checked_sub(last_frame.elapsed()).unwrap_or_default();
+        // every key waiting is taken before the frame is played
+        let mut key = read_key(wait)?;
+        while let Some(code) = key {
+            match bindings.iter().find(|&&(bound, _)| bound == code).map(|&(_, action)| action) {
+                Some(Action::Quit) => return Ok(Ending::Saved(save_game(&tetris, replay.as_ref()))),
+                Some(Action::Pause) => paused = !paused,
+                Some(Action::ToggleAi) => {
+                    ai = match ai {
+                        Some(_) => None,
+                        None => {
+                            if let Some(ref mut replay) = replay {
+                                if !replay.player.ends_with(" + AI") {
+                                    replay.player += " + AI";
+                                }
+                            }
+                            let (bot, message) = create_bot(bot_command, true);
+                            bot_message = message;
+                            Some(BotPlayer::new(bot))
+                        }
+                    };
+                }
+                Some(_) if paused => {},
+                Some(action) => actions.push(action),
+                None if code == KeyCode::Null => screen.redraw(),
+                None => {}
+            }
+            key = read_key(Duration::from_secs(0))?;
+        }
+
+        if paused {
+            last_frame = Instant::now();
+        }
+        while !paused && last_frame.elapsed() >= frame_time {
+            last_frame += frame_time;
+            if let Some(ref mut ai) = ai {
+                actions = ai.next_actions(&tetris);
+                if let Some(message) = ai.take_messages().pop() {
+                    bot_message = Some(message);
+                }
+            }
+            if let Some(ref mut replay) = replay {
+                for action in &actions {
+                    replay.record(tetris.frame, *action);
+                }
+            }
+            tetris.step(&actions);
+            finesse.update(&tetris, &actions);
+            actions.clear();
+        }
+
+        if tetris.game_over {
+            delete_save();
+            return Ok(Ending::GameOver(Box::new(tetris), finesse, replay))
+        }
+        let message = bot_message.as_deref().filter(|_| ai.is_some());
+        screen.draw(&render(&tetris, &hud, &finesse, ai.is_some(), message, paused))?;
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<(BoardSettings, Arc<PieceSet>, Option<String>), String> {
+    let mut settings = BoardSettings::default();
+    let mut pieces = Arc::new(PieceSet::standard());
+    let mut bot_command = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or(format!("{} needs a value", arg))?;
+        match arg.as_str() {
+            "--board" => {
+                let partial_lock_out = settings.partial_lock_out;
+                settings = BoardSettings::parse(value).ok_or(format!("unknown board {}", value))?;
+                settings.partial_lock_out = partial_lock_out;
+            }
+            "--lock-out" => settings.partial_lock_out = match value.as_str() {
+                "full" => false,
+                "partial" => true,
+                _ => return Err(format!("unknown lock out rule {}", value))
+            },
+            "--pieces" => pieces = Arc::new(PieceSet::load(value)?),
+            "--bot" => bot_command = Some(value.clone()),
+            _ => return Err(format!("unknown option {}", arg))
+        }
+    }
+    Ok((settings, pieces, bot_command))
+}
+
+pub fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let (settings, pieces, bot_command) = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let ending = Screen::new().and_then(|mut screen| play(&mut screen, settings, pieces, bot_command.as_deref()));
+    // the screen is gone by now, so what is printed stays in the terminal
+    match ending {
+        Ok(Ending::GameOver(tetris, finesse, replay)) => {
+            if let Some(mut replay) = replay {
+                replay.finish(&tetris);
+                match save_replay(&replay) {
+                    Ok(file_name) => println!("Replay saved to {}, check it with `tetris-sim verify {}`", file_name, file_name),
+                    Err(e) => println!("Failed to save the replay: {}", e)
+                }
+            }
+            for line in game_summary(&tetris, &finesse, record_highscores(&tetris)) {
+                println!("{}", line);
+            }
+        }
+        Ok(Ending::Saved(true)) => println!("Game saved, it can be continued next time."),
+        Ok(Ending::Saved(false)) => println!("Failed to save the game."),
+        Ok(Ending::Left) => {}
+        Err(e) => {
+            eprintln!("The terminal failed: {}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,347 @@
+// The playing field. Which cells are filled is kept as one bit per cell, a u16 per line, so that collisions,
+// cleared lines and holes are worked out a whole line at a time; the colour of every cell is kept alongside
+// for drawing and saving the board.
+use tetrimino::Tetrimino;
+
+use std::ops::Deref;
+
+pub const MAX_WIDTH: usize = 16;
+// the colour of the lines sent by an opponent, after the colours of the pieces
+pub const GARBAGE: u8 = 8;
+// room for pieces to hang over either side of the board while they are tested against the walls
+const WALL: isize = 8;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Bitboard {
+    // the top line first, with column x in bit x
+    pub rows: Vec<u16>,
+    pub width: usize,
+}
+
+impl Bitboard {
+    pub fn new(width: usize, height: usize) -> Bitboard {
+        assert!(width <= MAX_WIDTH, "boards are at most {} columns wide", MAX_WIDTH);
+        Bitboard {rows: vec![0; height], width}
+    }
+
+    // the bits of a full line
+    pub fn full(&self) -> u16 {
+        ((1u32 << self.width) - 1) as u16
+    }
+
+    // the walls and the floor count as filled
+    pub fn is_filled(&self, x: isize, y: usize) -> bool {
+        x < 0 || x as usize >= self.width || y >= self.rows.len() || self.rows[y] & (1 << x) != 0
+    }
+
+    // whether a piece, given as the bits of each of its lines, fits with its top left corner at (x, y)
+    pub fn fits(&self, masks: &[u16], x: isize, y: usize) -> bool {
+        let shift = x + WALL;
+        if shift < 0 {
+            return false
+        }
+        let walls = !((self.full() as u64) << WALL);
+        for (line, mask) in masks.iter().enumerate() {
+            if *mask == 0 {
+                continue
+            }
+            if y + line >= self.rows.len() {
+                return false
+            }
+            if ((*mask as u64) << shift) & (walls | (self.rows[y + line] as u64) << WALL) != 0 {
+                return false
+            }
+        }
+        true
+    }
+
+    // the piece has to fit
+    pub fn place(&mut self, masks: &[u16], x: isize, y: usize) {
+        for (line, mask) in masks.iter().enumerate() {
+            if *mask != 0 {
+                self.rows[y + line] |= (((*mask as u64) << (x + WALL)) >> WALL) as u16;
+            }
+        }
+    }
+
+    // removes the full lines, moving the ones above down, and returns how many there were
+    pub fn clear_lines(&mut self) -> u32 {
+        let full = self.full();
+        let height = self.rows.len();
+        self.rows.retain(|row| *row != full);
+        let cleared = height - self.rows.len();
+        self.rows.splice(0..0, vec![0; cleared]);
+        cleared as u32
+    }
+
+    // pushes the lines up and fills the bottom ones with every column but `hole`, returning false if filled
+    // cells were pushed off the top
+    pub fn add_garbage(&mut self, lines: usize, hole: usize) -> bool {
+        let lines = lines.min(self.rows.len());
+        let overflow = self.rows[..lines].iter().any(|row| *row != 0);
+        let garbage = self.full() & !(1 << hole);
+        self.rows.drain(..lines);
+        self.rows.extend(vec![garbage; lines]);
+        !overflow
+    }
+
+    pub fn column_heights(&self) -> Vec<u32> {
+        let height = self.rows.len();
+        (0..self.width).map(|x| {
+            self.rows.iter().position(|row| row & (1 << x) != 0).map_or(0, |y| (height - y) as u32)
+        }).collect()
+    }
+
+    // empty cells with a filled one somewhere above them
+    pub fn holes(&self) -> u32 {
+        let mut covered = 0;
+        let mut holes = 0;
+        for row in &self.rows {
+            holes += (covered & !row).count_ones();
+            covered |= row;
+        }
+        holes
+    }
+
+    // changes between filled and empty along each line, the walls on both sides counting as filled
+    pub fn row_transitions(&self) -> u32 {
+        let walls = 1 | (1 << (self.width + 1));
+        let mask = (1u32 << (self.width + 1)) - 1;
+        self.rows.iter().map(|row| {
+            let line = ((*row as u32) << 1) | walls;
+            ((line ^ (line >> 1)) & mask).count_ones()
+        }).sum()
+    }
+
+    // changes between filled and empty down each column, from the empty space above the board to the floor
+    pub fn column_transitions(&self) -> u32 {
+        let mut previous = 0;
+        let mut transitions = 0;
+        for row in &self.rows {
+            transitions += (previous ^ row).count_ones();
+            previous = *row;
+        }
+        transitions + (previous ^ self.full()).count_ones()
+    }
+
+    // empty cells with filled cells or walls on both sides, each counting for how deep into its well it is
+    pub fn wells(&self) -> u32 {
+        let full = self.full();
+        let mut depths = [0; MAX_WIDTH];
+        let mut wells = 0;
+        for row in &self.rows {
+            let left_filled = (row << 1) | 1;
+            let right_filled = (row >> 1) | (1 << (self.width - 1));
+            let in_well = !row & left_filled & right_filled & full;
+            for (x, depth) in depths.iter_mut().enumerate().take(self.width) {
+                if in_well & (1 << x) != 0 {
+                    *depth += 1;
+                    wells += *depth;
+                } else {
+                    *depth = 0;
+                }
+            }
+        }
+        wells
+    }
+}
+
+// the board the game is played on; it reads like a grid of colours, 0 being an empty cell
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Board {
+    pub bits: Bitboard,
+    colours: Vec<Vec<u8>>,
+}
+
+impl Board {
+    pub fn new(width: usize, height: usize) -> Board {
+        Board {bits: Bitboard::new(width, height), colours: vec![vec![0; width]; height]}
+    }
+
+    // a board with the given colours, if every line is as wide as the first one and not too wide
+    pub fn from_colours(colours: Vec<Vec<u8>>) -> Option<Board> {
+        let width = colours.first()?.len();
+        if width == 0 || width > MAX_WIDTH || colours.iter().any(|line| line.len() != width) {
+            return None
+        }
+        let rows = colours.iter()
+            .map(|line| line.iter().enumerate().filter(|&(_, case)| *case != 0).fold(0, |row, (x, _)| row | 1 << x))
+            .collect();
+        Some(Board {bits: Bitboard {rows, width}, colours})
+    }
+
+    pub fn width(&self) -> usize {
+        self.bits.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.bits.rows.len()
+    }
+
+    // the piece has to fit
+    pub fn place(&mut self, piece: &Tetrimino) {
+        let state = piece.current_state as usize;
+        self.bits.place(&piece.masks[state], piece.x, piece.y);
+        for (line_num, line) in piece.states[state].iter().enumerate() {
+            for (case_num, case) in line.iter().enumerate() {
+                if *case != 0 {
+                    self.colours[piece.y + line_num][(piece.x + case_num as isize) as usize] = *case;
+                }
+            }
+        }
+    }
+
+    pub fn clear_lines(&mut self) -> u32 {
+        let full = self.bits.full();
+        if !self.bits.rows.contains(&full) {
+            return 0
+        }
+        let width = self.width();
+        let rows = &self.bits.rows;
+        let mut line = 0;
+        self.colours.retain(|_| {
+            line += 1;
+            rows[line - 1] != full
+        });
+        let cleared = self.bits.clear_lines();
+        self.colours.splice(0..0, vec![vec![0; width]; cleared as usize]);
+        cleared
+    }
+
+    pub fn add_garbage(&mut self, lines: usize, hole: usize) -> bool {
+        let width = self.width();
+        let lines = lines.min(self.height());
+        self.colours.drain(..lines);
+        self.colours.extend((0..lines).map(|_| (0..width).map(|x| if x == hole { 0 } else { GARBAGE }).collect()));
+        self.bits.add_garbage(lines, hole)
+    }
+}
+
+impl Deref for Board {
+    type Target = [Vec<u8>];
+
+    fn deref(&self) -> &[Vec<u8>] {
+        &self.colours
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pieces::PieceSet;
+
+    // a board from lines of colours, '.' being an empty cell
+    fn from_lines(lines: &[&str]) -> Board {
+        Board::from_colours(lines.iter().map(|line| {
+            line.chars().map(|c| c.to_digit(10).unwrap_or(0) as u8).collect()
+        }).collect()).unwrap()
+    }
+
+    fn colours(board: &Board) -> Vec<String> {
+        board.iter().map(|line| line.iter().map(|case| if *case == 0 { '.' } else { (b'0' + case) as char }).collect())
+            .collect()
+    }
+
+    // every cell with a colour has its bit set, and no other
+    fn assert_consistent(board: &Board) {
+        assert_eq!(board.bits.rows.len(), board.len());
+        for (y, line) in board.iter().enumerate() {
+            assert_eq!(line.len(), board.width());
+            for (x, case) in line.iter().enumerate() {
+                assert_eq!(board.bits.is_filled(x as isize, y), *case != 0, "cell {} of line {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn from_colours() {
+        let board = from_lines(&["....", ".12.", "8888"]);
+        assert_eq!(board.bits.rows, vec![0b0000, 0b0110, 0b1111]);
+        assert_eq!((board.width(), board.height()), (4, 3));
+        assert_consistent(&board);
+        assert!(Board::from_colours(vec![vec![0; MAX_WIDTH]; 2]).is_some());
+        assert!(Board::from_colours(vec![vec![0; MAX_WIDTH + 1]; 2]).is_none());
+        assert!(Board::from_colours(vec![vec![0; 4], vec![0; 3]]).is_none());
+        assert!(Board::from_colours(vec![vec![]]).is_none());
+        assert!(Board::from_colours(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn walls_and_floor() {
+        let board = from_lines(&["....", "...."]);
+        assert!(board.bits.is_filled(-1, 0));
+        assert!(board.bits.is_filled(4, 0));
+        assert!(board.bits.is_filled(0, 2));
+        assert!(board.bits.fits(&[0b11], 2, 1));
+        assert!(!board.bits.fits(&[0b11], 3, 1));
+        assert!(!board.bits.fits(&[0b11], -1, 1));
+        assert!(!board.bits.fits(&[0b11, 0b11], 0, 1));
+        // the empty lines of a piece's box can hang off the board
+        assert!(board.bits.fits(&[0b11, 0], 0, 1));
+    }
+
+    #[test]
+    fn place() {
+        let pieces = PieceSet::standard();
+        let mut board = Board::new(10, 4);
+        let mut piece = pieces.create(pieces.kind("T").unwrap());
+        piece.x = 0;
+        piece.y = 1;
+        piece.current_state = 1;
+        assert!(piece.test_current_position(&board));
+        board.place(&piece);
+        assert_eq!(colours(&board), vec!["..........", ".7........", "77........", ".7........"]);
+        assert_consistent(&board);
+        assert!(!piece.test_current_position(&board));
+    }
+
+    #[test]
+    fn clear_lines() {
+        let mut board = from_lines(&["1.........", "2222222222", "3.3.3.3.3.", "4444444444", "5555555555", "6........."]);
+        assert_eq!(board.clear_lines(), 3);
+        assert_eq!(colours(&board), vec!["..........", "..........", "..........", "1.........", "3.3.3.3.3.",
+            "6........."]);
+        assert_consistent(&board);
+        assert_eq!(board.clear_lines(), 0);
+        assert_eq!(board.height(), 6);
+
+        let mut full = from_lines(&["1111", "2222"]);
+        assert_eq!(full.clear_lines(), 2);
+        assert_eq!(colours(&full), vec!["....", "...."]);
+        assert_consistent(&full);
+    }
+
+    #[test]
+    fn clear_lines_sixteen_wide() {
+        let mut board = Board::from_colours(vec![vec![1; MAX_WIDTH], vec![0; MAX_WIDTH]]).unwrap();
+        assert_eq!(board.bits.full(), u16::MAX);
+        assert_eq!(board.clear_lines(), 1);
+        assert!(board.bits.rows.iter().all(|row| *row == 0));
+        assert_consistent(&board);
+    }
+
+    #[test]
+    fn add_garbage() {
+        let mut board = from_lines(&["......", "......", "..1...", ".22..."]);
+        assert!(board.add_garbage(2, 3));
+        assert_eq!(colours(&board), vec!["..1...", ".22...", "888.88", "888.88"]);
+        assert_consistent(&board);
+        // the filled top line is pushed off
+        assert!(!board.add_garbage(1, 0));
+        assert_eq!(colours(&board), vec![".22...", "888.88", "888.88", ".88888"]);
+        assert_consistent(&board);
+        // more lines than the board has only fill it
+        assert!(!board.add_garbage(10, 5));
+        assert_eq!(colours(&board), vec!["88888."; 4]);
+        assert_consistent(&board);
+    }
+
+    #[test]
+    fn column_heights() {
+        assert_eq!(from_lines(&["....", "...."]).bits.column_heights(), vec![0, 0, 0, 0]);
+        assert_eq!(from_lines(&["1111", "1111"]).bits.column_heights(), vec![2, 2, 2, 2]);
+        let board = from_lines(&[".1..", "....", "1..1", "1.11"]);
+        assert_eq!(board.bits.column_heights(), vec![2, 4, 1, 2]);
+        assert_eq!(board.bits.holes(), 3);
+    }
+}
@@ -0,0 +1,334 @@
+extern crate sdl2;
+
+use tetris::input::Action;
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::GameControllerSubsystem;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+
+const CONTROLS_FILE: &str = "controls.txt";
+const DEFAULT_DEADZONE: i16 = 10_000;
+
+pub struct Bindings {
+    pub keys: Vec<(Keycode, Action)>,
+    pub buttons: Vec<(Button, Action)>,
+    pub deadzone: i16,
+}
+
+impl Default for Bindings {
+    fn default() -> Bindings {
+        Bindings {
+            keys: vec![(Keycode::Left, Action::MoveLeft),
+                    (Keycode::Right, Action::MoveRight),
+                    (Keycode::Down, Action::SoftDrop),
+                    (Keycode::Space, Action::HardDrop),
+                    (Keycode::Up, Action::RotateClockwise),
+                    (Keycode::Z, Action::RotateCounterClockwise),
+                    (Keycode::C, Action::Hold),
+                    (Keycode::P, Action::Pause),
+                    (Keycode::Escape, Action::Quit),
+                    (Keycode::A, Action::ToggleAi)],
+            buttons: vec![(Button::DPadLeft, Action::MoveLeft),
+                    (Button::DPadRight, Action::MoveRight),
+                    (Button::DPadDown, Action::SoftDrop),
+                    (Button::DPadUp, Action::HardDrop),
+                    (Button::B, Action::RotateClockwise),
+                    (Button::A, Action::RotateCounterClockwise),
+                    (Button::X, Action::RotateClockwise),
+                    (Button::Y, Action::Hold),
+                    (Button::LeftShoulder, Action::Hold),
+                    (Button::RightShoulder, Action::Hold),
+                    (Button::Start, Action::Pause),
+                    (Button::Back, Action::ToggleAi)],
+            deadzone: DEFAULT_DEADZONE,
+        }
+    }
+}
+
+// player 1 on the left of the keyboard and player 2 on the arrows, each on their own controller
+fn versus_defaults() -> Vec<Bindings> {
+    let buttons = Bindings::default().buttons;
+    vec![Bindings {
+            keys: vec![(Keycode::A, Action::MoveLeft),
+                    (Keycode::D, Action::MoveRight),
+                    (Keycode::S, Action::SoftDrop),
+                    (Keycode::Space, Action::HardDrop),
+                    (Keycode::W, Action::RotateClockwise),
+                    (Keycode::Q, Action::RotateCounterClockwise),
+                    (Keycode::LShift, Action::Hold),
+                    (Keycode::P, Action::Pause),
+                    (Keycode::Escape, Action::Quit),
+                    (Keycode::F1, Action::ToggleAi)],
+            buttons: buttons.clone(),
+            deadzone: DEFAULT_DEADZONE,
+        },
+        Bindings {
+            keys: vec![(Keycode::Left, Action::MoveLeft),
+                    (Keycode::Right, Action::MoveRight),
+                    (Keycode::Down, Action::SoftDrop),
+                    (Keycode::Return, Action::HardDrop),
+                    (Keycode::Up, Action::RotateClockwise),
+                    (Keycode::RCtrl, Action::RotateCounterClockwise),
+                    (Keycode::RShift, Action::Hold),
+                    (Keycode::F2, Action::ToggleAi)],
+            buttons,
+            deadzone: DEFAULT_DEADZONE,
+        }]
+}
+
+impl Bindings {
+    // reads the bindings from the controls file, keeping the defaults for any action it doesn't mention
+    //
+    //     key Left move_left
+    //     button dpleft move_left
+    //     deadzone 8000
+    pub fn load() -> Bindings {
+        Bindings::read(Bindings::default(), None)
+    }
+
+    // the bindings of each player in a versus game, which the controls file gives on lines starting with the
+    // player, as in "p2 key Left move_left"
+    pub fn load_versus() -> Vec<Bindings> {
+        versus_defaults().into_iter().enumerate()
+            .map(|(player, defaults)| Bindings::read(defaults, Some(&format!("p{}", player + 1))))
+            .collect()
+    }
+
+    fn read(bindings: Bindings, player: Option<&str>) -> Bindings {
+        let mut content = String::new();
+        if File::open(CONTROLS_FILE).and_then(|mut f| f.read_to_string(&mut content)).is_err() {
+            return bindings
+        }
+        Bindings::parse(bindings, &content, player)
+    }
+
+    fn parse(mut bindings: Bindings, content: &str, player: Option<&str>) -> Bindings {
+        let mut rebound_keys = Vec::new();
+        let mut rebound_buttons = Vec::new();
+        for line in content.lines() {
+            let mut words = line.split_whitespace().collect::<Vec<_>>();
+            if let Some(player) = player {
+                if words.first() != Some(&player) {
+                    continue
+                }
+                words.remove(0);
+            }
+            match words.as_slice() {
+                ["key", name, action] => {
+                    if let (Some(key), Some(action)) = (Keycode::from_name(name), Action::from_name(action)) {
+                        if !rebound_keys.contains(&action) {
+                            bindings.keys.retain(|&(_, bound)| bound != action);
+                            rebound_keys.push(action);
+                        }
+                        bindings.keys.push((key, action));
+                    }
+                }
+                ["button", name, action] => {
+                    if let (Some(button), Some(action)) = (Button::from_string(name), Action::from_name(action)) {
+                        if !rebound_buttons.contains(&action) {
+                            bindings.buttons.retain(|&(_, bound)| bound != action);
+                            rebound_buttons.push(action);
+                        }
+                        bindings.buttons.push((button, action));
+                    }
+                }
+                ["deadzone", value] => {
+                    if let Ok(value) = value.parse::<i16>() {
+                        bindings.deadzone = value.max(0);
+                    }
+                }
+                _ => {}
+            }
+        }
+        bindings
+    }
+
+    pub fn key_action(&self, keycode: Keycode) -> Option<Action> {
+        self.keys.iter().find(|&&(key, _)| key == keycode).map(|&(_, action)| action)
+    }
+
+    pub fn button_action(&self, button: Button) -> Option<Action> {
+        self.buttons.iter().find(|&&(bound, _)| bound == button).map(|&(_, action)| action)
+    }
+}
+
+pub struct Controls {
+    pub bindings: Bindings,
+    // the bindings of each player in a versus game
+    pub players: Vec<Bindings>,
+    subsystem: GameControllerSubsystem,
+    controllers: HashMap<i32, GameController>,
+    // the controllers in the order they were connected, the first one being player 1's in a versus game
+    order: Vec<i32>,
+    // last direction of the left stick of each controller, so a tilt only fires once
+    sticks: HashMap<i32, (i8, i8)>,
+    // whether the window has been closed, which quits whatever screen is showing and then the game
+    pub closed: bool,
+}
+
+impl Controls {
+    pub fn new(subsystem: GameControllerSubsystem, bindings: Bindings, players: Vec<Bindings>) -> Controls {
+        Controls {
+            bindings,
+            players,
+            subsystem,
+            controllers: HashMap::new(),
+            order: Vec::new(),
+            sticks: HashMap::new(),
+            closed: false,
+        }
+    }
+
+    pub fn translate(&mut self, event: &Event) -> Option<Action> {
+        match *event {
+            Event::Quit {..} => {
+                self.closed = true;
+                Some(Action::Quit)
+            }
+            Event::KeyDown {keycode: Some(keycode), ..} => self.bindings.key_action(keycode),
+            Event::ControllerButtonDown {button, ..} => self.bindings.button_action(button),
+            Event::ControllerAxisMotion {which, axis, value, ..} => {
+                stick_action(&mut self.sticks, which, axis, value, self.bindings.deadzone)
+            }
+            Event::ControllerDeviceAdded {which, ..} => {
+                self.connect(which as u32);
+                None
+            }
+            Event::ControllerDeviceRemoved {which, ..} => {
+                self.controllers.remove(&which);
+                self.order.retain(|id| *id != which);
+                self.sticks.remove(&which);
+                None
+            }
+            _ => None
+        }
+    }
+
+    fn connect(&mut self, index: u32) {
+        if !self.subsystem.is_game_controller(index) {
+            return
+        }
+        match self.subsystem.open(index) {
+            Ok(controller) => {
+                eprintln!("Controller connected: {}", controller.name());
+                self.order.push(controller.instance_id());
+                self.controllers.insert(controller.instance_id(), controller);
+            }
+            Err(e) => eprintln!("Failed to open controller {}: {:?}", index, e)
+        }
+    }
+
+    // the action in a versus game and the player who made it; keys go to whoever they are bound to, and
+    // controllers to the players in the order they were connected
+    pub fn translate_player(&mut self, event: &Event) -> Option<(usize, Action)> {
+        match *event {
+            Event::KeyDown {keycode: Some(keycode), ..} => self.players.iter().enumerate()
+                .filter_map(|(player, bindings)| bindings.key_action(keycode).map(|action| (player, action)))
+                .next(),
+            Event::ControllerButtonDown {which, button, ..} => {
+                let player = self.controller_player(which)?;
+                self.players[player].button_action(button).map(|action| (player, action))
+            }
+            Event::ControllerAxisMotion {which, axis, value, ..} => {
+                let player = self.controller_player(which)?;
+                stick_action(&mut self.sticks, which, axis, value, self.players[player].deadzone).map(|action| (player, action))
+            }
+            _ => self.translate(event).map(|action| (0, action))
+        }
+    }
+
+    fn controller_player(&self, which: i32) -> Option<usize> {
+        self.order.iter().position(|id| *id == which).filter(|player| *player < self.players.len())
+    }
+}
+
+// the action a tilt of the left stick makes, if it just went past the deadzone in a new direction
+fn stick_action(sticks: &mut HashMap<i32, (i8, i8)>, which: i32, axis: Axis, value: i16, deadzone: i16) -> Option<Action> {
+    let direction = if value < -deadzone {
+        -1
+    } else if value > deadzone {
+        1
+    } else {
+        0
+    };
+
+    let stick = sticks.entry(which).or_insert((0, 0));
+    let previous = match axis {
+        Axis::LeftX => &mut stick.0,
+        Axis::LeftY => &mut stick.1,
+        _ => return None
+    };
+    if *previous == direction {
+        return None
+    }
+    *previous = direction;
+
+    match (axis, direction) {
+        (Axis::LeftX, -1) => Some(Action::MoveLeft),
+        (Axis::LeftX, 1) => Some(Action::MoveRight),
+        (Axis::LeftY, 1) => Some(Action::SoftDrop),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let content = "key X hold\nkey V hold\nbutton dpleft rotate_cw\ndeadzone 8000\n\
+            key Nothing hold\nkey Left nothing\nsomething else\n";
+        let bindings = Bindings::parse(Bindings::default(), content, None);
+        // rebinding an action replaces its default bindings, and it can be bound more than once
+        assert_eq!(bindings.key_action(Keycode::X), Some(Action::Hold));
+        assert_eq!(bindings.key_action(Keycode::V), Some(Action::Hold));
+        assert_eq!(bindings.key_action(Keycode::C), None);
+        assert_eq!(bindings.key_action(Keycode::Left), Some(Action::MoveLeft));
+        assert_eq!(bindings.button_action(Button::DPadLeft), Some(Action::RotateClockwise));
+        assert_eq!(bindings.button_action(Button::B), None);
+        assert_eq!(bindings.button_action(Button::DPadRight), Some(Action::MoveRight));
+        assert_eq!(bindings.deadzone, 8000);
+
+        assert_eq!(Bindings::parse(Bindings::default(), "deadzone -5\n", None).deadzone, 0);
+        assert_eq!(Bindings::parse(Bindings::default(), "deadzone lots\n", None).deadzone, DEFAULT_DEADZONE);
+    }
+
+    #[test]
+    fn parse_players() {
+        let content = "p2 key K hard_drop\nkey J hard_drop\np1 deadzone 500\n";
+        let players = versus_defaults().into_iter().enumerate()
+            .map(|(player, defaults)| Bindings::parse(defaults, content, Some(&format!("p{}", player + 1))))
+            .collect::<Vec<_>>();
+        assert_eq!(players[0].key_action(Keycode::Space), Some(Action::HardDrop));
+        assert_eq!(players[0].key_action(Keycode::J), None);
+        assert_eq!(players[0].deadzone, 500);
+        assert_eq!(players[1].key_action(Keycode::K), Some(Action::HardDrop));
+        assert_eq!(players[1].key_action(Keycode::Return), None);
+        assert_eq!(players[1].deadzone, DEFAULT_DEADZONE);
+    }
+
+    #[test]
+    fn stick() {
+        let mut sticks = HashMap::new();
+        let mut tilt = |which: i32, axis: Axis, value: i16| stick_action(&mut sticks, which, axis, value, 10_000);
+        // nothing happens inside the deadzone, and a tilt only fires once
+        assert_eq!(tilt(0, Axis::LeftX, -10_000), None);
+        assert_eq!(tilt(0, Axis::LeftX, -10_001), Some(Action::MoveLeft));
+        assert_eq!(tilt(0, Axis::LeftX, -30_000), None);
+        assert_eq!(tilt(0, Axis::LeftX, 20_000), Some(Action::MoveRight));
+        assert_eq!(tilt(0, Axis::LeftX, 0), None);
+        assert_eq!(tilt(0, Axis::LeftX, 20_000), Some(Action::MoveRight));
+        // down soft drops, up does nothing
+        assert_eq!(tilt(0, Axis::LeftY, 20_000), Some(Action::SoftDrop));
+        assert_eq!(tilt(0, Axis::LeftY, -20_000), None);
+        // each controller has a stick of its own, and the other axes are left alone
+        assert_eq!(tilt(1, Axis::LeftX, 20_000), Some(Action::MoveRight));
+        assert_eq!(tilt(1, Axis::RightX, -20_000), None);
+        assert_eq!(tilt(1, Axis::TriggerLeft, 20_000), None);
+    }
+}
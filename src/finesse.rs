@@ -0,0 +1,154 @@
+// Finesse is placing every piece with as few inputs as it takes. A fault is a piece that took more moves and
+// rotations to get from where it spawned to where it was dropped than it needed to.
+use board::Board;
+use input::Action;
+use pathfind::find_path;
+use tetris::Tetris;
+use tetrimino::Tetrimino;
+
+// the fewest moves and rotations that take a piece freshly spawned on the game's board to where `target` is on
+// `game_map`, soft drops being free
+pub fn minimal_inputs(tetris: &Tetris, game_map: &Board, target: &Tetrimino) -> Option<u32> {
+    let spawned = tetris.pieces.spawn(&tetris.settings, game_map, target.kind);
+    find_path(game_map, &spawned, target).map(|path| path.inputs)
+}
+
+// counts the finesse faults of a game as it is played
+pub struct FinesseTracker {
+    pub faults: u32,
+    // inputs made beyond the ones that were needed, over all the faults
+    pub extra_inputs: u32,
+    // pieces that were checked, which leaves out the ones the game can't find a way to from their spawn
+    pub checked: u32,
+    inputs: u32,
+    num_pieces: u32,
+    // the map the current piece is being placed on
+    game_map: Board,
+}
+
+impl FinesseTracker {
+    pub fn new(tetris: &Tetris) -> FinesseTracker {
+        FinesseTracker {
+            faults: 0,
+            extra_inputs: 0,
+            checked: 0,
+            inputs: 0,
+            num_pieces: tetris.num_pieces,
+            game_map: tetris.game_map.clone(),
+        }
+    }
+
+    // to be called after every step with the actions it was given
+    pub fn update(&mut self, tetris: &Tetris, actions: &[Action]) {
+        for action in actions {
+            match *action {
+                Action::MoveLeft | Action::MoveRight | Action::RotateClockwise | Action::RotateCounterClockwise => {
+                    self.inputs += 1
+                }
+                // a new piece comes in, from the hold or the queue
+                Action::Hold => self.inputs = 0,
+                // whatever comes after it in the same frame is for the next piece, and ignored by the game
+                Action::HardDrop => break,
+                _ => {}
+            }
+        }
+
+        if tetris.num_pieces == self.num_pieces {
+            return
+        }
+        if let Some(ref piece) = tetris.locked_piece {
+            if let Some(needed) = minimal_inputs(tetris, &self.game_map, piece) {
+                self.checked += 1;
+                if self.inputs > needed {
+                    self.faults += 1;
+                    self.extra_inputs += self.inputs - needed;
+                }
+            }
+        }
+        self.inputs = 0;
+        self.num_pieces = tetris.num_pieces;
+        self.game_map = tetris.game_map.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the named piece on an empty classic board, dropped as far as it goes in the given column and state
+    fn target(tetris: &Tetris, name: &str, x: isize, state: u8) -> Tetrimino {
+        let mut piece = tetris.spawn_tetrimino(tetris.pieces.kind(name).unwrap());
+        piece.x = x;
+        piece.current_state = state;
+        assert!(piece.test_position(&tetris.game_map, state as usize, x, piece.y), "{} doesn't fit at {}", name, x);
+        while piece.test_position(&tetris.game_map, state as usize, x, piece.y + 1) {
+            piece.y += 1;
+        }
+        piece
+    }
+
+    #[test]
+    fn known_optimum() {
+        let tetris = Tetris::with_seed(1);
+        // name, column of its box, state, fewest inputs: the pieces come in with their box in column 4
+        let table = [
+            ("I", 4, 0, 0),
+            ("I", 0, 0, 4),
+            ("I", 6, 0, 2),
+            // standing up in the second column of its box
+            ("I", -1, 1, 6),
+            ("I", 8, 1, 5),
+            ("T", 4, 0, 0),
+            ("T", 4, 1, 1),
+            ("T", 4, 3, 1),
+            // turned around, either way
+            ("T", 4, 2, 2),
+            ("T", 0, 2, 6),
+            ("T", 7, 2, 5),
+            ("O", 5, 0, 0),
+            ("O", 0, 0, 5),
+        ];
+        for &(name, x, state, inputs) in table.iter() {
+            let target = target(&tetris, name, x, state);
+            assert_eq!(minimal_inputs(&tetris, &tetris.game_map, &target), Some(inputs),
+                "{} at {} in state {}", name, x, state);
+        }
+    }
+
+    // plays the inputs on the first piece, a frame each, with the tracker following
+    fn play(tetris: &mut Tetris, finesse: &mut FinesseTracker, actions: &[Action]) {
+        for action in actions {
+            tetris.gravity_frames = 0;
+            tetris.step(&[*action]);
+            finesse.update(tetris, &[*action]);
+        }
+    }
+
+    #[test]
+    fn faults() {
+        let mut tetris = Tetris::with_seed(1);
+        tetris.current_piece = Some(tetris.spawn_tetrimino(tetris.pieces.kind("T").unwrap()));
+        let mut finesse = FinesseTracker::new(&tetris);
+
+        // turned around in two, which is as few as it takes
+        play(&mut tetris, &mut finesse, &[Action::RotateClockwise, Action::RotateClockwise, Action::HardDrop]);
+        assert_eq!((finesse.checked, finesse.faults, finesse.extra_inputs), (1, 0, 0));
+
+        // to the left wall, which takes four moves, in nine, the last one against the wall
+        tetris.current_piece = Some(tetris.spawn_tetrimino(tetris.pieces.kind("T").unwrap()));
+        let moves = [Action::MoveLeft, Action::MoveRight, Action::MoveLeft, Action::MoveLeft, Action::MoveLeft,
+            Action::MoveLeft, Action::MoveRight, Action::MoveLeft, Action::MoveLeft, Action::HardDrop];
+        play(&mut tetris, &mut finesse, &moves);
+        assert_eq!(tetris.locked_piece.as_ref().unwrap().x, 0);
+        assert_eq!((finesse.checked, finesse.faults, finesse.extra_inputs), (2, 1, 5));
+    }
+
+    #[test]
+    fn hold_starts_over() {
+        let mut tetris = Tetris::with_seed(1);
+        let mut finesse = FinesseTracker::new(&tetris);
+        play(&mut tetris, &mut finesse, &[Action::MoveLeft, Action::MoveRight, Action::RotateClockwise, Action::Hold]);
+        play(&mut tetris, &mut finesse, &[Action::HardDrop]);
+        assert_eq!((finesse.checked, finesse.faults), (1, 0));
+    }
+}
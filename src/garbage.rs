@@ -0,0 +1,338 @@
+// The lines a clear sends to an opponent, following the guideline's attack table, and the garbage waiting to come
+// up a board. Garbage that comes in is first cancelled by the lines the defender's own clears would send, and what
+// is left rises under the stack once its delay is over and a piece locks without clearing anything.
+use board::Board;
+use tetrimino::Tetrimino;
+
+use std::collections::VecDeque;
+
+// frames garbage waits before it can come up the board
+pub const DEFAULT_GARBAGE_DELAY: u32 = 30;
+// lines sent for clearing 0 to 4 lines, without and with a T-spin; a T-spin mini sends as much as a plain clear
+const CLEAR_LINES: [u32; 5] = [0, 0, 1, 2, 4];
+const T_SPIN_LINES: [u32; 5] = [0, 2, 4, 6, 8];
+// lines added for each clear after the first in a row, the last one going on for longer combos
+const COMBO_LINES: [u32; 12] = [0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+const BACK_TO_BACK_LINES: u32 = 1;
+const PERFECT_CLEAR_LINES: u32 = 10;
+// the most lines a single clear can send
+pub const MAX_ATTACK: u32 = T_SPIN_LINES[4] + COMBO_LINES[COMBO_LINES.len() - 1] + BACK_TO_BACK_LINES + PERFECT_CLEAR_LINES;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TSpin {
+    None,
+    // two of the corners around the centre in front of where the T points are filled
+    Full,
+    // only one of them is
+    Mini,
+}
+
+// what locking a piece did
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LineClear {
+    pub lines: u32,
+    pub t_spin: TSpin,
+    // the board was left empty
+    pub perfect: bool,
+}
+
+impl LineClear {
+    // tetrises and T-spins that clear lines keep a back-to-back going
+    pub fn is_difficult(&self) -> bool {
+        self.lines == 4 || (self.lines > 0 && self.t_spin != TSpin::None)
+    }
+
+    pub fn name(&self) -> String {
+        let mut words = match self.t_spin {
+            TSpin::None => vec![],
+            TSpin::Full => vec!["T-spin"],
+            TSpin::Mini => vec!["T-spin", "mini"],
+        };
+        words.extend(["", "single", "double", "triple", "tetris"].get(self.lines as usize).filter(|name| !name.is_empty()));
+        if self.perfect {
+            words.push("perfect clear");
+        }
+        words.join(" ")
+    }
+}
+
+// the lines sent for a clear that is the `combo`th in a row, counting from 1, and whether it carries on a back-to-back
+pub fn attack(clear: &LineClear, combo: u32, back_to_back: bool) -> u32 {
+    if clear.lines == 0 {
+        return 0
+    }
+    let table = match clear.t_spin {
+        TSpin::None | TSpin::Mini => &CLEAR_LINES,
+        TSpin::Full => &T_SPIN_LINES,
+    };
+    let mut lines = table[(clear.lines as usize).min(4)];
+    lines += COMBO_LINES[(combo.saturating_sub(1) as usize).min(COMBO_LINES.len() - 1)];
+    if back_to_back && clear.is_difficult() {
+        lines += BACK_TO_BACK_LINES;
+    }
+    if clear.perfect {
+        lines += PERFECT_CLEAR_LINES;
+    }
+    lines
+}
+
+// the cell of a T-shaped piece its three others are around, and the way its stem points, if the piece is one
+fn t_centre(piece: &Tetrimino) -> Option<((isize, isize), (isize, isize))> {
+    let state = &piece.states[piece.current_state as usize];
+    let filled = |x: isize, y: isize| x >= 0 && y >= 0 && state.get(y as usize)
+        .and_then(|line| line.get(x as usize)).is_some_and(|case| *case != 0);
+    let cells = (0..state.len() as isize)
+        .flat_map(|y| (0..state.len() as isize).map(move |x| (x, y)))
+        .filter(|&(x, y)| filled(x, y))
+        .collect::<Vec<_>>();
+    if cells.len() != 4 {
+        return None
+    }
+    let sides = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+    cells.iter().filter_map(|&(x, y)| {
+        let around = sides.iter().filter(|&&(dx, dy)| filled(x + dx, y + dy)).collect::<Vec<_>>();
+        if around.len() != 3 {
+            return None
+        }
+        // the stem is the one across from the missing side
+        let &(dx, dy) = around.into_iter().find(|&&(dx, dy)| !filled(x - dx, y - dy))?;
+        Some(((piece.x + x, piece.y as isize + y), (dx, dy)))
+    }).next()
+}
+
+// whether the piece, turned into where it is as its last move, was spun in: three of the four corners around the
+// centre of a T are filled, the walls and floor counting, and it is a full T-spin if both in front of it are
+pub fn t_spin(game_map: &Board, piece: &Tetrimino) -> TSpin {
+    let ((x, y), (dx, dy)) = match t_centre(piece) {
+        Some(centre) => centre,
+        None => return TSpin::None
+    };
+    let filled = |cx: isize, cy: isize| cy >= 0 && game_map.bits.is_filled(cx, cy as usize);
+    let corners = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+    if corners.iter().filter(|&&(cx, cy)| filled(x + cx, y + cy)).count() < 3 {
+        return TSpin::None
+    }
+    // the corners on the side the stem points to
+    let front = corners.iter().filter(|&&(cx, cy)| cx * dx + cy * dy > 0).filter(|&&(cx, cy)| filled(x + cx, y + cy)).count();
+    if front == 2 { TSpin::Full } else { TSpin::Mini }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Pending {
+    pub lines: u32,
+    // the column of the gap, which the sender picks
+    pub hole: usize,
+    // frames until it can come up
+    pub delay: u32,
+}
+
+// garbage sent to a board that hasn't come up it yet, the oldest first
+#[derive(Clone, Default, Debug)]
+pub struct GarbageQueue {
+    pub pending: VecDeque<Pending>,
+}
+
+impl GarbageQueue {
+    pub fn push(&mut self, lines: u32, hole: usize, delay: u32) {
+        if lines > 0 {
+            self.pending.push_back(Pending {lines, hole, delay});
+        }
+    }
+
+    pub fn step(&mut self) {
+        for pending in &mut self.pending {
+            pending.delay = pending.delay.saturating_sub(1);
+        }
+    }
+
+    // takes the attack off the oldest garbage first, returning what is left of it to send on
+    pub fn cancel(&mut self, mut attack: u32) -> u32 {
+        while attack > 0 {
+            let pending = match self.pending.front_mut() {
+                Some(pending) => pending,
+                None => break
+            };
+            let cancelled = attack.min(pending.lines);
+            pending.lines -= cancelled;
+            attack -= cancelled;
+            if pending.lines == 0 {
+                self.pending.pop_front();
+            }
+        }
+        attack
+    }
+
+    // removes the garbage whose delay is over, to come up the board
+    pub fn take_ready(&mut self) -> Vec<Pending> {
+        let mut ready = Vec::new();
+        while self.pending.front().is_some_and(|pending| pending.delay == 0) {
+            ready.push(self.pending.pop_front().unwrap());
+        }
+        ready
+    }
+
+    pub fn total(&self) -> u32 {
+        self.pending.iter().map(|pending| pending.lines).sum()
+    }
+
+    pub fn ready(&self) -> u32 {
+        self.pending.iter().filter(|pending| pending.delay == 0).map(|pending| pending.lines).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use board::GARBAGE;
+    use pieces::PieceSet;
+
+    fn clear(lines: u32, t_spin: TSpin, perfect: bool) -> LineClear {
+        LineClear {lines, t_spin, perfect}
+    }
+
+    // a board 4 wide of the given lines, '#' standing for a cell, and a standard piece placed on it
+    fn spin(lines: &[&str], name: &str, (x, y, state): (isize, usize, u8)) -> TSpin {
+        let colours = lines.iter().map(|line| line.chars().map(|c| if c == '#' { GARBAGE } else { 0 }).collect())
+            .collect();
+        let game_map = Board::from_colours(colours).unwrap();
+        let pieces = PieceSet::standard();
+        let mut piece = pieces.create(pieces.kind(name).unwrap());
+        piece.x = x;
+        piece.y = y;
+        piece.current_state = state;
+        t_spin(&game_map, &piece)
+    }
+
+    #[test]
+    fn attack_table() {
+        // lines, T-spin, perfect clear, combo, back-to-back, lines sent
+        let table = [
+            (0, TSpin::None, false, 1, false, 0),
+            (0, TSpin::Full, false, 5, true, 0),
+            (1, TSpin::None, false, 1, false, 0),
+            (2, TSpin::None, false, 1, false, 1),
+            (3, TSpin::None, false, 1, false, 2),
+            (4, TSpin::None, false, 1, false, 4),
+            (1, TSpin::Mini, false, 1, false, 0),
+            (2, TSpin::Mini, false, 1, false, 1),
+            (1, TSpin::Full, false, 1, false, 2),
+            (2, TSpin::Full, false, 1, false, 4),
+            (3, TSpin::Full, false, 1, false, 6),
+            (1, TSpin::None, true, 1, false, 10),
+            (4, TSpin::None, true, 1, false, 14),
+        ];
+        for &(lines, t_spin, perfect, combo, back_to_back, sent) in table.iter() {
+            assert_eq!(attack(&clear(lines, t_spin, perfect), combo, back_to_back), sent,
+                "{} lines, {:?}, perfect {}, combo {}, back-to-back {}", lines, t_spin, perfect, combo, back_to_back);
+        }
+    }
+
+    #[test]
+    fn combo() {
+        let sent = (1..=14).map(|combo| attack(&clear(1, TSpin::None, false), combo, false)).collect::<Vec<_>>();
+        assert_eq!(sent, vec![0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5, 5, 5]);
+        assert_eq!(attack(&clear(2, TSpin::Full, false), 4, true), 4 + 1 + 1);
+        assert_eq!(attack(&clear(4, TSpin::Full, true), 100, true), MAX_ATTACK);
+    }
+
+    #[test]
+    fn back_to_back() {
+        // lines, T-spin, lines sent without and with a back-to-back going
+        let table = [
+            (1, TSpin::None, 0, 0),
+            (3, TSpin::None, 2, 2),
+            (4, TSpin::None, 4, 5),
+            (1, TSpin::Mini, 0, 1),
+            (2, TSpin::Full, 4, 5),
+            (3, TSpin::Full, 6, 7),
+        ];
+        for &(lines, t_spin, without, with) in table.iter() {
+            let clear = clear(lines, t_spin, false);
+            assert_eq!(clear.is_difficult(), with > without);
+            assert_eq!(attack(&clear, 1, false), without);
+            assert_eq!(attack(&clear, 1, true), with);
+        }
+        assert!(!clear(0, TSpin::Full, false).is_difficult());
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(clear(4, TSpin::None, false).name(), "tetris");
+        assert_eq!(clear(2, TSpin::Full, false).name(), "T-spin double");
+        assert_eq!(clear(1, TSpin::Mini, false).name(), "T-spin mini single");
+        assert_eq!(clear(0, TSpin::Full, false).name(), "T-spin");
+        assert_eq!(clear(1, TSpin::None, true).name(), "single perfect clear");
+    }
+
+    #[test]
+    fn three_corners() {
+        // the T points down from the middle line, the corners around its centre being the four cells at the ends of
+        // the top and bottom lines
+        let table = [
+            (["....", "....", "...."], TSpin::None),
+            (["....", "....", "#.#."], TSpin::None),
+            (["#...", "....", "#.#."], TSpin::Full),
+            (["#.#.", "....", "#.#."], TSpin::Full),
+            (["#.#.", "....", "#..."], TSpin::Mini),
+            (["#.#.", "....", "..#."], TSpin::Mini),
+        ];
+        for (lines, expected) in table.iter() {
+            assert_eq!(spin(lines, "T", (0, 1, 0)), *expected, "{:?}", lines);
+        }
+    }
+
+    #[test]
+    fn walls_and_floor() {
+        // pointing right against the left wall, which fills both corners behind it
+        assert_eq!(spin(&["....", "....", "...."], "T", (-1, 0, 3)), TSpin::None);
+        assert_eq!(spin(&["....", "....", ".#.."], "T", (-1, 0, 3)), TSpin::Mini);
+        assert_eq!(spin(&[".#..", "....", ".#.."], "T", (-1, 0, 3)), TSpin::Full);
+        // pointing up on the floor
+        assert_eq!(spin(&["....", "#...", "...."], "T", (0, 1, 2)), TSpin::Mini);
+        assert_eq!(spin(&["....", "#.#.", "...."], "T", (0, 1, 2)), TSpin::Full);
+    }
+
+    #[test]
+    fn only_t_pieces() {
+        assert_eq!(spin(&["#...", "....", "#.#."], "I", (0, 1, 0)), TSpin::None);
+    }
+
+    #[test]
+    fn cancelling() {
+        let mut queue = GarbageQueue::default();
+        queue.push(0, 1, 0);
+        assert!(queue.pending.is_empty());
+        queue.push(3, 2, 2);
+        queue.push(2, 5, 0);
+        queue.push(4, 7, 3);
+        assert_eq!(queue.total(), 9);
+
+        // the oldest goes first
+        assert_eq!(queue.cancel(4), 0);
+        assert_eq!(queue.pending.iter().map(|pending| (pending.lines, pending.hole)).collect::<Vec<_>>(),
+            vec![(1, 5), (4, 7)]);
+        assert_eq!(queue.cancel(0), 0);
+        assert_eq!(queue.total(), 5);
+        // what is left over is sent on
+        assert_eq!(queue.cancel(8), 3);
+        assert!(queue.pending.is_empty());
+        assert_eq!(queue.cancel(2), 2);
+    }
+
+    #[test]
+    fn delay() {
+        let mut queue = GarbageQueue::default();
+        queue.push(3, 2, 2);
+        queue.push(2, 5, 0);
+        // ready garbage waits behind older garbage that isn't
+        assert_eq!(queue.ready(), 2);
+        assert!(queue.take_ready().is_empty());
+        queue.step();
+        assert!(queue.take_ready().is_empty());
+        queue.step();
+        queue.step();
+        assert_eq!(queue.ready(), 5);
+        assert_eq!(queue.take_ready(), vec![Pending {lines: 3, hole: 2, delay: 0}, Pending {lines: 2, hole: 5, delay: 0}]);
+        assert_eq!(queue.total(), 0);
+    }
+}
@@ -1,11 +1,20 @@
 extern crate sdl2;
 
-use tetris::Tetris;
 use sdl2::pixels::Color;
 use sdl2::render::{Canvas, Texture, TextureCreator};
 use sdl2::video::{Window, WindowContext};
 use sdl2::rect::Rect;
 use sdl2::image::LoadTexture;
+use sdl2::ttf::{Font, Sdl2TtfContext};
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+// the point sizes the font is loaded in; text is drawn with the smallest one at least as high as it, so it is never
+// scaled up and only ever scaled down a little
+const FONT_SIZES: [u16; 4] = [16, 32, 64, 128];
+// the strings kept rendered for the next frames
+const MAX_CACHED_TEXTS: usize = 256;
 
 pub fn load_asset<'a>(texture_creator: &'a TextureCreator<WindowContext>, file: &str) -> Texture<'a> {
     match texture_creator.load_texture(file) {
@@ -28,7 +37,7 @@ pub fn create_texture_rect<'a>(canvas: &mut Canvas<Window>, texture_creator: &'a
 }
 
 fn create_texture_from_text<'a>(texture_creator: &'a TextureCreator<WindowContext>, 
-    font: &sdl2::ttf::Font, text: &str, r: u8, g:u8, b: u8) -> Option<Texture<'a>> {
+    font: &Font, text: &str, r: u8, g:u8, b: u8) -> Option<Texture<'a>> {
         if let Ok(surface) = font.render(text).blended(Color::RGB(r, g, b)) {
             texture_creator.create_texture_from_surface(&surface).ok()
         } else {
@@ -36,13 +45,90 @@ fn create_texture_from_text<'a>(texture_creator: &'a TextureCreator<WindowContex
         }
 }
 
-fn get_rect_from_text(text: &str, x: i32, y:i32) -> Option<Rect> {
-    Some(Rect::new(x, y, text.len() as u32 * 10, 30))
+// which side of the point given text is drawn from
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Align {
+    Left,
+    Centre,
+    Right,
 }
 
-pub fn display_score<'a>(tetris: &Tetris, canvas: &mut Canvas<Window>, texture_creator: &'a TextureCreator<WindowContext>, 
-    font: &sdl2::ttf::Font, x: i32) {
-    let score_text = format!("Score: {}", tetris.score);
-    let score = create_texture_from_text(&texture_creator, &font, &score_text, 255, 255, 255).expect("Cannot render text.");
-    canvas.copy(&score, None, get_rect_from_text(&score_text, x, 0)).expect("Couldn't render text.");
-}
\ No newline at end of file
+// white text of any height, measured with the font's own metrics. Most of what is written doesn't change from one
+// frame to the next, so every string is only rendered once and its texture kept while it is still being drawn
+pub struct Text<'a> {
+    texture_creator: &'a TextureCreator<WindowContext>,
+    // the font in each of `FONT_SIZES`
+    fonts: Vec<Font<'a, 'static>>,
+    // the texture of each string in each font, with the draw it was last used for
+    cache: RefCell<HashMap<(usize, String), (Texture<'a>, u64)>>,
+    draws: Cell<u64>,
+}
+
+impl<'a> Text<'a> {
+    pub fn load(ttf_context: &'a Sdl2TtfContext, texture_creator: &'a TextureCreator<WindowContext>, file: &str)
+        -> Result<Text<'a>, String> {
+        let mut fonts = Vec::new();
+        for size in FONT_SIZES.iter() {
+            let mut font = ttf_context.load_font(file, *size)?;
+            font.set_style(sdl2::ttf::STYLE_BOLD);
+            fonts.push(font);
+        }
+        Ok(Text {texture_creator, fonts, cache: RefCell::new(HashMap::new()), draws: Cell::new(0)})
+    }
+
+    // the font text of the height is drawn with
+    fn font_for(&self, height: u32) -> usize {
+        self.fonts.iter().position(|font| font.height() as u32 >= height).unwrap_or(self.fonts.len() - 1)
+    }
+
+    // the width and height of the text drawn at the height
+    pub fn measure(&self, text: &str, height: u32) -> (u32, u32) {
+        let font = &self.fonts[self.font_for(height)];
+        let (width, font_height) = font.size_of(text).unwrap_or((0, 0));
+        (width * height / font_height.max(1), height)
+    }
+
+    // the most characters of the text that fit in the width
+    pub fn fit(&self, text: &str, height: u32, width: u32) -> String {
+        let mut fitted = String::new();
+        for c in text.chars() {
+            fitted.push(c);
+            if self.measure(&fitted, height).0 > width {
+                fitted.pop();
+                break
+            }
+        }
+        fitted
+    }
+
+    // draws the text with its top at `y`, starting, centred on or ending at `x`
+    pub fn draw(&self, canvas: &mut Canvas<Window>, text: &str, (x, y): (i32, i32), height: u32, align: Align) {
+        // there is nothing to render, which the font would fail to
+        if text.is_empty() || height == 0 {
+            return
+        }
+        let (width, _) = self.measure(text, height);
+        let x = match align {
+            Align::Left => x,
+            Align::Centre => x - width as i32 / 2,
+            Align::Right => x - width as i32,
+        };
+
+        let draws = self.draws.get() + 1;
+        self.draws.set(draws);
+        let mut cache = self.cache.borrow_mut();
+        let key = (self.font_for(height), text.to_string());
+        if !cache.contains_key(&key) {
+            // the strings that haven't been drawn lately, like scores that have gone up since, make way
+            if cache.len() >= MAX_CACHED_TEXTS {
+                cache.retain(|_, &mut (_, drawn)| draws - drawn < MAX_CACHED_TEXTS as u64 / 2);
+            }
+            let texture = create_texture_from_text(self.texture_creator, &self.fonts[key.0], text, 255, 255, 255)
+                .expect("Cannot render text.");
+            cache.insert(key.clone(), (texture, draws));
+        }
+        let entry = cache.get_mut(&key).unwrap();
+        entry.1 = draws;
+        canvas.copy(&entry.0, None, Rect::new(x, y, width.max(1), height)).expect("Couldn't render text.");
+    }
+}
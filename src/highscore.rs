@@ -1,15 +1,20 @@
+use finesse::FinesseTracker;
+use tetris::Tetris;
+
 use std::io::{self, Write, Read};
 use std::fs::File;
+use std::cmp::Reverse;
+use std::env;
 
-const HIGHSCORE_FILE: &'static str = "scores.txt";
+const HIGHSCORE_FILE: &str = "scores.txt";
 const NUM_HIGHSCORES: usize = 5;
 
-fn write_to_file(content: &str, file_name: &str) -> io::Result<()> {
+pub fn write_to_file(content: &str, file_name: &str) -> io::Result<()> {
     let mut f = File::create(file_name)?;
     f.write_all(content.as_bytes())
 }
 
-fn read_from_file(file_name: &str) -> io::Result<String> {
+pub fn read_from_file(file_name: &str) -> io::Result<String> {
     let mut f = File::open(file_name)?;
     let mut content = String::new();
     f.read_to_string(&mut content)?;
@@ -26,7 +31,7 @@ fn string_to_slice(line: &str) -> Vec<u32> {
 
 pub fn load_highscores_and_lines() -> Option<(Vec<u32>, Vec<u32>)> {
     if let Ok(content) = read_from_file(HIGHSCORE_FILE) {
-        let mut lines = content.splitn(2, "\n").map(|line| string_to_slice(line)).collect::<Vec<_>>();
+        let mut lines = content.splitn(2, "\n").map(string_to_slice).collect::<Vec<_>>();
         if lines.len() == 2 {
             let (number_lines, highscores) = (lines.pop().unwrap(), lines.pop().unwrap());
             Some((highscores, number_lines))
@@ -58,4 +63,87 @@ pub fn update_vec(v: &mut Vec<u32>, value: u32) -> bool {
         }
         false
     }
-}
\ No newline at end of file
+}
+
+// keeps the game's score and lines among the best, returning whether each was the best yet
+pub fn record_highscores(tetris: &Tetris) -> (bool, bool) {
+    match load_highscores_and_lines() {
+        Some((mut highscores, mut lines_sent)) => {
+            let new_highest_highscore = update_vec(&mut highscores, tetris.score);
+            let new_highest_lines_sent = update_vec(&mut lines_sent, tetris.num_lines);
+            if new_highest_highscore || new_highest_lines_sent {
+                save_highscores_and_lines(&highscores, &lines_sent);
+            }
+            (new_highest_highscore, new_highest_lines_sent)
+        }
+        None => {
+            save_highscores_and_lines(&[tetris.score], &[tetris.num_lines]);
+            (true, true)
+        }
+    }
+}
+
+// how the game went, a line each, for front-ends that print it once the game is over
+pub fn game_summary(tetris: &Tetris, finesse: &FinesseTracker, (best_score, best_lines): (bool, bool)) -> Vec<String> {
+    vec![
+        "Game over...".to_string(),
+        format!("Score:            {}{}", tetris.score, if best_score {"[NEW HIGHSCORE]"} else {""}),
+        format!("Number of lines:  {}{}", tetris.num_lines, if best_lines {"[NEW HIGHSCORE]"} else {""}),
+        format!("Current level:    {}", tetris.current_level),
+        format!("Finesse faults:   {} in {} pieces, {} extra inputs", finesse.faults, finesse.checked, finesse.extra_inputs),
+    ]
+}
+
+// the name replays and the leaderboard go under unless the player gives another
+pub fn player_name() -> String {
+    env::var("USER").or_else(|_| env::var("USERNAME")).unwrap_or_else(|_| "player".to_string())
+}
+
+const LEADERBOARD_FILE: &str = "leaderboard.txt";
+pub const LEADERBOARD_SIZE: usize = 10;
+
+// a game on the leaderboard, under the name the player gave it
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Entry {
+    pub name: String,
+    pub score: u32,
+    pub lines: u32,
+    pub level: u32,
+}
+
+// the best games, best first, a line each with the score, lines and level before the name, which can have spaces in it
+pub fn load_leaderboard() -> Vec<Entry> {
+    let content = read_from_file(LEADERBOARD_FILE).unwrap_or_default();
+    let mut entries = content.lines().filter_map(|line| {
+        let mut words = line.splitn(4, ' ');
+        let score = words.next()?.parse().ok()?;
+        let lines = words.next()?.parse().ok()?;
+        let level = words.next()?.parse().ok()?;
+        Some(Entry {name: words.next()?.to_string(), score, lines, level})
+    }).collect::<Vec<_>>();
+    entries.sort_by_key(|entry| Reverse(entry.score));
+    entries.truncate(LEADERBOARD_SIZE);
+    entries
+}
+
+pub fn save_leaderboard(entries: &[Entry]) -> bool {
+    let content = entries.iter()
+        .map(|entry| format!("{} {} {} {}\n", entry.score, entry.lines, entry.level, entry.name))
+        .collect::<String>();
+    write_to_file(&content, LEADERBOARD_FILE).is_ok()
+}
+
+// where a game with the score would go on the leaderboard, if it makes it on; a game has to score to get on, and
+// goes under the games that scored as much before it
+pub fn leaderboard_place(entries: &[Entry], score: u32) -> Option<usize> {
+    let place = entries.iter().position(|entry| score > entry.score).unwrap_or(entries.len());
+    Some(place).filter(|place| score > 0 && *place < LEADERBOARD_SIZE)
+}
+
+// puts the game on the leaderboard if it makes it, returning where it went
+pub fn add_to_leaderboard(entries: &mut Vec<Entry>, entry: Entry) -> Option<usize> {
+    let place = leaderboard_place(entries, entry.score)?;
+    entries.insert(place, entry);
+    entries.truncate(LEADERBOARD_SIZE);
+    Some(place)
+}
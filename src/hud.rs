@@ -0,0 +1,132 @@
+// What is shown beside a game on its own while it is played. Every stat is shown unless the HUD file hides it, with a
+// line for each one to leave out, and a later "show" line brings one back:
+//
+//     hide apm
+//     hide back_to_back
+use tetris::{Tetris, FPS};
+
+use std::fs::File;
+use std::io::Read;
+
+pub const HUD_FILE: &str = "hud.txt";
+// the names of the stats are padded to this, so that their values line up
+const LABEL_WIDTH: usize = 7;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stat {
+    Score,
+    Level,
+    // with the lines still to clear to get to the next level
+    Lines,
+    Time,
+    PiecesPerSecond,
+    AttackPerMinute,
+    // clears in a row after the first one
+    Combo,
+    // whether the next tetris or T-spin carries on a back-to-back
+    BackToBack,
+}
+
+pub const STATS: [Stat; 8] = [Stat::Score, Stat::Level, Stat::Lines, Stat::Time, Stat::PiecesPerSecond,
+    Stat::AttackPerMinute, Stat::Combo, Stat::BackToBack];
+
+impl Stat {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Stat::Score => "score",
+            Stat::Level => "level",
+            Stat::Lines => "lines",
+            Stat::Time => "time",
+            Stat::PiecesPerSecond => "pps",
+            Stat::AttackPerMinute => "apm",
+            Stat::Combo => "combo",
+            Stat::BackToBack => "back_to_back",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Stat> {
+        STATS.iter().find(|stat| stat.name() == name).cloned()
+    }
+
+    fn label(&self) -> &'static str {
+        match *self {
+            Stat::Score => "Score",
+            Stat::Level => "Level",
+            Stat::Lines => "Lines",
+            Stat::Time => "Time",
+            Stat::PiecesPerSecond => "PPS",
+            Stat::AttackPerMinute => "APM",
+            Stat::Combo => "Combo",
+            Stat::BackToBack => "B2B",
+        }
+    }
+
+    // the stat as it is shown; the rates are worked out from the frames played, so they don't count pauses
+    pub fn value(&self, tetris: &Tetris) -> String {
+        let seconds = tetris.frame as f64 / FPS as f64;
+        let per_second = |count: u32| if seconds > 0.0 { count as f64 / seconds } else { 0.0 };
+        match *self {
+            Stat::Score => tetris.score.to_string(),
+            Stat::Level => tetris.current_level.to_string(),
+            Stat::Lines => match tetris.lines_to_next_level() {
+                Some(left) => format!("{} ({} to next)", tetris.num_lines, left),
+                None => tetris.num_lines.to_string()
+            },
+            Stat::Time => {
+                let seconds = tetris.frame / FPS;
+                format!("{}:{:02}", seconds / 60, seconds % 60)
+            }
+            Stat::PiecesPerSecond => format!("{:.2}", per_second(tetris.num_pieces)),
+            Stat::AttackPerMinute => format!("{:.1}", per_second(tetris.sent) * 60.0),
+            Stat::Combo => tetris.combo.saturating_sub(1).to_string(),
+            Stat::BackToBack => if tetris.back_to_back { "Yes" } else { "No" }.to_string(),
+        }
+    }
+}
+
+// a line of the HUD, the name followed by the value
+pub fn hud_line(label: &str, value: &str) -> String {
+    format!("{:width$} {}", label, value, width = LABEL_WIDTH - 1)
+}
+
+pub struct Hud {
+    // the stats shown, in the order of `STATS`
+    pub stats: Vec<Stat>,
+}
+
+impl Default for Hud {
+    fn default() -> Hud {
+        Hud {stats: STATS.to_vec()}
+    }
+}
+
+impl Hud {
+    // the stats the HUD file leaves shown, or all of them without one
+    pub fn load() -> Hud {
+        let mut hud = Hud::default();
+        let mut content = String::new();
+        if File::open(HUD_FILE).and_then(|mut f| f.read_to_string(&mut content)).is_err() {
+            return hud
+        }
+        for line in content.lines() {
+            match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                ["hide", name] => if let Some(stat) = Stat::from_name(name) {
+                    hud.stats.retain(|shown| *shown != stat);
+                },
+                ["show", name] => if let Some(stat) = Stat::from_name(name) {
+                    if !hud.stats.contains(&stat) {
+                        hud.stats.push(stat);
+                        hud.stats.sort_by_key(|shown| STATS.iter().position(|stat| stat == shown));
+                    }
+                },
+                _ => {}
+            }
+        }
+        hud
+    }
+
+    // a line for every stat shown
+    pub fn lines(&self, tetris: &Tetris) -> Vec<String> {
+        self.stats.iter().map(|stat| hud_line(stat.label(), &stat.value(tetris))).collect()
+    }
+}
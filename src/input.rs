@@ -0,0 +1,46 @@
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    SoftDrop,
+    HardDrop,
+    RotateClockwise,
+    RotateCounterClockwise,
+    Hold,
+    Pause,
+    Quit,
+    ToggleAi,
+}
+
+pub const ACTIONS: [Action; 10] = [Action::MoveLeft, Action::MoveRight, Action::SoftDrop, Action::HardDrop,
+    Action::RotateClockwise, Action::RotateCounterClockwise, Action::Hold, Action::Pause, Action::Quit, Action::ToggleAi];
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Action::MoveLeft => "move_left",
+            Action::MoveRight => "move_right",
+            Action::SoftDrop => "soft_drop",
+            Action::HardDrop => "hard_drop",
+            Action::RotateClockwise => "rotate_cw",
+            Action::RotateCounterClockwise => "rotate_ccw",
+            Action::Hold => "hold",
+            Action::Pause => "pause",
+            Action::Quit => "quit",
+            Action::ToggleAi => "toggle_ai",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTIONS.iter().find(|action| action.name() == name).cloned()
+    }
+
+    // stable number used to store the action in replays
+    pub fn code(&self) -> u8 {
+        ACTIONS.iter().position(|action| action == self).unwrap() as u8
+    }
+
+    pub fn from_code(code: u8) -> Option<Action> {
+        ACTIONS.get(code as usize).cloned()
+    }
+}
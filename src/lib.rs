@@ -0,0 +1,27 @@
+// The game itself, without any of the SDL front-end, so that it can also be simulated headlessly
+extern crate rand;
+#[macro_use]
+extern crate serde_json;
+
+pub mod tetrimino;
+pub mod pieces;
+pub mod board;
+pub mod tetris;
+pub mod highscore;
+pub mod input;
+pub mod randomizer;
+pub mod save;
+pub mod replay;
+pub mod ai;
+pub mod tbp;
+pub mod sim;
+pub mod tune;
+pub mod finesse;
+pub mod pathfind;
+pub mod garbage;
+pub mod versus;
+pub mod net;
+pub mod spectate;
+pub mod royale;
+pub mod royale_net;
+pub mod hud;
@@ -1,223 +1,1655 @@
 extern crate sdl2;
+extern crate tetris;
 
-pub mod tetrimino;
-pub mod tetris;
-pub mod highscore;
-pub mod graphics;
+mod graphics;
+mod controls;
 
-use highscore::{load_highscores_and_lines, save_highscores_and_lines, update_vec};
-use tetris::Tetris;
-use graphics::{create_texture_rect, display_score, load_asset};
+use tetris::highscore::{add_to_leaderboard, leaderboard_place, load_leaderboard, player_name, record_highscores,
+    save_leaderboard, Entry};
+use tetris::tetris::{BoardSettings, Tetris, FPS, NUM_NEXT_PIECES};
+use tetris::randomizer::random_seed;
+use tetris::pieces::PieceSet;
+use graphics::{create_texture_rect, load_asset, Align, Text};
+use tetris::input::Action;
+use tetris::ai::{Bot, BotPlayer};
+use tetris::tbp;
+use tetris::finesse::FinesseTracker;
+use controls::{Bindings, Controls};
+use tetris::save::{delete_save, load_game, save_game};
+use tetris::replay::{save_replay, verify_file, Mode, Replay, ReplayPlayer};
+use tetris::versus::{RoundResult, Versus, NUM_PLAYERS};
+use tetris::garbage::DEFAULT_GARBAGE_DELAY;
+use tetris::net::{Session, DEFAULT_PORT};
+use tetris::spectate::{spectate_address, Spectator, SpectatorServer, DEFAULT_SPECTATE_PORT};
+use tetris::royale::{badge_bonus, Targeting, TARGETINGS};
+use tetris::royale_net::{RoyaleClient, DEFAULT_ROYALE_PORT};
+use tetris::hud::{hud_line, Hud, Stat};
 
 use sdl2::pixels::Color;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
-use sdl2::render::TextureCreator;
+use sdl2::controller::Button;
+use sdl2::render::{BlendMode, Canvas, Texture, TextureCreator};
+use sdl2::video::{FullscreenType, Window};
 use sdl2::rect::Rect;
 use sdl2::image::INIT_PNG;
 
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant};
 use std::thread::sleep;
+use std::env;
+use std::process;
+use std::sync::Arc;
+use std::slice;
 
-const FPS: u32 = 60;
 const WAIT_TIME: u32 = 1_000_000_000u32 / FPS;
-const LEVEL_TIMES: [u32; 10] = [1000, 850, 700, 600, 500, 400, 300, 250, 221, 190];
 
 const TETRIS_HEIGHT: usize = 40;
-const GRID_ORIGIN_X: i32 = 0;
-const GRID_ORIGIN_Y : i32 = 0;
-const GRID_WIDTH: u32 = TETRIS_HEIGHT as u32 * 10;
-const GRID_HEIGHT: u32 = TETRIS_HEIGHT as u32 * 16;
-const WINDOW_WIDTH: u32 = GRID_WIDTH;
-const WINDOW_HEIGHT: u32 = GRID_HEIGHT;
-
-fn handle_events(tetris: &mut Tetris, quit: &mut bool, timer: &mut SystemTime, event_pump: &mut sdl2::EventPump, 
-    paused: &mut bool) -> bool {
-    let mut make_permanent = false;
+// the height of the text written over the game
+const TEXT_HEIGHT: u32 = 30;
+// the window can be resized, and made as big as the screen with F11, but not made smaller than this
+const MIN_WINDOW_WIDTH: u32 = 320;
+const MIN_WINDOW_HEIGHT: u32 = 240;
+// the panels on each side of a game on its own, in cells, each of the coming pieces getting a few lines of the one
+// on the right under its title, and the lines they need for boards lower than that
+const PANEL_COLUMNS: u32 = 5;
+const PREVIEW_LINES: u32 = 3;
+const PANEL_LINES: u32 = 1 + NUM_NEXT_PIECES as u32 * PREVIEW_LINES;
+// the space between the boards of a versus game and the strip above them the players' wins are shown in
+const VERSUS_GAP: u32 = 80;
+const VERSUS_HUD_HEIGHT: u32 = 40;
+// the incoming garbage meter on the left of each board
+const METER_WIDTH: u32 = 16;
+const DEFAULT_ROUNDS: u32 = 3;
+// the most rounds a versus game started from the menu can be the best of
+const MAX_ROUNDS: u32 = 9;
+// the strip above the board of a battle royale, and the other players' boards beside it in columns, each under its label
+const ROYALE_HUD_HEIGHT: u32 = 80;
+const MINI_COLUMNS: usize = 2;
+const MINI_GAP: u32 = 20;
+const MINI_LABEL_HEIGHT: u32 = 30;
+const MINI_MIN_WIDTH: u32 = 90;
+const MAX_MINI_CELL: u32 = 10;
+const TARGETING_KEYS: [Keycode; 4] = [Keycode::Num1, Keycode::Num2, Keycode::Num3, Keycode::Num4];
+// frames the result of a round is shown for before it can be skipped, so a drop meant for the game doesn't
+const RESULT_DELAY: u32 = FPS;
+
+const REPLAY_SPEEDS: [f64; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const REPLAY_SEEK_FRAMES: u32 = 5 * FPS;
+// how long an input stays on the replay HUD after it was made
+const INPUT_DISPLAY_FRAMES: u32 = 15;
+// the menus are scaled to the window, their lines no higher than this, and their titles take up a few lines
+const MENU_LINE_HEIGHT: u32 = 48;
+const MENU_TITLE_LINES: u32 = 3;
+// the longest name a game can go on the leaderboard under
+const MAX_NAME_LENGTH: usize = 16;
+const USAGE: &str = "Usage: tetris [--board classic|guideline|<width>x<height>[+<hidden lines>]] \
+    [--lock-out full|partial] [--pieces <file>] [--bot \"<bot command>\"] [--spectate [<address>:]<port>]
+       tetris versus [--rounds <best of>] [--garbage-delay <frames>] [--board ...] [--lock-out ...] [--pieces <file>] \
+    [--bot ...] [--spectate ...]
+       tetris host [--port <port>] [--rounds ...] [--garbage-delay ...] [--board ...] [--lock-out ...] [--pieces <file>] \
+    [--bot ...] [--spectate ...]
+       tetris join <address>[:<port>] [--bot ...] [--spectate ...]
+       tetris royale <address>[:<port>] [--targeting random|attackers|kos|badges] [--bot ...] [--spectate ...]
+       tetris spectate <address>[:<port>]
+       tetris replay <file>
+       tetris verify <file>";
+
+// everything the game is drawn with
+struct Assets<'a> {
+    grid: Texture<'a>,
+    textures: Vec<Texture<'a>>,
+    text: Text<'a>,
+    // the stats shown beside a game on its own
+    hud: Hud,
+}
+
+fn handle_events(canvas: &mut Canvas<Window>, quit: &mut bool, event_pump: &mut sdl2::EventPump, controls: &mut Controls,
+    paused: &mut bool, toggle_ai: &mut bool, actions: &mut Vec<Action>) {
+    for event in event_pump.poll_iter() {
+        toggle_fullscreen(canvas, &event);
+        if let Event::ControllerDeviceRemoved {..} = event {
+            // don't let the game carry on while the controller is reconnected
+            *paused = true;
+        }
+        match controls.translate(&event) {
+            Some(Action::Quit) => *quit = true,
+            Some(Action::Pause) => *paused = !*paused,
+            Some(Action::ToggleAi) => *toggle_ai = !*toggle_ai,
+            Some(_) if *paused => {},
+            Some(action) => actions.push(action),
+            None => {}
+        }
+    }
+}
+
+// asks whether to resume the suspended game, returning None if the window is closed
+fn ask_to_continue(tetris: &Tetris, canvas: &mut Canvas<Window>, assets: &Assets, event_pump: &mut sdl2::EventPump,
+    controls: &mut Controls) -> Option<bool> {
+    loop {
+        for event in event_pump.poll_iter() {
+            toggle_fullscreen(canvas, &event);
+            controls.translate(&event);
+            match event {
+                Event::Quit {..} => return None,
+                Event::KeyDown {keycode: Some(Keycode::Y), ..} | Event::KeyDown {keycode: Some(Keycode::Return), ..} |
+                Event::ControllerButtonDown {button: Button::Start, ..} => return Some(true),
+                Event::KeyDown {keycode: Some(Keycode::N), ..} | Event::KeyDown {keycode: Some(Keycode::Escape), ..} |
+                Event::ControllerButtonDown {button: Button::Back, ..} => return Some(false),
+                _ => {}
+            }
+        }
+
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        assets.text.draw(canvas, "Saved game found", (40, 200), TEXT_HEIGHT, Align::Left);
+        assets.text.draw(canvas, &format!("Score: {}  Level: {}", tetris.score, tetris.current_level), (40, 240), TEXT_HEIGHT,
+            Align::Left);
+        assets.text.draw(canvas, "Continue? (Y/N)", (40, 300), TEXT_HEIGHT, Align::Left);
+        canvas.present();
+
+        sleep(Duration::new(0, WAIT_TIME));
+    }
+}
+
+// the external bot if one was given, otherwise the built-in AI, saying which
+fn create_bot(bot_command: Option<&str>) -> Box<dyn Bot> {
+    let (bot, message) = tbp::create_bot(bot_command, false);
+    if let Some(message) = message {
+        println!("{}", message);
+    }
+    bot
+}
+
+// what the bot had to say, like why the built-in AI is playing for it
+fn print_bot_messages(ai: &mut BotPlayer) {
+    for message in ai.take_messages() {
+        println!("{}", message);
+    }
+}
+
+// where a board is drawn in the window: the top left corner of its shown lines, their size and the size of its cells
+#[derive(Clone, Copy)]
+struct Layout {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    cell: u32,
+}
+
+impl Layout {
+    fn new(settings: &BoardSettings, x: i32, y: i32, cell: u32) -> Layout {
+        Layout {x, y, width: cell * settings.width as u32, height: cell * settings.height as u32, cell}
+    }
+
+    // the square of the cell in the given column and shown line
+    fn cell(&self, column: isize, line: usize) -> Rect {
+        Rect::new(self.x + column as i32 * self.cell as i32, self.y + line as i32 * self.cell as i32, self.cell, self.cell)
+    }
+}
+
+// the shown lines of the board with full sized cells
+fn board_size(settings: &BoardSettings) -> (u32, u32) {
+    let layout = Layout::new(settings, 0, 0, TETRIS_HEIGHT as u32);
+    (layout.width, layout.height)
+}
+
+// where the parts of a game on its own are drawn, worked out from the size of the window: the board in the middle,
+// the held piece and the numbers of the game in panels on its left and the coming pieces in a panel on its right
+struct GameLayout {
+    board: Layout,
+    hold: Rect,
+    stats: Rect,
+    next: Rect,
+}
+
+// the lines and columns a game on its own takes up, in cells
+fn game_cells(settings: &BoardSettings) -> (u32, u32) {
+    (2 * PANEL_COLUMNS + settings.width as u32, (settings.height as u32).max(PANEL_LINES))
+}
+
+// the cells are made as big as the window allows and the game is centred in it, with bars left on the sides it
+// doesn't fill
+fn game_layout(settings: &BoardSettings, (window_width, window_height): (u32, u32)) -> GameLayout {
+    let (columns, lines) = game_cells(settings);
+    let cell = (window_width / columns).min(window_height / lines).max(1);
+    let x = (window_width.saturating_sub(columns * cell) / 2) as i32;
+    let y = (window_height.saturating_sub(lines * cell) / 2) as i32;
+    let panel_width = PANEL_COLUMNS * cell;
+    let board_y = y + ((lines - settings.height as u32) * cell / 2) as i32;
+    let board = Layout::new(settings, x + panel_width as i32, board_y, cell);
+    let hold_height = (1 + PREVIEW_LINES) * cell;
+    GameLayout {
+        board,
+        hold: Rect::new(x, y, panel_width, hold_height),
+        stats: Rect::new(x, y + hold_height as i32, panel_width, lines * cell - hold_height),
+        next: Rect::new(board.x + board.width as i32, y, panel_width, lines * cell),
+    }
+}
+
+// the window a game on its own opens in, with full sized cells
+fn window_size(settings: &BoardSettings) -> (u32, u32) {
+    let (columns, lines) = game_cells(settings);
+    (columns * TETRIS_HEIGHT as u32, lines * TETRIS_HEIGHT as u32)
+}
+
+// the boards of a versus game side by side, under the strip the wins are shown in
+fn versus_layouts(settings: &BoardSettings) -> Vec<Layout> {
+    let (width, _) = board_size(settings);
+    (0..NUM_PLAYERS).map(|player| {
+        Layout::new(settings, (METER_WIDTH + player as u32 * (width + VERSUS_GAP)) as i32, VERSUS_HUD_HEIGHT as i32,
+            TETRIS_HEIGHT as u32)
+    }).collect()
+}
+
+fn versus_window_size(settings: &BoardSettings) -> (u32, u32) {
+    let (width, height) = board_size(settings);
+    (METER_WIDTH + NUM_PLAYERS as u32 * width + (NUM_PLAYERS as u32 - 1) * VERSUS_GAP, height + VERSUS_HUD_HEIGHT)
+}
+
+// the screens with several boards are laid out for full sized cells, and the renderer scales them to fit the window,
+// keeping their proportions with bars on the sides they don't fill; without a size they are drawn as they are
+fn letterbox(canvas: &mut Canvas<Window>, size: Option<(u32, u32)>) {
+    let (width, height) = size.unwrap_or((0, 0));
+    if canvas.set_logical_size(width, height).is_err() {
+        println!("Failed to scale the game to the window.");
+    }
+}
+
+// F11 switches between the window and the whole screen, on every screen
+fn toggle_fullscreen(canvas: &mut Canvas<Window>, event: &Event) {
+    if let Event::KeyDown {keycode: Some(Keycode::F11), repeat: false, ..} = *event {
+        let window = canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off
+        };
+        if let Err(e) = window.set_fullscreen(fullscreen) {
+            println!("Failed to switch to fullscreen: {}", e);
+        }
+    }
+}
+
+fn clear(canvas: &mut Canvas<Window>, colour: Color) {
+    canvas.set_draw_color(colour);
+    canvas.clear();
+}
+
+// darkens everything drawn so far, so what is drawn over it stands out
+fn dim(canvas: &mut Canvas<Window>) {
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+    canvas.fill_rect(None).expect("Failed to dim the screen.");
+    canvas.set_blend_mode(BlendMode::None);
+}
+
+fn draw_game(canvas: &mut Canvas<Window>, assets: &Assets, tetris: &Tetris, layout: &Layout) {
+    let hidden = tetris.settings.hidden;
+    canvas.copy(&assets.grid, None, Rect::new(layout.x, layout.y, layout.width, layout.height))
+        .expect("Failed to copy texture to window.");
+
+    if let Some(ref piece) = tetris.current_piece {
+        for (line_num, line) in piece.states[piece.current_state as usize].iter().enumerate() {
+            for (case_num, case) in line.iter().enumerate() {
+                if *case == 0 || piece.y + line_num < hidden {
+                    continue
+                }
+                canvas.copy(&assets.textures[*case as usize - 1], None,
+                        layout.cell(piece.x + case_num as isize, piece.y + line_num - hidden))
+                    .expect("Failed to copy texture to window");
+            }
+        }
+    }
+
+    for (line_num, line) in tetris.game_map.iter().skip(hidden).enumerate() {
+        for (case_num, case) in line.iter().enumerate() {
+            if *case == 0 {
+                continue 
+            }
+            canvas.copy(&assets.textures[*case as usize - 1], None, layout.cell(case_num as isize, line_num))
+                .expect("Failed to copy texture to window.");
+        }
+    }
+}
+
+// the piece as it comes in, centred in the box with its empty lines and columns left out, its cells no bigger than
+// the board's
+fn draw_preview(canvas: &mut Canvas<Window>, assets: &Assets, tetris: &Tetris, kind: u8, rect: Rect, cell: u32) {
+    let piece = tetris.pieces.create(kind);
+    let state = &piece.states[0];
+    let filled = |case: &u8| *case != 0;
+    let lines = state.iter().enumerate().filter(|(_, line)| line.iter().any(filled)).map(|(y, _)| y).collect::<Vec<_>>();
+    let columns = (0..state[0].len()).filter(|x| state.iter().any(|line| line[*x] != 0)).collect::<Vec<_>>();
+    let (top, left) = match (lines.first(), columns.first()) {
+        (Some(top), Some(left)) => (*top, *left),
+        _ => return
+    };
+    let (num_lines, num_columns) = (lines.len() as u32, (columns[columns.len() - 1] - left + 1) as u32);
+    let size = cell.min(rect.width() / num_columns).min(rect.height() / num_lines);
+    let x = rect.x() + (rect.width() - num_columns * size) as i32 / 2;
+    let y = rect.y() + (rect.height() - num_lines * size) as i32 / 2;
+    for (line_num, line) in state.iter().enumerate().skip(top) {
+        for (case_num, case) in line.iter().enumerate().skip(left) {
+            if *case != 0 {
+                let (cell_x, cell_y) = ((case_num - left) as u32 * size, (line_num - top) as u32 * size);
+                let cell_rect = Rect::new(x + cell_x as i32, y + cell_y as i32, size, size);
+                canvas.copy(&assets.textures[*case as usize - 1], None, cell_rect).expect("Failed to copy texture to window.");
+            }
+        }
+    }
+}
+
+// a game on its own, with the held piece, the HUD and the coming pieces in the panels beside it
+fn draw_single(canvas: &mut Canvas<Window>, assets: &Assets, tetris: &Tetris, layout: &GameLayout, extra: &[String]) {
+    clear(canvas, Color::RGB(0, 0, 0));
+    canvas.set_draw_color(Color::RGB(25, 25, 25));
+    canvas.fill_rects(&[layout.hold, layout.stats, layout.next]).expect("Failed to draw the panels.");
+    draw_game(canvas, assets, tetris, &layout.board);
+
+    let cell = layout.board.cell;
+    let margin = cell as i32 / 4;
+    let text_height = cell * 3 / 4;
+    assets.text.draw(canvas, "Hold", (layout.hold.x() + margin, layout.hold.y() + margin), text_height, Align::Left);
+    let preview_height = PREVIEW_LINES * cell;
+    let preview_cell = cell * 3 / 4;
+    if let Some(ref piece) = tetris.held_piece {
+        let rect = Rect::new(layout.hold.x(), layout.hold.y() + cell as i32, layout.hold.width(), preview_height);
+        draw_preview(canvas, assets, tetris, piece.kind, rect, preview_cell);
+    }
+    assets.text.draw(canvas, "Next", (layout.next.x() + margin, layout.next.y() + margin), text_height, Align::Left);
+    for (num, kind) in tetris.next_pieces.iter().enumerate() {
+        let rect = Rect::new(layout.next.x(), layout.next.y() + (cell + num as u32 * preview_height) as i32, layout.next.width(),
+            preview_height);
+        draw_preview(canvas, assets, tetris, *kind, rect, preview_cell);
+    }
+    // the lines of the HUD are smaller, so that they fit the panel
+    let line_height = cell * 2 / 3;
+    for (line_num, line) in assets.hud.lines(tetris).iter().chain(extra).enumerate() {
+        let y = layout.stats.y() + margin + (line_num as u32 * line_height) as i32;
+        assets.text.draw(canvas, line, (layout.stats.x() + margin, y), cell / 2, Align::Left);
+    }
+}
+
+// a game on its own, returning it as it ended with the finesse of its pieces, or None if it was left before then
+fn play_game(canvas: &mut Canvas<Window>, assets: &Assets, event_pump: &mut sdl2::EventPump, controls: &mut Controls,
+    options: &GameOptions, spectators: &mut Option<SpectatorServer>) -> Option<(Tetris, FinesseTracker)> {
+    let bot_command = options.bot_command.as_deref();
+    letterbox(canvas, None);
+    let mut tetris = Tetris::with_settings(random_seed(), options.settings, options.pieces.clone());
+    let mut replay = Some(Replay::new(Mode::Marathon, options.settings, options.pieces.clone(), tetris.seed, &player_name()));
+    if let Some(saved) = load_game() {
+        match ask_to_continue(&saved.tetris, canvas, assets, event_pump, controls) {
+            Some(true) => {
+                tetris = saved.tetris;
+                replay = saved.replay;
+                if replay.is_none() {
+                    println!("The replay of the saved game is missing, this game won't be recorded.");
+                }
+            }
+            Some(false) => delete_save(),
+            None => return None
+        }
+    }
+
+    let mut finesse = FinesseTracker::new(&tetris);
+    let mut paused = false;
+    let mut ai: Option<BotPlayer> = None;
+    // an external bot plays from the start
+    let mut toggle_ai = bot_command.is_some();
+    let mut actions = Vec::new();
+    let frame_time = Duration::new(0, WAIT_TIME);
+    let mut last_frame = Instant::now();
     
-    if !*paused {
-        if let Some(ref mut piece) = tetris.current_piece {
-            let mut tmp_x = piece.x;
-            let mut tmp_y = piece.y;
-
-            for event in event_pump.poll_iter() {
-                match event {
-                    Event::Quit {..} | Event::KeyDown {keycode: Some(Keycode::Escape), ..} => {*quit = true; break},
-                    Event::KeyDown{keycode: Some(Keycode::P), ..} => {*paused = !*paused; break},
-                    Event::KeyDown{keycode: Some(Keycode::Down), ..} => {*timer = SystemTime::now(); tmp_y += 1},
-                    Event::KeyDown{keycode: Some(Keycode::Right), ..} => tmp_x += 1,
-                    Event::KeyDown{keycode: Some(Keycode::Left), ..} => tmp_x -= 1,
-                    Event::KeyDown{keycode: Some(Keycode::Up), ..} => piece.rotate(&tetris.game_map),
-                    Event::KeyDown{keycode: Some(Keycode::Space), ..} => {
-                        let x = piece.x; 
-                        let mut y = piece.y;
-                        while piece.change_position(&tetris.game_map, x, y +1) {
-                            y += 1;
+    loop {
+        let mut quit = false;
+        handle_events(canvas, &mut quit, event_pump, controls, &mut paused, &mut toggle_ai, &mut actions);
+        if toggle_ai {
+            ai = match ai {
+                Some(_) => None,
+                None => {
+                    // so a leaderboard can tell games the AI took part in
+                    if let Some(ref mut replay) = replay {
+                        if !replay.player.ends_with(" + AI") {
+                            replay.player += " + AI";
                         }
-                        make_permanent = true
-                    },
-                    _ => {}
+                    }
+                    Some(BotPlayer::new(create_bot(bot_command)))
+                }
+            };
+            toggle_ai = false;
+        }
+
+        // the game advances in whole frames, however fast the screen is drawn, so that it can be replayed
+        if paused {
+            last_frame = Instant::now();
+        }
+        while !paused && last_frame.elapsed() >= frame_time {
+            last_frame += frame_time;
+            // the AI's inputs take the place of the player's
+            if let Some(ref mut ai) = ai {
+                actions = ai.next_actions(&tetris);
+                print_bot_messages(ai);
+            }
+            if let Some(ref mut replay) = replay {
+                for action in &actions {
+                    replay.record(tetris.frame, *action);
                 }
             }
-            if !make_permanent {
-                if !piece.change_position(&tetris.game_map, tmp_x, tmp_y) && tmp_y != piece.y {
-                    make_permanent = true;
+            tetris.step(&actions);
+            finesse.update(&tetris, &actions);
+            actions.clear();
+        }
+        if let Some(ref mut spectators) = *spectators {
+            spectators.broadcast(slice::from_ref(&tetris));
+        }
+
+        if tetris.game_over {
+            delete_save();
+            if let Some(mut replay) = replay {
+                replay.finish(&tetris);
+                match save_replay(&replay) {
+                    Ok(file_name) => println!("Replay saved to {}, check it with `tetris verify {}`", file_name, file_name),
+                    Err(e) => println!("Failed to save the replay: {}", e)
                 }
             }
+            return Some((tetris, finesse))
+        }
+
+        // the layout follows the window as it is resized
+        let layout = game_layout(&tetris.settings, canvas.output_size().expect("Failed to get the window size."));
+        let mut extra = vec![hud_line("Faults", &finesse.faults.to_string())];
+        if ai.is_some() {
+            extra.push("AI playing".to_string());
+        }
+        draw_single(canvas, assets, &tetris, &layout, &extra);
+        if paused {
+            dim(canvas);
+            let board = layout.board;
+            draw_lines_at(canvas, assets, (board.x + board.width as i32 / 2, board.y + board.height as i32 / 2),
+                &["Paused".to_string(), "Esc to save and leave".to_string()]);
+        }
+
+        canvas.present();
+
+        if quit {
+            if save_game(&tetris, replay.as_ref()) {
+                println!("Game saved, it can be continued next time.");
+            } else {
+                println!("Failed to save the game.");
+            }
+            return None
         }
-        if make_permanent {
-            tetris.make_permanent();
-            *timer = SystemTime::now();
+
+        if let Some(remaining) = frame_time.checked_sub(last_frame.elapsed()) {
+            sleep(remaining);
         }
-        make_permanent
     }
-    else {
+}
+
+// a red bar beside the board as high as the garbage waiting to come up it, brighter for the lines that are ready to
+fn draw_garbage_meter(canvas: &mut Canvas<Window>, tetris: &Tetris, layout: &Layout) {
+    let bottom = layout.y + layout.height as i32;
+    let ready = tetris.garbage.ready() as i32 * layout.cell as i32;
+    let total = (tetris.garbage.total() as i32 * layout.cell as i32).min(layout.height as i32);
+    let x = layout.x - METER_WIDTH as i32;
+    if total > ready {
+        canvas.set_draw_color(Color::RGB(120, 0, 0));
+        canvas.fill_rect(Rect::new(x, bottom - total, METER_WIDTH, (total - ready) as u32)).expect("Failed to draw the meter.");
+    }
+    if ready > 0 {
+        let ready = ready.min(total);
+        canvas.set_draw_color(Color::RGB(230, 30, 30));
+        canvas.fill_rect(Rect::new(x, bottom - ready, METER_WIDTH, ready as u32)).expect("Failed to draw the meter.");
+    }
+}
+
+fn draw_versus(canvas: &mut Canvas<Window>, assets: &Assets, versus: &Versus, ai: &[Option<BotPlayer>]) {
+    clear(canvas, Color::RGB(40, 40, 40));
+    for (player, layout) in versus_layouts(&versus.settings).iter().enumerate() {
+        draw_game(canvas, assets, &versus.games[player], layout);
+        draw_garbage_meter(canvas, &versus.games[player], layout);
+        let status = format!("P{}{}  Wins: {}  Sent: {}", player + 1, if ai[player].is_some() {" AI"} else {""},
+            versus.wins[player], versus.sent[player]);
+        assets.text.draw(canvas, &status, (layout.x, 5), TEXT_HEIGHT, Align::Left);
+    }
+}
+
+// the lines in the middle of the versus window, each on a black box
+fn draw_lines(canvas: &mut Canvas<Window>, assets: &Assets, settings: &BoardSettings, lines: &[String]) {
+    let (window_width, window_height) = versus_window_size(settings);
+    draw_lines_at(canvas, assets, (window_width as i32 / 2, window_height as i32 / 2), lines);
+}
+
+// the lines centred on the point, each on a black box
+fn draw_lines_at(canvas: &mut Canvas<Window>, assets: &Assets, (centre_x, centre_y): (i32, i32), lines: &[String]) {
+    for (line_num, line) in lines.iter().enumerate() {
+        let (width, height) = assets.text.measure(line, TEXT_HEIGHT);
+        let y = centre_y - 40 + line_num as i32 * 40;
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.fill_rect(Rect::new(centre_x - width as i32 / 2 - 10, y - 5, width + 20, height + 10))
+            .expect("Failed to draw the result.");
+        assets.text.draw(canvas, line, (centre_x, y), TEXT_HEIGHT, Align::Centre);
+    }
+}
+
+// who won the round that just ended, and the match if it is over
+fn result_lines(versus: &Versus, result: RoundResult) -> Vec<String> {
+    let mut lines = vec![match result {
+        RoundResult::Winner(player) => format!("Player {} wins round {}", player + 1, versus.round),
+        RoundResult::Draw => format!("Round {} is a draw", versus.round),
+    }];
+    let score = versus.wins.iter().map(|wins| wins.to_string()).collect::<Vec<_>>().join(" - ");
+    lines.push(match versus.winner() {
+        Some(player) => format!("Player {} wins the match {}", player + 1, score),
+        None => score
+    });
+    lines
+}
+
+fn print_match_result(versus: &Versus) {
+    if let Some(player) = versus.winner() {
+        println!("Player {} won the match {}", player + 1,
+            versus.wins.iter().map(|wins| wins.to_string()).collect::<Vec<_>>().join("-"));
+    }
+}
+
+// shows the boards as they ended with the lines over them until a player carries on, returning false if they
+// would rather stop
+fn show_result(canvas: &mut Canvas<Window>, assets: &Assets, event_pump: &mut sdl2::EventPump, controls: &mut Controls,
+    versus: &Versus, ai: &[Option<BotPlayer>], lines: &[String]) -> bool {
+    let mut frames = 0;
+    loop {
         for event in event_pump.poll_iter() {
+            toggle_fullscreen(canvas, &event);
+            controls.translate_player(&event);
             match event {
-                Event::KeyDown{keycode: Some(Keycode::P), ..} => {*paused = !*paused; break}
+                Event::Quit {..} | Event::KeyDown {keycode: Some(Keycode::Escape), ..} |
+                Event::ControllerButtonDown {button: Button::Back, ..} => return false,
+                Event::KeyDown {keycode: Some(Keycode::Return), ..} | Event::KeyDown {keycode: Some(Keycode::Space), ..} |
+                Event::ControllerButtonDown {button: Button::Start, ..} if frames >= RESULT_DELAY => return true,
                 _ => {}
             }
         }
-        make_permanent
+
+        draw_versus(canvas, assets, versus, ai);
+        draw_lines(canvas, assets, &versus.settings, lines);
+        canvas.present();
+
+        frames += 1;
+        sleep(Duration::new(0, WAIT_TIME));
     }
 }
 
-fn print_game_info(tetris: &Tetris) {
-    let mut new_highest_highscore = true;
-    let mut new_highest_lines_sent = true;
-    if let Some((mut highscores, mut lines_sent)) = load_highscores_and_lines() {
-        new_highest_highscore = update_vec(&mut highscores, tetris.score);
-        new_highest_lines_sent = update_vec(&mut lines_sent, tetris.num_lines);
-        if new_highest_highscore || new_highest_lines_sent {
-            save_highscores_and_lines(&highscores, &lines_sent);
+// two players on one machine, each with their own keys or controller, the lines one clears coming up as garbage
+// on the other's board
+fn play_versus(canvas: &mut Canvas<Window>, assets: &Assets, event_pump: &mut sdl2::EventPump, controls: &mut Controls,
+    mut versus: Versus, options: &GameOptions, spectators: &mut Option<SpectatorServer>) {
+    let bot_command = options.bot_command.as_deref();
+    letterbox(canvas, Some(versus_window_size(&versus.settings)));
+
+    let mut paused = false;
+    let mut ai: Vec<Option<BotPlayer>> = (0..NUM_PLAYERS).map(|_| None).collect();
+    let mut actions = vec![Vec::new(); NUM_PLAYERS];
+    let frame_time = Duration::new(0, WAIT_TIME);
+    let mut last_frame = Instant::now();
+
+    loop {
+        for event in event_pump.poll_iter() {
+            toggle_fullscreen(canvas, &event);
+            if let Event::ControllerDeviceRemoved {..} = event {
+                paused = true;
+            }
+            match controls.translate_player(&event) {
+                Some((_, Action::Quit)) => return,
+                Some((_, Action::Pause)) => paused = !paused,
+                Some((player, Action::ToggleAi)) => ai[player] = match ai[player] {
+                    Some(_) => None,
+                    None => Some(BotPlayer::new(create_bot(bot_command)))
+                },
+                Some(_) if paused => {},
+                Some((player, action)) => actions[player].push(action),
+                None => {}
+            }
+        }
+
+        let mut result = None;
+        if paused {
+            last_frame = Instant::now();
+        }
+        while !paused && result.is_none() && last_frame.elapsed() >= frame_time {
+            last_frame += frame_time;
+            for (player, ai) in ai.iter_mut().enumerate() {
+                if let Some(ref mut ai) = *ai {
+                    actions[player] = ai.next_actions(&versus.games[player]);
+                    print_bot_messages(ai);
+                }
+            }
+            result = versus.step(&actions);
+            for actions in &mut actions {
+                actions.clear();
+            }
+        }
+        if let Some(ref mut spectators) = *spectators {
+            spectators.broadcast(&versus.games);
+        }
+
+        if let Some(result) = result {
+            let lines = result_lines(&versus, result);
+            let carry_on = show_result(canvas, assets, event_pump, controls, &versus, &ai, &lines);
+            print_match_result(&versus);
+            if !carry_on || versus.winner().is_some() {
+                return
+            }
+            versus.next_round();
+            for ai in ai.iter_mut().flatten() {
+                ai.reset();
+            }
+            last_frame = Instant::now();
+            continue
+        }
+
+        draw_versus(canvas, assets, &versus, &ai);
+        if paused {
+            dim(canvas);
+            draw_lines(canvas, assets, &versus.settings, &["Paused".to_string()]);
+        }
+        canvas.present();
+
+        if let Some(remaining) = frame_time.checked_sub(last_frame.elapsed()) {
+            sleep(remaining);
         }
-    } else {
-        save_highscores_and_lines(&[tetris.score], &[tetris.num_lines]);
     }
-    println!("Game over...");
-    println!("Score:            {}{}", tetris.score, if new_highest_highscore {"[NEW HIGHSCORE]"} else {""});
-    println!("Number of lines:  {}{}", tetris.num_lines, if new_highest_lines_sent {"[NEW HIGHSCORE]"} else {""});
-    println!("Current level:    {}", tetris.current_level);
 }
 
-fn is_time_over(tetris: &Tetris, timer: &SystemTime) -> bool {
-    match timer.elapsed() {
-        Ok(elapsed) => {
-            let millis = elapsed.as_secs() as u32 * 1000 + elapsed.subsec_nanos() / 1_000_000;
-            millis > LEVEL_TIMES[tetris.current_level as usize - 1]
+// a versus match against a player on another machine, this one's board played with the single player controls
+fn play_online(canvas: &mut Canvas<Window>, assets: &Assets, event_pump: &mut sdl2::EventPump, controls: &mut Controls,
+    mut session: Session, options: &GameOptions, spectators: &mut Option<SpectatorServer>) {
+    let bot_command = options.bot_command.as_deref();
+    letterbox(canvas, Some(versus_window_size(&session.versus.settings)));
+    let local = session.local;
+    let mut ai: Vec<Option<BotPlayer>> = (0..NUM_PLAYERS).map(|_| None).collect();
+    let mut actions = Vec::new();
+    let mut round = session.versus.round;
+    let frame_time = Duration::new(0, WAIT_TIME);
+    let mut last_frame = Instant::now();
+
+    loop {
+        for event in event_pump.poll_iter() {
+            toggle_fullscreen(canvas, &event);
+            match controls.translate(&event) {
+                Some(Action::Quit) => return,
+                Some(Action::ToggleAi) => ai[local] = match ai[local] {
+                    Some(_) => None,
+                    None => Some(BotPlayer::new(create_bot(bot_command)))
+                },
+                // the other player can't be kept waiting
+                Some(Action::Pause) | None => {},
+                Some(action) => actions.push(action),
+            }
+        }
+
+        let mut error = session.poll().err();
+        while error.is_none() && last_frame.elapsed() >= frame_time {
+            last_frame += frame_time;
+            if let Some(ref mut ai) = ai[local] {
+                actions = ai.next_actions(&session.versus.games[local]);
+                print_bot_messages(ai);
+            }
+            if session.send_inputs(&actions) {
+                actions.clear();
+            }
+            loop {
+                match session.step() {
+                    Ok(true) => {},
+                    Ok(false) => break,
+                    Err(e) => {
+                        error = Some(e);
+                        break
+                    }
+                }
+            }
+        }
+        if let Some(ref mut spectators) = *spectators {
+            spectators.broadcast(&session.versus.games);
+        }
+        // the games wait for the other side rather than falling behind, so the time spent waiting isn't made up
+        if last_frame.elapsed() >= frame_time {
+            last_frame = Instant::now();
+        }
+
+        if session.versus.round != round {
+            round = session.versus.round;
+            for ai in ai.iter_mut().flatten() {
+                ai.reset();
+            }
+        }
+        if session.finished {
+            print_match_result(&session.versus);
+            return
+        }
+        if let Some(e) = error {
+            println!("The match ended early: {}", e);
+            show_result(canvas, assets, event_pump, controls, &session.versus, &ai, &["The match ended early".to_string(), e]);
+            return
+        }
+
+        draw_versus(canvas, assets, &session.versus, &ai);
+        let (window_width, _) = versus_window_size(&session.versus.settings);
+        let latency = session.latency.map_or("-".to_string(), |latency| format!("{} ms", latency.as_millis()));
+        assets.text.draw(canvas, &format!("You P{}  Ping {}", local + 1, latency),
+            (window_width as i32 / 2, VERSUS_HUD_HEIGHT as i32 + 5), TEXT_HEIGHT, Align::Centre);
+        if let Some(result) = session.result {
+            draw_lines(canvas, assets, &session.versus.settings, &result_lines(&session.versus, result));
+        }
+        canvas.present();
+
+        if let Some(remaining) = frame_time.checked_sub(last_frame.elapsed()) {
+            sleep(remaining);
         }
-        Err(_) => false
     }
 }
 
-pub fn main() {
-    let sdl_context = sdl2::init().expect("SDL initialization failed.");
-    let video_subsystem = sdl_context.video().expect("SDL video subsystem initialisation failed.");
-    sdl2::image::init(INIT_PNG).expect("Failed to initialise the image context.");
-    let ttf_context = sdl2::ttf::init().expect("Failed to initialise SDL ttf.");
+// where the board of this player in a battle royale is drawn, under the strip their status is shown in
+fn royale_layout(settings: &BoardSettings) -> Layout {
+    Layout::new(settings, METER_WIDTH as i32, ROYALE_HUD_HEIGHT as i32, TETRIS_HEIGHT as u32)
+}
 
-    let mut tetris = Tetris::new();
-    let mut timer = SystemTime::now();
-    let mut event_pump = sdl_context.event_pump().expect("Failed to get SDL event pump");
+// the size of the cells of the others' boards, as big as they can be for all of them to fit beside this player's
+fn mini_cell(settings: &BoardSettings, num_players: usize) -> u32 {
+    let rows = (num_players - 1).div_ceil(MINI_COLUMNS) as u32;
+    let (_, height) = board_size(settings);
+    ((height / rows).saturating_sub(MINI_LABEL_HEIGHT + MINI_GAP) / settings.height as u32).clamp(2, MAX_MINI_CELL)
+}
 
-    let window = video_subsystem.window("Tetris.rs", WINDOW_WIDTH, WINDOW_HEIGHT)
-        .position_centered() 
-        .build() 
-        .expect("Failed to create window.");
+fn mini_width(settings: &BoardSettings, num_players: usize) -> u32 {
+    (mini_cell(settings, num_players) * settings.width as u32).max(MINI_MIN_WIDTH)
+}
 
-    let mut canvas = window.into_canvas() 
-        .target_texture() 
-        .present_vsync() 
-        .build() 
-        .expect("Failed to create canvas.");
+// the shown lines of every other player's board, in the order of the players, in columns beside this player's
+fn mini_boards(settings: &BoardSettings, num_players: usize, local: usize) -> Vec<(usize, Rect)> {
+    let cell = mini_cell(settings, num_players);
+    let (width, _) = board_size(settings);
+    let slot_width = mini_width(settings, num_players) + MINI_GAP;
+    let slot_height = MINI_LABEL_HEIGHT + cell * settings.height as u32 + MINI_GAP;
+    (0..num_players).filter(|player| *player != local).enumerate().map(|(slot, player)| {
+        let x = METER_WIDTH + width + MINI_GAP + (slot % MINI_COLUMNS) as u32 * slot_width;
+        let y = ROYALE_HUD_HEIGHT + (slot / MINI_COLUMNS) as u32 * slot_height + MINI_LABEL_HEIGHT;
+        (player, Rect::new(x as i32, y as i32, cell * settings.width as u32, cell * settings.height as u32))
+    }).collect()
+}
 
-    let texture_creator: TextureCreator<_> = canvas.texture_creator();
+fn royale_window_size(settings: &BoardSettings, num_players: usize) -> (u32, u32) {
+    let (width, height) = board_size(settings);
+    (METER_WIDTH + width + MINI_GAP + MINI_COLUMNS as u32 * (mini_width(settings, num_players) + MINI_GAP),
+        ROYALE_HUD_HEIGHT + height)
+}
 
-    let grid = create_texture_rect(&mut canvas, &texture_creator, 0, 0, 0, GRID_WIDTH, GRID_HEIGHT)
-        .expect("Failed to create texture.");
+fn draw_mini_board(canvas: &mut Canvas<Window>, assets: &Assets, board: &[Vec<u8>], rect: Rect) {
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.fill_rect(rect).expect("Failed to draw the board.");
+    let cell = rect.height() / board.len().max(1) as u32;
+    for (line_num, line) in board.iter().enumerate() {
+        for (case_num, case) in line.iter().enumerate() {
+            if let Some(texture) = assets.textures.get((*case as usize).wrapping_sub(1)) {
+                let cell_rect = Rect::new(rect.x() + (case_num as u32 * cell) as i32, rect.y() + (line_num as u32 * cell) as i32,
+                    cell, cell);
+                canvas.copy(texture, None, cell_rect).expect("Failed to copy texture to window.");
+            }
+        }
+    }
+}
 
-    let textures = [load_asset(&texture_creator, "assets/1.png"), load_asset(&texture_creator, "assets/2.png"), 
-    load_asset(&texture_creator, "assets/3.png"), load_asset(&texture_creator, "assets/4.png"), 
-    load_asset(&texture_creator, "assets/5.png"), load_asset(&texture_creator, "assets/6.png"), 
-    load_asset(&texture_creator, "assets/7.png")];
+// this player's board with their status over it, and everyone else's board small beside it, outlined in red for the
+// one this player is targeting and in orange for the ones targeting this player
+fn draw_royale(canvas: &mut Canvas<Window>, assets: &Assets, client: &RoyaleClient, ai: bool) {
+    clear(canvas, Color::RGB(40, 40, 40));
+    let settings = client.game.settings;
+    let layout = royale_layout(&settings);
+    draw_game(canvas, assets, &client.game, &layout);
+    draw_garbage_meter(canvas, &client.game, &layout);
 
-    let mut font = ttf_context.load_font("assets/Inconsolata-Regular.ttf", 128).expect("Failed to load image.");
-    font.set_style(sdl2::ttf::STYLE_BOLD);
+    let me = &client.players[client.local];
+    let left = client.players.iter().filter(|player| player.alive).count();
+    let status = format!("P{}{}  Badges: {} (+{}%)  KOs: {}  Left: {}/{}", client.local + 1, if ai {" AI"} else {""},
+        me.badges, badge_bonus(me.badges) * 25, me.kos, left, client.players.len());
+    assets.text.draw(canvas, &status, (5, 5), TEXT_HEIGHT, Align::Left);
+    let mut targeting = TARGETINGS.iter().enumerate()
+        .map(|(key, targeting)| format!("{}{}{}", key + 1, if *targeting == me.targeting {"*"} else {" "}, targeting.name()))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if client.multiplier != 100 {
+        targeting += &format!("  Attacks {}%", client.multiplier);
+    }
+    assets.text.draw(canvas, &targeting, (5, 40), TEXT_HEIGHT, Align::Left);
+
+    let attackers = client.attackers();
+    for (player, rect) in mini_boards(&settings, client.players.len(), client.local) {
+        let outline = if me.target == Some(player) {
+            Some(Color::RGB(230, 30, 30))
+        } else if attackers.contains(&player) {
+            Some(Color::RGB(240, 150, 20))
+        } else {
+            None
+        };
+        if let Some(colour) = outline {
+            canvas.set_draw_color(colour);
+            canvas.fill_rect(Rect::new(rect.x() - 3, rect.y() - 3, rect.width() + 6, rect.height() + 6))
+                .expect("Failed to draw the outline.");
+        }
+        draw_mini_board(canvas, assets, &client.boards[player], rect);
+
+        let other = &client.players[player];
+        let label = format!("P{}", player + 1);
+        assets.text.draw(canvas, &label, (rect.x(), rect.y() - MINI_LABEL_HEIGHT as i32), TEXT_HEIGHT, Align::Left);
+        // a yellow square for every quarter the player's badges add to their attacks, after the label
+        let (label_width, _) = assets.text.measure(&label, TEXT_HEIGHT);
+        canvas.set_draw_color(Color::RGB(240, 210, 40));
+        for badge in 0..badge_bonus(other.badges) as i32 {
+            canvas.fill_rect(Rect::new(rect.x() + label_width as i32 + 6 + badge * 12, rect.y() - 20, 8, 8))
+                .expect("Failed to draw the badges.");
+        }
+        if let Some(place) = other.place {
+            let centre = (rect.x() + rect.width() as i32 / 2, rect.y() + rect.height() as i32 / 2 + 40);
+            draw_lines_at(canvas, assets, centre, &[format!("#{}", place)]);
+        }
+    }
+}
+
+// who won the battle royale and how this player did
+fn royale_result_lines(client: &RoyaleClient) -> Vec<String> {
+    let me = &client.players[client.local];
+    let mut lines = Vec::new();
+    match client.players.iter().position(|player| player.place == Some(1)) {
+        Some(winner) if winner == client.local => lines.push("You win!".to_string()),
+        Some(winner) => lines.push(format!("Player {} wins", winner + 1)),
+        None => {}
+    }
+    if let Some(place) = me.place {
+        lines.push(format!("You finished #{} with {} KOs", place, me.kos));
+    }
+    lines
+}
+
+// shows the boards as they ended with the lines over them until the player leaves
+fn show_royale_result(canvas: &mut Canvas<Window>, assets: &Assets, event_pump: &mut sdl2::EventPump, client: &RoyaleClient,
+    lines: &[String]) {
+    let mut frames = 0;
+    let (window_width, window_height) = royale_window_size(&client.game.settings, client.players.len());
+    loop {
+        for event in event_pump.poll_iter() {
+            toggle_fullscreen(canvas, &event);
+            match event {
+                Event::Quit {..} | Event::KeyDown {keycode: Some(Keycode::Escape), ..} => return,
+                Event::KeyDown {keycode: Some(Keycode::Return), ..} | Event::KeyDown {keycode: Some(Keycode::Space), ..} |
+                Event::ControllerButtonDown {button: Button::Start, ..} if frames >= RESULT_DELAY => return,
+                _ => {}
+            }
+        }
+
+        draw_royale(canvas, assets, client, false);
+        draw_lines_at(canvas, assets, (window_width as i32 / 2, window_height as i32 / 2), lines);
+        canvas.present();
+
+        frames += 1;
+        sleep(Duration::new(0, WAIT_TIME));
+    }
+}
+
+// a battle royale run by a server, this player's board played with the single player controls and the keys 1 to 4
+// picking who their attacks go to
+fn play_royale(canvas: &mut Canvas<Window>, assets: &Assets, event_pump: &mut sdl2::EventPump, controls: &mut Controls,
+    mut client: RoyaleClient, options: &GameOptions, spectators: &mut Option<SpectatorServer>) {
+    let bot_command = options.bot_command.as_deref();
+    let layout = royale_layout(&client.game.settings);
+    letterbox(canvas, Some(royale_window_size(&client.game.settings, client.players.len())));
+    let mut ai: Option<BotPlayer> = None;
+    let mut actions = Vec::new();
+    let frame_time = Duration::new(0, WAIT_TIME);
+    let mut last_frame = Instant::now();
 
-    let mut paused = false;
-    
     loop {
-        if is_time_over(&tetris, &timer) && !paused {
-            let mut make_permanent = false;
-            if let Some(ref mut piece) = tetris.current_piece {
-                let x = piece.x;
-                let y = piece.y + 1;
-                make_permanent = !piece.change_position(&tetris.game_map, x, y);
+        let mut error = None;
+        for event in event_pump.poll_iter() {
+            toggle_fullscreen(canvas, &event);
+            let targeting = match event {
+                Event::KeyDown {keycode: Some(keycode), ..} => TARGETING_KEYS.iter().position(|key| *key == keycode),
+                _ => None
+            };
+            if let Some(targeting) = targeting {
+                error = client.set_targeting(TARGETINGS[targeting]).err().or(error);
+                continue
             }
-            if make_permanent {
-                tetris.make_permanent();
+            match controls.translate(&event) {
+                Some(Action::Quit) => return,
+                Some(Action::ToggleAi) => ai = match ai {
+                    Some(_) => None,
+                    None => Some(BotPlayer::new(create_bot(bot_command)))
+                },
+                // the others can't be kept waiting
+                Some(Action::Pause) | None => {},
+                Some(action) => actions.push(action),
             }
-            timer = SystemTime::now();
         }
 
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
-        canvas.clear();
-        canvas.copy(&grid, 
-                None, 
-                Rect::new(GRID_ORIGIN_X, GRID_ORIGIN_Y, GRID_WIDTH, GRID_HEIGHT))
-                    .expect("Failed to copy texture to window.");
+        error = error.or_else(|| client.poll().err());
+        // the game only starts once everyone has joined
+        if !client.started {
+            last_frame = Instant::now();
+        }
+        while error.is_none() && last_frame.elapsed() >= frame_time {
+            last_frame += frame_time;
+            if let Some(ref mut ai) = ai {
+                actions = ai.next_actions(&client.game);
+                print_bot_messages(ai);
+            }
+            error = client.step(&actions).err();
+            actions.clear();
+        }
+        if let Some(ref mut spectators) = *spectators {
+            spectators.broadcast(slice::from_ref(&client.game));
+        }
+
+        if let Some(e) = error {
+            println!("The match ended early: {}", e);
+            show_royale_result(canvas, assets, event_pump, &client, &["The match ended early".to_string(), e]);
+            return
+        }
+        if client.finished {
+            let lines = royale_result_lines(&client);
+            println!("{}", lines.join(", "));
+            show_royale_result(canvas, assets, event_pump, &client, &lines);
+            return
+        }
+
+        draw_royale(canvas, assets, &client, ai.is_some());
+        let centre = (layout.x + layout.width as i32 / 2, layout.y + layout.height as i32 / 2);
+        if !client.started {
+            draw_lines_at(canvas, assets, centre, &[format!("Waiting for players {}/{}", client.joined, client.players.len())]);
+        } else if client.game.game_over {
+            let place = client.players[client.local].place.map_or("Knocked out".to_string(), |place| format!("#{}", place));
+            draw_lines_at(canvas, assets, centre, &[place]);
+        }
+        canvas.present();
 
-        if tetris.current_piece.is_none() {
-            let current_piece = tetris.create_new_tetrimino();
-            if !current_piece.test_current_position(&tetris.game_map) {
-                print_game_info(&tetris);
-                break
+        if let Some(remaining) = frame_time.checked_sub(last_frame.elapsed()) {
+            sleep(remaining);
+        }
+    }
+}
+
+// watches the games another window is playing, as they are streamed to it
+fn play_spectate(mut spectator: Spectator, canvas: &mut Canvas<Window>, assets: &Assets, event_pump: &mut sdl2::EventPump) {
+    let mut letterboxed = None;
+    loop {
+        for event in event_pump.poll_iter() {
+            toggle_fullscreen(canvas, &event);
+            match event {
+                Event::Quit {..} | Event::KeyDown {keycode: Some(Keycode::Escape), ..} => return,
+                _ => {}
             }
-            tetris.current_piece = Some(current_piece);
+        }
+        if let Err(e) = spectator.poll() {
+            println!("Stopped watching: {}", e);
+            return
         }
 
-        let mut quit = false;
-        
-        if !handle_events(&mut tetris, &mut quit, &mut timer, &mut event_pump, &mut paused) {
-            if let Some(ref mut piece) = tetris.current_piece {
-                for (line_num, line) in piece.states[piece.current_state as usize].iter().enumerate() {
-                    for (case_num, case) in line.iter().enumerate() {
-                        if *case == 0 {
-                            continue
-                        }
-                    canvas.copy(&textures[*case as usize - 1],
-                                None, 
-                                Rect::new(GRID_ORIGIN_X + (piece.x + case_num as isize) as i32 * TETRIS_HEIGHT as i32, 
-                                        GRID_ORIGIN_Y + (piece.y + line_num) as i32 * TETRIS_HEIGHT as i32, 
-                                        TETRIS_HEIGHT as u32, TETRIS_HEIGHT as u32))
-                        .expect("Failed to copy texture to window");
-                    }
+        let games = &spectator.games;
+        if let Some(game) = games.first() {
+            // one game on its own, more side by side
+            let scaled = if games.len() == 1 { None } else { Some(versus_window_size(&game.settings)) };
+            if letterboxed != scaled {
+                letterbox(canvas, scaled);
+                letterboxed = scaled;
+            }
+            if games.len() == 1 {
+                let layout = game_layout(&game.settings, canvas.output_size().expect("Failed to get the window size."));
+                draw_single(canvas, assets, game, &layout, &[]);
+            } else {
+                clear(canvas, Color::RGB(40, 40, 40));
+                for (player, (game, layout)) in games.iter().zip(versus_layouts(&game.settings)).enumerate() {
+                    draw_game(canvas, assets, game, &layout);
+                    draw_garbage_meter(canvas, game, &layout);
+                    assets.text.draw(canvas, &format!("P{}  Score: {}", player + 1, game.score), (layout.x, 5), TEXT_HEIGHT,
+                        Align::Left);
                 }
             }
+        } else {
+            clear(canvas, Color::RGB(0, 0, 0));
+            assets.text.draw(canvas, "Waiting for the game", (20, 20), TEXT_HEIGHT, Align::Left);
         }
+        canvas.present();
 
-        for (line_num, line) in tetris.game_map.iter().enumerate() {
-            for (case_num, case) in line.iter().enumerate() {
-                if *case == 0 {
-                    continue 
+        sleep(Duration::new(0, WAIT_TIME));
+    }
+}
+
+// Space pauses, Up/Down change the speed, Left/Right seek, ','/'.' step a frame back or forward and Home restarts
+fn play_replay(replay: Replay, canvas: &mut Canvas<Window>, assets: &Assets, event_pump: &mut sdl2::EventPump) {
+    println!("Replay of {}: score {}, {} lines, level {}", replay.player, replay.score, replay.lines, replay.level);
+    let mut player = ReplayPlayer::new(replay);
+    let mut playing = true;
+    let mut speed = 2;
+    // fraction of a frame the playback has moved on since the last step
+    let mut progress = 0.0;
+    let mut last_frame = Instant::now();
+
+    loop {
+        for event in event_pump.poll_iter() {
+            toggle_fullscreen(canvas, &event);
+            let frame = player.frame();
+            match event {
+                Event::Quit {..} | Event::KeyDown {keycode: Some(Keycode::Escape), ..} => return,
+                Event::KeyDown {keycode: Some(Keycode::Space), ..} => playing = !playing,
+                Event::KeyDown {keycode: Some(Keycode::Up), ..} => speed = (speed + 1).min(REPLAY_SPEEDS.len() - 1),
+                Event::KeyDown {keycode: Some(Keycode::Down), ..} => speed = speed.saturating_sub(1),
+                Event::KeyDown {keycode: Some(Keycode::Right), ..} => player.seek(frame + REPLAY_SEEK_FRAMES),
+                Event::KeyDown {keycode: Some(Keycode::Left), ..} => player.seek(frame.saturating_sub(REPLAY_SEEK_FRAMES)),
+                Event::KeyDown {keycode: Some(Keycode::Home), ..} => player.seek(0),
+                Event::KeyDown {keycode: Some(Keycode::Period), ..} => {
+                    playing = false;
+                    player.step();
+                }
+                Event::KeyDown {keycode: Some(Keycode::Comma), ..} => {
+                    playing = false;
+                    player.seek(frame.saturating_sub(1));
+                }
+                _ => {}
+            }
+        }
+
+        let elapsed = last_frame.elapsed();
+        last_frame = Instant::now();
+        if playing {
+            progress += elapsed.as_secs_f64() * FPS as f64 * REPLAY_SPEEDS[speed];
+            while progress >= 1.0 {
+                progress -= 1.0;
+                if !player.step() {
+                    playing = false;
+                    break
                 }
-                canvas.copy(&textures[*case as usize - 1], 
-                            None, 
-                            Rect::new(GRID_ORIGIN_X + case_num as i32 * TETRIS_HEIGHT as i32, GRID_ORIGIN_Y + line_num as i32 * TETRIS_HEIGHT as i32,
-                                    TETRIS_HEIGHT as u32, TETRIS_HEIGHT as u32))
-                    .expect("Failed to copy texture to window.");
             }
+        } else {
+            progress = 0.0;
         }
 
-        display_score(&tetris, &mut canvas, &texture_creator, &font, WINDOW_WIDTH as i32 - 110);
+        let layout = game_layout(&player.tetris.settings, canvas.output_size().expect("Failed to get the window size."));
+        draw_single(canvas, assets, &player.tetris, &layout, &[]);
+
+        // the playback in the bottom right corner of the board and the inputs just made under it, as wide as the board
+        let frame = player.frame();
+        let status = format!("{}/{} {}x{}", frame, player.replay.frames, REPLAY_SPEEDS[speed],
+            if player.is_finished() {" end"} else if playing {""} else {" paused"});
+        let board = layout.board;
+        let text_height = board.cell * 3 / 4;
+        let bottom = board.y + board.height as i32;
+        assets.text.draw(canvas, &status, (board.x + board.width as i32, bottom - 2 * board.cell as i32), text_height,
+            Align::Right);
+        let inputs = (frame.saturating_sub(INPUT_DISPLAY_FRAMES)..frame)
+            .flat_map(|f| player.actions_at(f))
+            .map(|action| action.name())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let inputs = assets.text.fit(&inputs, text_height, board.width);
+        assets.text.draw(canvas, &inputs, (board.x, bottom - board.cell as i32), text_height, Align::Left);
 
         canvas.present();
 
-        if quit {
-            print_game_info(&tetris);
-            break
+        sleep(Duration::new(0, WAIT_TIME));
+    }
+}
+
+// a screen of choices: its title, lines of text under it and the items that can be picked, which are only taken
+// once it has been shown for the frames it waits
+struct Menu {
+    title: String,
+    text: Vec<String>,
+    items: Vec<String>,
+    wait: u32,
+}
+
+impl Menu {
+    fn new(title: &str, text: Vec<String>, items: &[&str]) -> Menu {
+        Menu {title: title.to_string(), text, items: items.iter().map(|item| item.to_string()).collect(), wait: 0}
+    }
+}
+
+// what was done on a menu: an item picked, the value of one changed to the left or right, or the menu left
+enum MenuChoice {
+    Pick(usize),
+    Change(usize, i32),
+    Back,
+}
+
+// the menu scaled to the window, centred in it with the selected item on a lighter box
+fn draw_menu(canvas: &mut Canvas<Window>, assets: &Assets, menu: &Menu, selected: Option<usize>) {
+    clear(canvas, Color::RGB(0, 0, 0));
+    let (width, height) = canvas.output_size().expect("Failed to get the window size.");
+    let lines = MENU_TITLE_LINES + menu.text.len() as u32 + 1 + menu.items.len() as u32;
+    let line_height = (height / (lines + 2)).clamp(1, MENU_LINE_HEIGHT);
+    // the text is made smaller still if its widest line wouldn't fit across the window
+    let mut text_height = line_height * 3 / 4;
+    let widest = menu.text.iter().chain(&menu.items).map(|line| assets.text.measure(line, text_height).0).max().unwrap_or(0);
+    if widest > width * 9 / 10 {
+        text_height = text_height * width * 9 / 10 / widest;
+    }
+
+    let centre = width as i32 / 2;
+    let mut y = (height.saturating_sub(lines * line_height) / 2) as i32;
+    assets.text.draw(canvas, &menu.title, (centre, y), line_height * 3 / 2, Align::Centre);
+    y += (MENU_TITLE_LINES * line_height) as i32;
+    for line in &menu.text {
+        assets.text.draw(canvas, line, (centre, y), text_height, Align::Centre);
+        y += line_height as i32;
+    }
+    y += line_height as i32;
+    for (item_num, item) in menu.items.iter().enumerate() {
+        if selected == Some(item_num) {
+            let (item_width, _) = assets.text.measure(item, text_height);
+            let margin = line_height / 2;
+            canvas.set_draw_color(Color::RGB(60, 60, 60));
+            canvas.fill_rect(Rect::new(centre - (item_width / 2 + margin) as i32, y - (line_height - text_height) as i32 / 2,
+                item_width + 2 * margin, line_height)).expect("Failed to draw the menu.");
+        }
+        assets.text.draw(canvas, item, (centre, y), text_height, Align::Centre);
+        y += line_height as i32;
+    }
+}
+
+// shows the menu until something is done on it, with the arrow keys or the d-pad moving between its items and
+// changing their values, Return, Space, A or Start picking one and Esc, B or Back leaving; closing the window leaves
+// it too
+fn run_menu(canvas: &mut Canvas<Window>, assets: &Assets, event_pump: &mut sdl2::EventPump, controls: &mut Controls,
+    menu: &Menu, selected: &mut usize) -> MenuChoice {
+    letterbox(canvas, None);
+    let num_items = menu.items.len();
+    *selected = (*selected).min(num_items - 1);
+    let mut frames = 0;
+    loop {
+        for event in event_pump.poll_iter() {
+            toggle_fullscreen(canvas, &event);
+            controls.translate(&event);
+            if controls.closed {
+                return MenuChoice::Back
+            }
+            match event {
+                Event::KeyDown {keycode: Some(Keycode::Up), ..} | Event::ControllerButtonDown {button: Button::DPadUp, ..} =>
+                    *selected = (*selected + num_items - 1) % num_items,
+                Event::KeyDown {keycode: Some(Keycode::Down), ..} | Event::ControllerButtonDown {button: Button::DPadDown, ..} =>
+                    *selected = (*selected + 1) % num_items,
+                Event::KeyDown {keycode: Some(Keycode::Left), ..} | Event::ControllerButtonDown {button: Button::DPadLeft, ..} =>
+                    return MenuChoice::Change(*selected, -1),
+                Event::KeyDown {keycode: Some(Keycode::Right), ..} |
+                Event::ControllerButtonDown {button: Button::DPadRight, ..} => return MenuChoice::Change(*selected, 1),
+                Event::KeyDown {keycode: Some(Keycode::Return), ..} | Event::KeyDown {keycode: Some(Keycode::Space), ..} |
+                Event::ControllerButtonDown {button: Button::A, ..} | Event::ControllerButtonDown {button: Button::Start, ..}
+                    if frames >= menu.wait => return MenuChoice::Pick(*selected),
+                Event::KeyDown {keycode: Some(Keycode::Escape), ..} | Event::ControllerButtonDown {button: Button::B, ..} |
+                Event::ControllerButtonDown {button: Button::Back, ..} => return MenuChoice::Back,
+                _ => {}
+            }
         }
 
+        draw_menu(canvas, assets, menu, Some(*selected));
+        canvas.present();
+
+        frames += 1;
         sleep(Duration::new(0, WAIT_TIME));
     }
-}
\ No newline at end of file
+}
+
+// the title screen, which the other screens are reached from and come back to
+fn main_menu(canvas: &mut Canvas<Window>, assets: &Assets, event_pump: &mut sdl2::EventPump, controls: &mut Controls,
+    options: &mut GameOptions, spectators: &mut Option<SpectatorServer>) {
+    let menu = Menu::new("Tetris.rs", Vec::new(), &["Play", "Options", "Leaderboard", "Quit"]);
+    let mut selected = 0;
+    while !controls.closed {
+        match run_menu(canvas, assets, event_pump, controls, &menu, &mut selected) {
+            MenuChoice::Pick(0) => select_mode(canvas, assets, event_pump, controls, options, spectators),
+            MenuChoice::Pick(1) => options_menu(canvas, assets, event_pump, controls, options),
+            MenuChoice::Pick(2) => show_leaderboard(canvas, assets, event_pump, controls),
+            MenuChoice::Change(..) => {}
+            MenuChoice::Pick(_) | MenuChoice::Back => return
+        }
+    }
+}
+
+fn select_mode(canvas: &mut Canvas<Window>, assets: &Assets, event_pump: &mut sdl2::EventPump, controls: &mut Controls,
+    options: &GameOptions, spectators: &mut Option<SpectatorServer>) {
+    let menu = Menu::new("Play", Vec::new(), &["Marathon", "Versus", "Back"]);
+    match run_menu(canvas, assets, event_pump, controls, &menu, &mut 0) {
+        MenuChoice::Pick(0) => play_marathon(canvas, assets, event_pump, controls, options, spectators),
+        MenuChoice::Pick(1) => {
+            let versus = Versus::new(random_seed(), options.rounds, options.settings, options.pieces.clone());
+            play_versus(canvas, assets, event_pump, controls, versus, options, spectators);
+        }
+        _ => {}
+    }
+}
+
+// the board, lock out rule and rounds of the games started from the menu
+fn options_menu(canvas: &mut Canvas<Window>, assets: &Assets, event_pump: &mut sdl2::EventPump, controls: &mut Controls,
+    options: &mut GameOptions) {
+    let mut selected = 0;
+    loop {
+        let settings = &mut options.settings;
+        // the board is named without its lock out rule, which has an item of its own
+        let board = BoardSettings {partial_lock_out: false, ..*settings};
+        let board_item = format!("Board: {}", board.name());
+        let lock_out_item = format!("Lock out: {}", if settings.partial_lock_out {"partial"} else {"full"});
+        let rounds_item = format!("Versus: best of {}", options.rounds);
+        let menu = Menu::new("Options", Vec::new(), &[&board_item, &lock_out_item, &rounds_item, "Back"]);
+        match run_menu(canvas, assets, event_pump, controls, &menu, &mut selected) {
+            MenuChoice::Pick(0) | MenuChoice::Change(0, _) => {
+                // a board given on the command line is swapped for the classic one
+                let next = if board == BoardSettings::CLASSIC {BoardSettings::GUIDELINE} else {BoardSettings::CLASSIC};
+                *settings = BoardSettings {partial_lock_out: settings.partial_lock_out, ..next};
+            }
+            MenuChoice::Pick(1) | MenuChoice::Change(1, _) => settings.partial_lock_out = !settings.partial_lock_out,
+            MenuChoice::Pick(2) => options.rounds = if options.rounds >= MAX_ROUNDS {1} else {options.rounds + 2},
+            MenuChoice::Change(2, change) => options.rounds = (options.rounds as i32 + 2 * change).clamp(1, MAX_ROUNDS as i32) as u32,
+            MenuChoice::Change(..) => {}
+            MenuChoice::Pick(_) | MenuChoice::Back => return
+        }
+    }
+}
+
+// a line of the leaderboard, in columns that line up in the monospaced font
+fn leaderboard_line(place: &str, name: &str, score: &str, lines: &str, level: &str) -> String {
+    format!("{:>2} {:<width$} {:>8} {:>5} {:>5}", place, name, score, lines, level, width = MAX_NAME_LENGTH)
+}
+
+fn show_leaderboard(canvas: &mut Canvas<Window>, assets: &Assets, event_pump: &mut sdl2::EventPump, controls: &mut Controls) {
+    let entries = load_leaderboard();
+    let text = if entries.is_empty() {
+        vec!["No games yet".to_string()]
+    } else {
+        let mut text = vec![leaderboard_line("", "Name", "Score", "Lines", "Level")];
+        text.extend(entries.iter().enumerate().map(|(place, entry)| {
+            leaderboard_line(&(place + 1).to_string(), &entry.name, &entry.score.to_string(), &entry.lines.to_string(),
+                &entry.level.to_string())
+        }));
+        text
+    };
+    run_menu(canvas, assets, event_pump, controls, &Menu::new("Leaderboard", text, &["Back"]), &mut 0);
+}
+
+// games on their own one after the other, for as long as the player wants to play again once one is over
+fn play_marathon(canvas: &mut Canvas<Window>, assets: &Assets, event_pump: &mut sdl2::EventPump, controls: &mut Controls,
+    options: &GameOptions, spectators: &mut Option<SpectatorServer>) {
+    while let Some((tetris, finesse)) = play_game(canvas, assets, event_pump, controls, options, spectators) {
+        if !game_over(canvas, assets, event_pump, controls, &tetris, &finesse) {
+            return
+        }
+    }
+}
+
+// asks for the name a game goes on the leaderboard under, typed over the player's own, returning None if they would
+// rather leave it off or the window is closed
+fn enter_name(canvas: &mut Canvas<Window>, assets: &Assets, event_pump: &mut sdl2::EventPump, controls: &mut Controls,
+    text: &[String]) -> Option<String> {
+    let text_input = canvas.window().subsystem().text_input();
+    text_input.start();
+    let mut name = player_name().chars().take(MAX_NAME_LENGTH).collect::<String>();
+    let entered = 'entry: loop {
+        for event in event_pump.poll_iter() {
+            toggle_fullscreen(canvas, &event);
+            controls.translate(&event);
+            if controls.closed {
+                break 'entry None
+            }
+            match event {
+                Event::TextInput {ref text, ..} => {
+                    for c in text.chars().filter(|c| !c.is_control()) {
+                        if name.chars().count() < MAX_NAME_LENGTH {
+                            name.push(c);
+                        }
+                    }
+                }
+                Event::KeyDown {keycode: Some(Keycode::Backspace), ..} => {
+                    name.pop();
+                }
+                Event::KeyDown {keycode: Some(Keycode::Return), ..} | Event::KeyDown {keycode: Some(Keycode::KpEnter), ..} |
+                Event::ControllerButtonDown {button: Button::Start, ..} => {
+                    // a name left empty is the player's own
+                    let name = name.trim();
+                    break 'entry Some(if name.is_empty() {player_name()} else {name.to_string()})
+                }
+                Event::KeyDown {keycode: Some(Keycode::Escape), ..} | Event::ControllerButtonDown {button: Button::Back, ..} =>
+                    break 'entry None,
+                _ => {}
+            }
+        }
+
+        let mut menu = Menu::new("Game over", text.to_vec(), &[]);
+        menu.text.push("A high score! Enter your name".to_string());
+        menu.items.push(format!("{}_", name));
+        draw_menu(canvas, assets, &menu, Some(0));
+        canvas.present();
+
+        sleep(Duration::new(0, WAIT_TIME));
+    };
+    text_input.stop();
+    entered
+}
+
+// how the game went, putting it on the leaderboard under the name the player enters if it makes it, then asking
+// whether to play again; returns false to go back to the title screen
+fn game_over(canvas: &mut Canvas<Window>, assets: &Assets, event_pump: &mut sdl2::EventPump, controls: &mut Controls,
+    tetris: &Tetris, finesse: &FinesseTracker) -> bool {
+    let (best_score, best_lines) = record_highscores(tetris);
+    let best = |value: String, best: bool| if best {format!("{} (best)", value)} else {value};
+    let mut text = vec![
+        hud_line("Score", &best(tetris.score.to_string(), best_score)),
+        hud_line("Lines", &best(tetris.num_lines.to_string(), best_lines)),
+        hud_line("Level", &tetris.current_level.to_string()),
+        hud_line("Time", &Stat::Time.value(tetris)),
+        hud_line("Faults", &finesse.faults.to_string()),
+    ];
+
+    let mut entries = load_leaderboard();
+    if leaderboard_place(&entries, tetris.score).is_some() {
+        if let Some(name) = enter_name(canvas, assets, event_pump, controls, &text) {
+            let entry = Entry {name, score: tetris.score, lines: tetris.num_lines, level: tetris.current_level};
+            if let Some(place) = add_to_leaderboard(&mut entries, entry) {
+                text.push(format!("#{} on the leaderboard", place + 1));
+                if !save_leaderboard(&entries) {
+                    text.push("Failed to save the leaderboard".to_string());
+                }
+            }
+        }
+        if controls.closed {
+            return false
+        }
+    }
+
+    // a drop meant for the game that just ended doesn't play the next one
+    let menu = Menu {wait: RESULT_DELAY, ..Menu::new("Game over", text, &["Play again", "Main menu"])};
+    matches!(run_menu(canvas, assets, event_pump, controls, &menu, &mut 0), MenuChoice::Pick(0))
+}
+
+// re-simulates a replay without opening a window, exiting with a non-zero status if it doesn't match its header
+fn verify(file_name: &str) {
+    let (report, status) = verify_file(file_name);
+    println!("{}", report);
+    if status != 0 {
+        process::exit(status);
+    }
+}
+
+// takes the value of the option out of the options, if it is there
+fn take_option(options: &mut Vec<String>, name: &str) -> Result<Option<String>, String> {
+    let index = match options.iter().position(|option| option == name) {
+        Some(index) => index,
+        None => return Ok(None)
+    };
+    let value = options.get(index + 1).ok_or(format!("{} needs a value", name))?.clone();
+    options.drain(index..index + 2);
+    Ok(Some(value))
+}
+
+// takes the options only a versus game has out of its options: the rounds it is the best of and the garbage delay
+fn take_versus_options(options: &mut Vec<String>) -> Result<(u32, u32), String> {
+    let rounds = match take_option(options, "--rounds")? {
+        Some(value) => value.parse::<u32>().ok().filter(|rounds| *rounds > 0)
+            .ok_or(format!("the number of rounds has to be at least 1, not {}", value))?,
+        None => DEFAULT_ROUNDS
+    };
+    let delay = match take_option(options, "--garbage-delay")? {
+        Some(value) => value.parse::<u32>().map_err(|_| format!("the garbage delay has to be a number of frames, not {}", value))?,
+        None => DEFAULT_GARBAGE_DELAY
+    };
+    Ok((rounds, delay))
+}
+
+// the options of a new game, each one followed by its value
+fn parse_game_options(args: &[String], options: &mut GameOptions) -> Result<(), String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or(format!("{} needs a value", arg))?;
+        match arg.as_str() {
+            "--board" => {
+                let partial_lock_out = options.settings.partial_lock_out;
+                options.settings = BoardSettings::parse(value).ok_or(format!("unknown board {}", value))?;
+                options.settings.partial_lock_out = partial_lock_out;
+            }
+            "--lock-out" => options.settings.partial_lock_out = match value.as_str() {
+                "full" => false,
+                "partial" => true,
+                _ => return Err(format!("unknown lock out rule {}", value))
+            },
+            "--pieces" => options.pieces = Arc::new(PieceSet::load(value)?),
+            "--bot" => options.bot_command = Some(value.clone()),
+            "--spectate" => options.spectate = Some(spectate_address(value)),
+            _ => return Err(format!("unknown option {}", arg))
+        }
+    }
+    Ok(())
+}
+
+// what the window opens on
+enum Screen {
+    Menu,
+    Versus(Versus),
+    Online(Box<Session>),
+    Royale(Box<RoyaleClient>),
+    Spectate(Spectator),
+    Replay(Replay),
+}
+
+// how a new game is set up
+struct GameOptions {
+    settings: BoardSettings,
+    pieces: Arc<PieceSet>,
+    bot_command: Option<String>,
+    // where spectators can connect to watch from
+    spectate: Option<String>,
+    // the rounds a versus game started from the menu is the best of
+    rounds: u32,
+}
+
+pub fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let mut options = GameOptions {settings: BoardSettings::default(), pieces: Arc::new(PieceSet::standard()),
+        bot_command: None, spectate: None, rounds: DEFAULT_ROUNDS};
+    let screen = match args.first().map(|arg| arg.as_str()) {
+        Some("verify") => {
+            match args.get(1) {
+                Some(file_name) => verify(file_name),
+                None => {
+                    println!("Usage: tetris verify <file>");
+                    process::exit(2);
+                }
+            }
+            return
+        }
+        Some("replay") => match args.get(1) {
+            Some(file_name) => match Replay::load(file_name) {
+                Ok(replay) => Screen::Replay(replay),
+                Err(e) => {
+                    println!("Failed to load {}: {}", file_name, e);
+                    return
+                }
+            },
+            None => {
+                println!("Usage: tetris replay <file>");
+                return
+            }
+        },
+        Some(command @ "versus") | Some(command @ "host") => {
+            let mut args = args[1..].to_vec();
+            let result = take_option(&mut args, "--port").and_then(|port| {
+                let port = match port {
+                    Some(port) if command == "host" => port.parse::<u16>().map_err(|_| format!("{} isn't a port", port))?,
+                    Some(_) => return Err("only a hosted game has a port".to_string()),
+                    None => DEFAULT_PORT
+                };
+                let (rounds, garbage_delay) = take_versus_options(&mut args)?;
+                parse_game_options(&args, &mut options)?;
+                Ok((port, rounds, garbage_delay))
+            });
+            let (port, rounds, garbage_delay) = result.unwrap_or_else(|e| {
+                println!("{}\n{}", e, USAGE);
+                process::exit(2);
+            });
+            if command == "versus" {
+                let mut versus = Versus::new(random_seed(), rounds, options.settings, options.pieces.clone());
+                versus.garbage_delay = garbage_delay;
+                Screen::Versus(versus)
+            } else {
+                println!("Waiting for a player on port {}...", port);
+                match Session::host(port, random_seed(), rounds, garbage_delay, options.settings, options.pieces.clone()) {
+                    Ok(session) => Screen::Online(Box::new(session)),
+                    Err(e) => {
+                        println!("Failed to host the game: {}", e);
+                        process::exit(1);
+                    }
+                }
+            }
+        }
+        Some("join") => match args.get(1) {
+            Some(address) => {
+                // the port can be left out
+                let address = if address.contains(':') { address.clone() } else { format!("{}:{}", address, DEFAULT_PORT) };
+                if let Err(e) = parse_game_options(&args[2..], &mut options) {
+                    println!("{}\n{}", e, USAGE);
+                    process::exit(2);
+                }
+                match Session::join(&address) {
+                    Ok(session) => Screen::Online(Box::new(session)),
+                    Err(e) => {
+                        println!("Failed to join the game: {}", e);
+                        process::exit(1);
+                    }
+                }
+            }
+            None => {
+                println!("Usage: tetris join <address>[:<port>]");
+                process::exit(2);
+            }
+        },
+        Some("royale") => match args.get(1) {
+            Some(address) => {
+                let address = if address.contains(':') { address.clone() } else { format!("{}:{}", address, DEFAULT_ROYALE_PORT) };
+                let mut args = args[2..].to_vec();
+                let targeting = take_option(&mut args, "--targeting").and_then(|targeting| match targeting {
+                    Some(targeting) => Targeting::from_name(&targeting).ok_or(format!("unknown targeting {}", targeting)),
+                    None => Ok(Targeting::Random)
+                }).and_then(|targeting| parse_game_options(&args, &mut options).map(|_| targeting));
+                let targeting = targeting.unwrap_or_else(|e| {
+                    println!("{}\n{}", e, USAGE);
+                    process::exit(2);
+                });
+                match RoyaleClient::join(&address, targeting) {
+                    Ok(client) => Screen::Royale(Box::new(client)),
+                    Err(e) => {
+                        println!("Failed to join the battle royale: {}", e);
+                        process::exit(1);
+                    }
+                }
+            }
+            None => {
+                println!("Usage: tetris royale <address>[:<port>]");
+                process::exit(2);
+            }
+        },
+        Some("spectate") => match args.get(1) {
+            Some(address) => {
+                let address = if address.contains(':') { address.clone() } else { format!("{}:{}", address, DEFAULT_SPECTATE_PORT) };
+                match Spectator::connect(&address) {
+                    Ok(spectator) => Screen::Spectate(spectator),
+                    Err(e) => {
+                        println!("Failed to watch the game: {}", e);
+                        process::exit(1);
+                    }
+                }
+            }
+            None => {
+                println!("Usage: tetris spectate <address>[:<port>]");
+                process::exit(2);
+            }
+        },
+        _ => {
+            if let Err(e) = parse_game_options(&args, &mut options) {
+                println!("{}\n{}", e, USAGE);
+                process::exit(2);
+            }
+            Screen::Menu
+        }
+    };
+
+    let sdl_context = sdl2::init().expect("SDL initialization failed.");
+    let video_subsystem = sdl_context.video().expect("SDL video subsystem initialisation failed.");
+    sdl2::image::init(INIT_PNG).expect("Failed to initialise the image context.");
+    let ttf_context = sdl2::ttf::init().expect("Failed to initialise SDL ttf.");
+    let controller_subsystem = sdl_context.game_controller().expect("SDL game controller subsystem initialisation failed.");
+
+    let mut event_pump = sdl_context.event_pump().expect("Failed to get SDL event pump");
+    let mut controls = Controls::new(controller_subsystem, Bindings::load(), Bindings::load_versus());
+
+    let mut spectators = match options.spectate {
+        Some(ref address) => match SpectatorServer::start(address) {
+            Ok(server) => {
+                println!("The game can be watched with `tetris spectate {}`", address);
+                Some(server)
+            }
+            Err(e) => {
+                println!("Spectators can't watch: {}", e);
+                None
+            }
+        },
+        None => None
+    };
+
+    let (window_width, window_height) = match screen {
+        Screen::Menu => window_size(&options.settings),
+        Screen::Spectate(_) => window_size(&options.settings),
+        Screen::Versus(ref versus) => versus_window_size(&versus.settings),
+        Screen::Online(ref session) => versus_window_size(&session.versus.settings),
+        Screen::Royale(ref client) => royale_window_size(&client.game.settings, client.players.len()),
+        Screen::Replay(ref replay) => window_size(&replay.settings),
+    };
+    let mut window = video_subsystem.window("Tetris.rs", window_width, window_height)
+        .position_centered() 
+        .resizable()
+        .build() 
+        .expect("Failed to create window.");
+    if window.set_minimum_size(MIN_WINDOW_WIDTH, MIN_WINDOW_HEIGHT).is_err() {
+        println!("Failed to set the smallest size of the window.");
+    }
+    // typed text is only wanted while a name is entered
+    video_subsystem.text_input().stop();
+
+    let mut canvas = window.into_canvas() 
+        .target_texture() 
+        .present_vsync() 
+        .build() 
+        .expect("Failed to create canvas.");
+
+    let texture_creator: TextureCreator<_> = canvas.texture_creator();
+
+    let grid = create_texture_rect(&mut canvas, &texture_creator, 0, 0, 0, window_width, window_height)
+        .expect("Failed to create texture.");
+
+    let textures = vec![load_asset(&texture_creator, "assets/1.png"), load_asset(&texture_creator, "assets/2.png"), 
+    load_asset(&texture_creator, "assets/3.png"), load_asset(&texture_creator, "assets/4.png"), 
+    load_asset(&texture_creator, "assets/5.png"), load_asset(&texture_creator, "assets/6.png"), 
+    load_asset(&texture_creator, "assets/7.png"),
+    create_texture_rect(&mut canvas, &texture_creator, 110, 110, 110, TETRIS_HEIGHT as u32, TETRIS_HEIGHT as u32)
+        .expect("Failed to create texture.")];
+
+    let text = Text::load(&ttf_context, &texture_creator, "assets/Inconsolata-Regular.ttf").expect("Failed to load the font.");
+
+    let assets = Assets {grid, textures, text, hud: Hud::load()};
+
+    match screen {
+        Screen::Menu => main_menu(&mut canvas, &assets, &mut event_pump, &mut controls, &mut options, &mut spectators),
+        Screen::Versus(versus) => play_versus(&mut canvas, &assets, &mut event_pump, &mut controls, versus, &options,
+            &mut spectators),
+        Screen::Online(session) => play_online(&mut canvas, &assets, &mut event_pump, &mut controls, *session, &options,
+            &mut spectators),
+        Screen::Royale(client) => play_royale(&mut canvas, &assets, &mut event_pump, &mut controls, *client, &options,
+            &mut spectators),
+        Screen::Spectate(spectator) => play_spectate(spectator, &mut canvas, &assets, &mut event_pump),
+        Screen::Replay(replay) => play_replay(replay, &mut canvas, &assets, &mut event_pump),
+    }
+}
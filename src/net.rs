@@ -0,0 +1,492 @@
+// Versus over the network. Both sides simulate both games and only send each other their inputs: the games are
+// deterministic, so with the seed and rules agreed at the start and the same inputs on the same frames, the boards
+// and the garbage they send each other come out the same on both sides. Inputs are played a few frames after they
+// are made, which hides the time they take to get across; if the other side's still haven't arrived, the games wait.
+//
+// The protocol is lines of text over TCP:
+//     hello <version>                     both, first
+//     rules <seed> <rounds> <garbage delay> <width> <height> <hidden> <partial lock out> <lines>
+//                                         the host, followed by that many lines of the piece set, none for the standard one
+//     ready                               the guest, once it has the rules
+//     input <frame> <actions>             both, for every frame, the codes of the actions separated by commas, or "-"
+//     check <frame> <checksum>            both, every second, to find out if the games have gone different ways
+//     ping <id>, pong <id>                both, every second, to measure the latency
+//     bye                                 either, when leaving
+use input::Action;
+use pieces::PieceSet;
+use tetris::{BoardSettings, FPS};
+use versus::{opponent, RoundResult, Versus, NUM_PLAYERS};
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const PROTOCOL_VERSION: u32 = 2;
+pub const DEFAULT_PORT: u16 = 7474;
+// frames between an input being made and played
+pub const INPUT_DELAY: u32 = 3;
+// frames the result of a round is shown for before the next one starts, the same on both sides
+pub const ROUND_PAUSE: u32 = 3 * FPS;
+const CHECK_INTERVAL: u32 = FPS;
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// the most lines of a piece set the other side can send, far more than any sensible set takes
+const MAX_PIECE_LINES: u64 = 4096;
+// FNV-1a, which comes out the same on every build and machine
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+enum Message {
+    Input(u32, Vec<Action>),
+    Check(u32, u64),
+    Ping(u32),
+    Pong(u32),
+    Bye,
+    // the connection broke, or the other side sent something that makes no sense
+    Closed(String),
+}
+
+fn parse_message(line: &str) -> Option<Message> {
+    let words = line.split_whitespace().collect::<Vec<_>>();
+    match words.as_slice() {
+        ["input", frame, actions] => {
+            let actions = match *actions {
+                "-" => Vec::new(),
+                actions => actions.split(',').map(|code| code.parse().ok().and_then(Action::from_code)).collect::<Option<_>>()?
+            };
+            Some(Message::Input(frame.parse().ok()?, actions))
+        }
+        ["check", frame, checksum] => Some(Message::Check(frame.parse().ok()?, checksum.parse().ok()?)),
+        ["ping", id] => Some(Message::Ping(id.parse().ok()?)),
+        ["pong", id] => Some(Message::Pong(id.parse().ok()?)),
+        ["bye"] => Some(Message::Bye),
+        _ => None
+    }
+}
+
+// reads the lines the other side sends on a thread of its own, so the game never waits on the network
+fn read_messages(mut reader: BufReader<TcpStream>) -> Receiver<Message> {
+    let (sender, receiver) = channel();
+    thread::spawn(move || loop {
+        let mut line = String::new();
+        let message = match reader.read_line(&mut line) {
+            Ok(0) => Message::Closed("the connection was closed".to_string()),
+            Ok(_) => parse_message(&line).unwrap_or_else(|| Message::Closed(format!("can't make sense of \"{}\"", line.trim()))),
+            Err(e) => Message::Closed(format!("the connection broke: {}", e))
+        };
+        let closed = matches!(message, Message::Bye | Message::Closed(_));
+        if sender.send(message).is_err() || closed {
+            return
+        }
+    });
+    receiver
+}
+
+// reads the other side's lines until the game starts, giving up on them if they go quiet
+pub fn handshake_reader(stream: &TcpStream) -> Result<BufReader<TcpStream>, String> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).map_err(|e| e.to_string())?;
+    stream.try_clone().map(BufReader::new).map_err(|e| e.to_string())
+}
+
+pub fn read_handshake_line(reader: &mut BufReader<TcpStream>) -> Result<String, String> {
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) => Err("the other side left before the game started".to_string()),
+        Ok(_) => Ok(line.trim().to_string()),
+        Err(e) => Err(format!("failed to hear from the other side: {}", e))
+    }
+}
+
+pub fn send_line(stream: &mut TcpStream, line: &str) -> Result<(), String> {
+    writeln!(stream, "{}", line).map_err(|e| format!("failed to send to the other side: {}", e))
+}
+
+// the board and the pieces, at the end of the rules: "<width> <height> <hidden> <partial lock out> <lines>", followed by
+// that many lines of the piece set, none for the standard one
+pub fn board_rules(settings: &BoardSettings, pieces: &PieceSet) -> (String, Vec<String>) {
+    let piece_lines = if pieces.is_standard() { Vec::new() } else { pieces.to_text().lines().map(String::from).collect() };
+    (format!("{} {} {} {} {}", settings.width, settings.height, settings.hidden, settings.partial_lock_out as u8,
+        piece_lines.len()), piece_lines)
+}
+
+// reads the rest of the board and pieces from their numbers in the rules
+pub fn read_board_rules(numbers: &[u64], reader: &mut BufReader<TcpStream>) -> Result<(BoardSettings, PieceSet), String> {
+    if numbers.len() != 5 {
+        return Err("the board is missing from the rules".to_string())
+    }
+    let spec = format!("{}x{}+{}", numbers[0], numbers[1], numbers[2]);
+    let mut settings = BoardSettings::parse(&spec).ok_or(format!("the board {} makes no sense", spec))?;
+    settings.partial_lock_out = numbers[3] != 0;
+
+    if numbers[4] > MAX_PIECE_LINES {
+        return Err(format!("the other side's pieces take {} lines, more than {}", numbers[4], MAX_PIECE_LINES))
+    }
+    let mut piece_lines = Vec::new();
+    for _ in 0..numbers[4] {
+        piece_lines.push(read_handshake_line(reader)?);
+    }
+    let pieces = if piece_lines.is_empty() {
+        PieceSet::standard()
+    } else {
+        PieceSet::parse(&piece_lines.join("\n")).map_err(|e| format!("the other side's pieces are invalid: {}", e))?
+    };
+    Ok((settings, pieces))
+}
+
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| (hash ^ *byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+// what both sides should agree on if they have played the same frames, hashing every number as 32 bits in little
+// endian so the two sides don't have to be the same build or run on the same kind of machine
+fn checksum(versus: &Versus) -> u64 {
+    let mut values = vec![versus.round];
+    for game in &versus.games {
+        values.extend(game.game_map.bits.rows.iter().map(|row| *row as u32));
+        values.extend([game.score, game.num_lines, game.garbage.total()]);
+        if let Some(ref piece) = game.current_piece {
+            values.extend([piece.kind as u32, piece.x as i32 as u32, piece.y as u32, piece.current_state as u32]);
+        }
+    }
+    values.iter().fold(FNV_OFFSET, |hash, value| fnv1a(hash, &value.to_le_bytes()))
+}
+
+// both sides say which version of the protocol they speak, and they have to be the same
+fn exchange_hello(stream: &mut TcpStream, reader: &mut BufReader<TcpStream>) -> Result<(), String> {
+    send_line(stream, &format!("hello {}", PROTOCOL_VERSION))?;
+    let line = read_handshake_line(reader)?;
+    match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["hello", version] if *version == PROTOCOL_VERSION.to_string() => Ok(()),
+        ["hello", version] => Err(format!("the other side speaks version {} of the protocol, not {}", version, PROTOCOL_VERSION)),
+        _ => Err(format!("the other side isn't a game of tetris, it said \"{}\"", line))
+    }
+}
+
+pub struct Session {
+    pub versus: Versus,
+    // which of the players this side is
+    pub local: usize,
+    stream: TcpStream,
+    messages: Receiver<Message>,
+    // the frame the games are on, counted across the rounds
+    pub frame: u32,
+    // inputs from this side and the other for the frames from `frame` on, in order
+    local_inputs: VecDeque<Vec<Action>>,
+    remote_inputs: VecDeque<Vec<Action>>,
+    // checksums of frames that only one side has worked out so far
+    local_checks: HashMap<u32, u64>,
+    remote_checks: HashMap<u32, u64>,
+    pings: HashMap<u32, Instant>,
+    next_ping: u32,
+    last_ping: Instant,
+    // the time a message takes to get to the other side and back
+    pub latency: Option<Duration>,
+    // the result of the round that just ended, while it is shown
+    pub result: Option<RoundResult>,
+    pause: u32,
+    // the match is over and its result has been shown
+    pub finished: bool,
+    // why the connection ended; the frames the other side's inputs have already come in for can still be played
+    closed: Option<String>,
+}
+
+impl Session {
+    // waits for a player to join on the port, then tells them the rules
+    pub fn host(port: u16, seed: u64, rounds: u32, garbage_delay: u32, settings: BoardSettings, pieces: Arc<PieceSet>)
+        -> Result<Session, String> {
+        let listener = TcpListener::bind(("0.0.0.0", port)).map_err(|e| format!("failed to listen on port {}: {}", port, e))?;
+        let (mut stream, _) = listener.accept().map_err(|e| format!("failed to accept a player: {}", e))?;
+        let mut reader = handshake_reader(&stream)?;
+        exchange_hello(&mut stream, &mut reader)?;
+
+        let (board, piece_lines) = board_rules(&settings, &pieces);
+        send_line(&mut stream, &format!("rules {} {} {} {}", seed, rounds, garbage_delay, board))?;
+        for line in &piece_lines {
+            send_line(&mut stream, line)?;
+        }
+        let line = read_handshake_line(&mut reader)?;
+        if line != "ready" {
+            return Err(format!("expected the other side to be ready, it said \"{}\"", line))
+        }
+
+        let mut versus = Versus::new(seed, rounds, settings, pieces);
+        versus.garbage_delay = garbage_delay;
+        Session::start(stream, reader, versus, 0)
+    }
+
+    // joins the game hosted at the address, playing by its rules
+    pub fn join(address: &str) -> Result<Session, String> {
+        let mut stream = TcpStream::connect(address).map_err(|e| format!("failed to connect to {}: {}", address, e))?;
+        let mut reader = handshake_reader(&stream)?;
+        exchange_hello(&mut stream, &mut reader)?;
+
+        let line = read_handshake_line(&mut reader)?;
+        let numbers = match line.split_whitespace().collect::<Vec<_>>().split_first() {
+            Some((&"rules", numbers)) if numbers.len() == 8 => numbers.iter().map(|number| number.parse::<u64>().ok())
+                .collect::<Option<Vec<_>>>(),
+            _ => None
+        }.ok_or(format!("expected the rules of the game, the other side said \"{}\"", line))?;
+        let (seed, rounds, garbage_delay) = (numbers[0], numbers[1] as u32, numbers[2] as u32);
+        if rounds == 0 {
+            return Err(format!("the rules make no sense: \"{}\"", line))
+        }
+        let (settings, pieces) = read_board_rules(&numbers[3..], &mut reader)?;
+        send_line(&mut stream, "ready")?;
+
+        let mut versus = Versus::new(seed, rounds, settings, Arc::new(pieces));
+        versus.garbage_delay = garbage_delay;
+        Session::start(stream, reader, versus, 1)
+    }
+
+    fn start(stream: TcpStream, reader: BufReader<TcpStream>, versus: Versus, local: usize) -> Result<Session, String> {
+        stream.set_read_timeout(None).map_err(|e| e.to_string())?;
+        // inputs are tiny and have to get there as soon as they can
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+        let mut session = Session {
+            versus,
+            local,
+            stream,
+            messages: read_messages(reader),
+            frame: 0,
+            local_inputs: VecDeque::new(),
+            remote_inputs: VecDeque::new(),
+            local_checks: HashMap::new(),
+            remote_checks: HashMap::new(),
+            pings: HashMap::new(),
+            next_ping: 0,
+            last_ping: Instant::now(),
+            latency: None,
+            result: None,
+            pause: 0,
+            finished: false,
+            closed: None,
+        };
+        // nothing is played on the first frames, the time inputs take to come into play
+        for _ in 0..INPUT_DELAY {
+            session.send_inputs(&[]);
+        }
+        Ok(session)
+    }
+
+    // handles what the other side has sent, returning an error once the game can't go on
+    pub fn poll(&mut self) -> Result<(), String> {
+        while self.closed.is_none() {
+            let message = match self.messages.try_recv() {
+                Ok(message) => message,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => Message::Closed("the connection was closed".to_string())
+            };
+            match message {
+                Message::Input(frame, actions) => {
+                    if frame != self.frame + self.remote_inputs.len() as u32 {
+                        return Err(format!("the other side sent the inputs of frame {} out of order", frame))
+                    }
+                    self.remote_inputs.push_back(actions);
+                }
+                Message::Check(frame, checksum) => {
+                    self.remote_checks.insert(frame, checksum);
+                    self.compare_checks(frame)?;
+                }
+                Message::Ping(id) => self.send(&format!("pong {}", id)),
+                Message::Pong(id) => {
+                    if let Some(sent) = self.pings.remove(&id) {
+                        self.latency = Some(sent.elapsed());
+                    }
+                }
+                Message::Bye => self.closed = Some("the other player left".to_string()),
+                Message::Closed(e) => self.closed = Some(e)
+            }
+        }
+
+        if self.last_ping.elapsed() >= PING_INTERVAL {
+            self.last_ping = Instant::now();
+            let id = self.next_ping;
+            self.next_ping += 1;
+            self.pings.insert(id, Instant::now());
+            self.send(&format!("ping {}", id));
+        }
+        match self.closed {
+            Some(ref e) if self.remote_inputs.is_empty() && !self.finished => Err(e.clone()),
+            _ => Ok(())
+        }
+    }
+
+    // sends the inputs of this side for the next frame they can go on, returning false if this side is already as
+    // far ahead of the games as the input delay, in which case they should be kept for later
+    pub fn send_inputs(&mut self, actions: &[Action]) -> bool {
+        if self.local_inputs.len() as u32 > INPUT_DELAY {
+            return false
+        }
+        let frame = self.frame + self.local_inputs.len() as u32;
+        let codes = if actions.is_empty() {
+            "-".to_string()
+        } else {
+            actions.iter().map(|action| action.code().to_string()).collect::<Vec<_>>().join(",")
+        };
+        self.send(&format!("input {} {}", frame, codes));
+        self.local_inputs.push_back(actions.to_vec());
+        true
+    }
+
+    // plays the next frame if the inputs of both sides are in, returning whether it did
+    pub fn step(&mut self) -> Result<bool, String> {
+        if self.finished || self.local_inputs.is_empty() || self.remote_inputs.is_empty() {
+            return Ok(false)
+        }
+        let mut actions = vec![Vec::new(); NUM_PLAYERS];
+        actions[self.local] = self.local_inputs.pop_front().unwrap();
+        actions[opponent(self.local)] = self.remote_inputs.pop_front().unwrap();
+
+        if self.pause > 0 {
+            self.pause -= 1;
+            if self.pause == 0 {
+                self.result = None;
+                if self.versus.winner().is_some() {
+                    self.finished = true;
+                } else {
+                    self.versus.next_round();
+                }
+            }
+        } else if let Some(result) = self.versus.step(&actions) {
+            self.result = Some(result);
+            self.pause = ROUND_PAUSE;
+        }
+        self.frame += 1;
+
+        if self.frame.is_multiple_of(CHECK_INTERVAL) {
+            let checksum = checksum(&self.versus);
+            self.send(&format!("check {} {}", self.frame, checksum));
+            self.local_checks.insert(self.frame, checksum);
+            self.compare_checks(self.frame)?;
+        }
+        Ok(true)
+    }
+
+    fn compare_checks(&mut self, frame: u32) -> Result<(), String> {
+        if let (Some(local), Some(remote)) = (self.local_checks.get(&frame), self.remote_checks.get(&frame)) {
+            if local != remote {
+                return Err(format!("the games went different ways by frame {}", frame))
+            }
+            self.local_checks.remove(&frame);
+            self.remote_checks.remove(&frame);
+        }
+        Ok(())
+    }
+
+    // a failure is kept until what the other side sent before it has been played
+    fn send(&mut self, line: &str) {
+        if self.closed.is_some() {
+            return
+        }
+        if let Err(e) = send_line(&mut self.stream, line) {
+            self.closed = Some(e);
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.send("bye");
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a port nothing is listening on
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    // joins the game on the port, waiting for it to be hosted
+    fn join(port: u16) -> Session {
+        let started = Instant::now();
+        loop {
+            match Session::join(&format!("127.0.0.1:{}", port)) {
+                Ok(session) => return session,
+                Err(e) if started.elapsed() > HANDSHAKE_TIMEOUT => panic!("{}", e),
+                Err(_) => thread::sleep(Duration::from_millis(10))
+            }
+        }
+    }
+
+    // the two ends of a connection to this machine
+    fn connection() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (client, listener.accept().unwrap().0)
+    }
+
+    #[test]
+    fn fnv1a_vectors() {
+        assert_eq!(fnv1a(FNV_OFFSET, b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(FNV_OFFSET, b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(FNV_OFFSET, b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn checksum_follows_the_games() {
+        let pieces = Arc::new(PieceSet::standard());
+        let mut first = Versus::new(5, 3, BoardSettings::default(), pieces.clone());
+        let second = Versus::new(5, 3, BoardSettings::default(), pieces);
+        assert_eq!(checksum(&first), checksum(&second));
+        first.step(&[vec![Action::HardDrop], Vec::new()]);
+        assert_ne!(checksum(&first), checksum(&second));
+    }
+
+    #[test]
+    fn too_many_piece_lines() {
+        let (_client, server) = connection();
+        let mut reader = handshake_reader(&server).unwrap();
+        let error = read_board_rules(&[10, 20, 0, 0, MAX_PIECE_LINES + 1], &mut reader).err().unwrap();
+        assert!(error.contains("more than"), "{}", error);
+    }
+
+    #[test]
+    fn loopback() {
+        let port = free_port();
+        let pieces = PieceSet::parse(include_str!("../pieces/triominoes.txt")).unwrap();
+        let shared = Arc::new(pieces.clone());
+        let host = thread::spawn(move || Session::host(port, 9, 2, 20, BoardSettings::GUIDELINE, shared).unwrap());
+        let mut guest = join(port);
+        let mut host = host.join().unwrap();
+
+        // the guest plays by the host's rules
+        assert_eq!((guest.local, host.local), (1, 0));
+        assert_eq!(guest.versus.garbage_delay, 20);
+        assert_eq!(guest.versus.games[0].settings, BoardSettings::GUIDELINE);
+        assert_eq!(*guest.versus.games[0].pieces, pieces);
+
+        let frames = 3 * CHECK_INTERVAL;
+        let started = Instant::now();
+        while host.frame < frames || guest.frame < frames || !host.local_checks.is_empty() || !guest.local_checks.is_empty() {
+            assert!(started.elapsed() < Duration::from_secs(10), "the games got stuck");
+            for (session, action) in [(&mut host, Action::HardDrop), (&mut guest, Action::MoveLeft)] {
+                if session.frame < frames {
+                    let actions = if session.frame % 7 == 0 { vec![action] } else { Vec::new() };
+                    session.send_inputs(&actions);
+                }
+                session.poll().unwrap();
+                session.step().unwrap();
+            }
+            if host.frame == guest.frame {
+                assert_eq!(checksum(&host.versus), checksum(&guest.versus));
+            }
+        }
+        assert!(host.versus.games[0].num_pieces > 0);
+        assert_eq!(host.versus.games[0].game_map, guest.versus.games[0].game_map);
+
+        drop(host);
+        let started = Instant::now();
+        while guest.poll().is_ok() {
+            assert!(started.elapsed() < Duration::from_secs(10), "the guest didn't hear the host leave");
+            guest.step().unwrap();
+        }
+    }
+}
@@ -0,0 +1,281 @@
+// Answers whether a piece can get somewhere on the board and with which inputs, going through every position
+// it can reach by moving, turning and soft dropping, so tucks under overhangs and spins are found as well
+use board::Board;
+use input::Action;
+use tetrimino::Tetrimino;
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+
+const MOVES: [Action; 5] = [Action::MoveLeft, Action::MoveRight, Action::RotateClockwise, Action::RotateCounterClockwise,
+    Action::SoftDrop];
+
+// column, line and state of the piece
+pub type Position = (isize, usize, u8);
+
+pub struct Path {
+    // the piece where the path leaves it, resting on the stack
+    pub piece: Tetrimino,
+    // the inputs that take it there, ending with a hard drop
+    pub actions: Vec<Action>,
+    // the number of moves and rotations, soft drops being free like holding a key down
+    pub inputs: u32,
+}
+
+struct Node {
+    // moves and rotations, then the lines they were made on added up, so that between two ways that take as many
+    // inputs the one that makes them higher up, before the piece has to be dropped, wins
+    cost: (u32, u32),
+    previous: Option<(Position, Action)>,
+    done: bool,
+}
+
+// the cells, as (column, line) on the game map, that the piece covers in the given position
+pub fn cells_at(piece: &Tetrimino, (x, y, state): Position) -> HashSet<(isize, usize)> {
+    let mut cells = HashSet::new();
+    for (line_num, line) in piece.states[state as usize].iter().enumerate() {
+        for (case_num, case) in line.iter().enumerate() {
+            if *case != 0 {
+                cells.insert((x + case_num as isize, y + line_num));
+            }
+        }
+    }
+    cells
+}
+
+// the same cells as `cells_at`, as the bits of each line the piece covers, which is quicker to compare
+fn footprint(piece: &Tetrimino, (x, y, state): Position) -> Vec<(usize, u64)> {
+    // a piece's box is at most 16 cells wide, so its column is never further left than that
+    piece.masks[state as usize].iter().enumerate()
+        .filter(|&(_, mask)| *mask != 0)
+        .map(|(line, mask)| (y + line, ((*mask as u64) << (x + 16)) >> 16))
+        .collect()
+}
+
+fn resting(game_map: &Board, piece: &Tetrimino, (x, y, state): Position) -> bool {
+    !piece.test_position(game_map, state as usize, x, y + 1)
+}
+
+// where the input takes the piece, if it can move at all
+pub fn next_position(game_map: &Board, piece: &Tetrimino, (x, y, state): Position, action: Action) -> Option<Position> {
+    let fits = |x: isize, y: usize| piece.test_position(game_map, state as usize, x, y);
+    match action {
+        Action::MoveLeft if fits(x - 1, y) => Some((x - 1, y, state)),
+        Action::MoveRight if fits(x + 1, y) => Some((x + 1, y, state)),
+        Action::SoftDrop if fits(x, y + 1) => Some((x, y + 1, state)),
+        Action::RotateClockwise => piece.rotation(game_map, x, y, state, true).map(|(x, state)| (x, y, state)),
+        Action::RotateCounterClockwise => piece.rotation(game_map, x, y, state, false).map(|(x, state)| (x, y, state)),
+        _ => None
+    }
+}
+
+// the nodes of the search, one for every position the piece could be in
+struct Nodes {
+    nodes: Vec<Option<Node>>,
+    width: usize,
+    num_states: usize,
+    // the piece's column never goes further than the width of its box past either side of the board
+    margin: isize,
+}
+
+impl Nodes {
+    fn new(game_map: &Board, piece: &Tetrimino) -> Nodes {
+        let margin = piece.states[0].len() as isize;
+        let width = game_map.width() + 2 * margin as usize;
+        let num_states = piece.states.len();
+        Nodes {nodes: (0..width * game_map.height() * num_states).map(|_| None).collect(), width, num_states, margin}
+    }
+
+    fn index(&self, (x, y, state): Position) -> usize {
+        (y * self.width + (x + self.margin) as usize) * self.num_states + state as usize
+    }
+
+    fn get(&self, position: Position) -> Option<&Node> {
+        self.nodes[self.index(position)].as_ref()
+    }
+
+    fn get_mut(&mut self, position: Position) -> Option<&mut Node> {
+        let index = self.index(position);
+        self.nodes[index].as_mut()
+    }
+
+    fn insert(&mut self, position: Position, node: Node) {
+        let index = self.index(position);
+        self.nodes[index] = Some(node);
+    }
+}
+
+// Dijkstra's algorithm over the positions of the piece, returning them from the cheapest to reach
+fn search(game_map: &Board, piece: &Tetrimino) -> (Vec<Position>, Nodes) {
+    let mut nodes = Nodes::new(game_map, piece);
+    let mut order = Vec::new();
+    if !piece.test_current_position(game_map) {
+        return (order, nodes)
+    }
+
+    let start = (piece.x, piece.y, piece.current_state);
+    nodes.insert(start, Node {cost: (0, 0), previous: None, done: false});
+    let mut queue = BinaryHeap::new();
+    queue.push(Reverse(((0, 0), start)));
+
+    while let Some(Reverse((cost, position))) = queue.pop() {
+        {
+            let node = nodes.get_mut(position).unwrap();
+            if node.done || node.cost != cost {
+                continue
+            }
+            node.done = true;
+        }
+        order.push(position);
+
+        for action in MOVES.iter() {
+            let next = match next_position(game_map, piece, position, *action) {
+                Some(next) => next,
+                None => continue
+            };
+            let next_cost = if *action == Action::SoftDrop { cost } else { (cost.0 + 1, cost.1 + position.1 as u32) };
+            let better = nodes.get(next).is_none_or(|node| !node.done && next_cost < node.cost);
+            if better {
+                nodes.insert(next, Node {cost: next_cost, previous: Some((position, *action)), done: false});
+                queue.push(Reverse((next_cost, next)));
+            }
+        }
+    }
+    (order, nodes)
+}
+
+fn build_path(piece: &Tetrimino, nodes: &Nodes, end: Position) -> Path {
+    let mut actions = Vec::new();
+    let mut position = end;
+    while let Some((previous, action)) = nodes.get(position).unwrap().previous {
+        actions.push(action);
+        position = previous;
+    }
+    actions.reverse();
+    // the hard drop does the same
+    while actions.last() == Some(&Action::SoftDrop) {
+        actions.pop();
+    }
+    actions.push(Action::HardDrop);
+
+    let mut placed = piece.clone();
+    placed.x = end.0;
+    placed.y = end.1;
+    placed.current_state = end.2;
+    Path {piece: placed, actions, inputs: nodes.get(end).unwrap().cost.0}
+}
+
+// every place the piece can come to rest in, each with the cheapest way there; placements covering the same
+// cells in different states only come up once
+pub fn reachable(game_map: &Board, piece: &Tetrimino) -> Vec<Path> {
+    let (order, nodes) = search(game_map, piece);
+    let mut paths: Vec<Path> = Vec::new();
+    let mut found_cells = Vec::new();
+    for position in order {
+        if !resting(game_map, piece, position) {
+            continue
+        }
+        let cells = footprint(piece, position);
+        if !found_cells.contains(&cells) {
+            found_cells.push(cells);
+            paths.push(build_path(piece, &nodes, position));
+        }
+    }
+    paths
+}
+
+// the cheapest way to drop the piece into the cells `target` covers
+pub fn find_path(game_map: &Board, piece: &Tetrimino, target: &Tetrimino) -> Option<Path> {
+    let target_cells = footprint(target, (target.x, target.y, target.current_state));
+    let (order, nodes) = search(game_map, piece);
+    order.into_iter()
+        .find(|position| resting(game_map, piece, *position) && footprint(piece, *position) == target_cells)
+        .map(|position| build_path(piece, &nodes, position))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use garbage::TSpin;
+    use tetris::Tetris;
+
+    // a classic game with the lines at the bottom of its board, '#' standing for a garbage cell, and the named piece
+    // in play
+    fn game(lines: &[&str], name: &str) -> Tetris {
+        let mut tetris = Tetris::with_seed(1);
+        let mut colours = vec![vec![0; 10]; 16 - lines.len()];
+        colours.extend(lines.iter().map(|line| line.chars().map(|c| if c == '#' { 8 } else { 0 }).collect()));
+        tetris.game_map = Board::from_colours(colours).unwrap();
+        let kind = tetris.pieces.kind(name).unwrap();
+        tetris.current_piece = Some(tetris.spawn_tetrimino(kind));
+        tetris
+    }
+
+    fn target(tetris: &Tetris, position: Position) -> Tetrimino {
+        let mut piece = tetris.current_piece.clone().unwrap();
+        piece.x = position.0;
+        piece.y = position.1;
+        piece.current_state = position.2;
+        piece
+    }
+
+    // plays the path through the game, a frame for each input and without gravity, and returns the cells the piece
+    // locked in
+    fn play(tetris: &mut Tetris, path: &Path) -> HashSet<(isize, usize)> {
+        let pieces = tetris.num_pieces;
+        for action in &path.actions {
+            assert_eq!(tetris.num_pieces, pieces, "the piece locked before the path was over");
+            tetris.gravity_frames = 0;
+            tetris.step(&[*action]);
+        }
+        assert_eq!(tetris.num_pieces, pieces + 1, "the path doesn't lock the piece");
+        let locked = tetris.locked_piece.as_ref().unwrap();
+        cells_at(locked, (locked.x, locked.y, locked.current_state))
+    }
+
+    #[test]
+    fn tuck_under_an_overhang() {
+        let mut tetris = game(&["######....", ".........."], "I");
+        let target = target(&tetris, (2, 15, 0));
+        let path = find_path(&tetris.game_map, tetris.current_piece.as_ref().unwrap(), &target).unwrap();
+        // the piece is dropped beside the overhang and slid under it
+        assert!(path.actions.contains(&Action::SoftDrop));
+        let moves = path.actions.iter().rposition(|action| *action == Action::MoveLeft).unwrap();
+        assert!(path.actions[..moves].contains(&Action::SoftDrop));
+        assert_eq!(play(&mut tetris, &path), cells_at(&target, (2, 15, 0)));
+    }
+
+    #[test]
+    fn spin_into_a_slot() {
+        let mut tetris = game(&["....#.....", "##...#####", "###.######"], "T");
+        // pointing up in the slot, which it can only turn into, the cell above its left side being free but the one
+        // above its right side filled
+        let target = target(&tetris, (2, 13, 2));
+        let path = find_path(&tetris.game_map, tetris.current_piece.as_ref().unwrap(), &target).unwrap();
+        let cells = play(&mut tetris, &path);
+        assert_eq!(cells, cells_at(&target, (2, 13, 2)));
+        let clear = tetris.last_clear.unwrap();
+        assert_eq!(clear.lines, 1);
+        assert_eq!(clear.t_spin, TSpin::Mini);
+    }
+
+    #[test]
+    fn every_placement_is_reached() {
+        let tetris = game(&["#...######", "##.#######"], "T");
+        let piece = tetris.current_piece.as_ref().unwrap();
+        let paths = reachable(&tetris.game_map, piece);
+        assert!(!paths.is_empty());
+        for path in paths {
+            let mut game = tetris.clone();
+            let placed = &path.piece;
+            assert_eq!(play(&mut game, &path), cells_at(placed, (placed.x, placed.y, placed.current_state)));
+        }
+    }
+
+    #[test]
+    fn sealed_off() {
+        let tetris = game(&["##########", "....######"], "I");
+        let target = target(&tetris, (0, 15, 0));
+        assert!(find_path(&tetris.game_map, tetris.current_piece.as_ref().unwrap(), &target).is_none());
+    }
+}
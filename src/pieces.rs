@@ -0,0 +1,276 @@
+// The pieces a game is played with, read from a file giving the cells of each one, so that other sets than the seven
+// tetriminos can be played, pentominoes for one. The format is described at the top of pieces/standard.txt.
+use board::{Board, MAX_WIDTH};
+use highscore::read_from_file;
+use tetrimino::{Piece, States, Tetrimino};
+use tetris::BoardSettings;
+
+pub const STANDARD_PIECES: &str = include_str!("../pieces/standard.txt");
+// the number of textures there are to draw cells with
+const NUM_COLOURS: u8 = 7;
+// the width of the board the spawn columns are given for
+const SPAWN_WIDTH: isize = 10;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PieceKind {
+    pub name: String,
+    pub colour: u8,
+    // every way the piece can face in clockwise order, each one a square box with the cells set to the colour
+    pub states: States,
+    // the column of its box where it comes in on a board 10 wide
+    pub spawn_x: isize,
+    // how many lines lower than usual it comes in
+    pub spawn_y: usize,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PieceSet {
+    pub kinds: Vec<PieceKind>,
+}
+
+// a piece as it is being read, before its box is squared off and its other states worked out
+struct PieceDef {
+    name: String,
+    colour: u8,
+    states: Vec<Vec<Vec<bool>>>,
+    spawn: Option<(isize, usize)>,
+}
+
+fn turn_clockwise(state: &Piece) -> Piece {
+    let size = state.len();
+    (0..size).map(|y| (0..size).map(|x| state[size - 1 - x][y]).collect()).collect()
+}
+
+fn num_cells(state: &Piece) -> usize {
+    state.iter().flat_map(|line| line.iter()).filter(|case| **case != 0).count()
+}
+
+impl PieceDef {
+    fn finish(self) -> Result<PieceKind, String> {
+        let error = |message: &str| format!("piece {}: {}", self.name, message);
+        if self.states.iter().any(|state| state.is_empty()) {
+            return Err(error("a state has no lines"))
+        }
+        if self.states.iter().any(|state| state.iter().any(|line| line.len() != state[0].len())) {
+            return Err(error("the lines of a state aren't all as long"))
+        }
+        let size = self.states.iter()
+            .map(|state| state.len().max(state.iter().map(|line| line.len()).max().unwrap_or(0)))
+            .max()
+            .unwrap_or(0);
+        if size > MAX_WIDTH {
+            return Err(error(&format!("it is more than {} cells across", MAX_WIDTH)))
+        }
+
+        // every state is padded out to the same square, which the piece turns in
+        let mut states = self.states.iter().map(|state| (0..size).map(|y| (0..size).map(|x| {
+            if state.get(y).and_then(|line| line.get(x)).cloned().unwrap_or(false) { self.colour } else { 0 }
+        }).collect()).collect()).collect::<States>();
+        let cells = num_cells(&states[0]);
+        if cells == 0 {
+            return Err(error("it has no cells"))
+        }
+        if states.iter().any(|state| num_cells(state) != cells) {
+            return Err(error("its states don't all have as many cells"))
+        }
+        if states.len() == 1 {
+            loop {
+                let next = turn_clockwise(states.last().unwrap());
+                if next == states[0] {
+                    break
+                }
+                states.push(next);
+            }
+        }
+
+        let (spawn_x, spawn_y) = self.spawn.unwrap_or(((SPAWN_WIDTH - size as isize) / 2, 0));
+        Ok(PieceKind {name: self.name, colour: self.colour, states, spawn_x, spawn_y})
+    }
+}
+
+impl PieceSet {
+    pub fn standard() -> PieceSet {
+        PieceSet::parse(STANDARD_PIECES).expect("The standard pieces are invalid.")
+    }
+
+    pub fn load(file_name: &str) -> Result<PieceSet, String> {
+        let content = read_from_file(file_name).map_err(|e| format!("failed to read {}: {}", file_name, e))?;
+        PieceSet::parse(&content).map_err(|e| format!("{}: {}", file_name, e))
+    }
+
+    pub fn parse(content: &str) -> Result<PieceSet, String> {
+        let mut defs: Vec<PieceDef> = Vec::new();
+        for (line_num, line) in content.lines().enumerate() {
+            let line = line.trim();
+            let error = |message: &str| format!("line {}: {}", line_num + 1, message);
+            if line.is_empty() || line.starts_with("//") {
+                continue
+            }
+
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            match words.as_slice() {
+                ["piece", name, colour] => {
+                    let colour = colour.parse::<u8>().ok().filter(|colour| (1..=NUM_COLOURS).contains(colour))
+                        .ok_or_else(|| error(&format!("the colour has to be from 1 to {}", NUM_COLOURS)))?;
+                    if defs.iter().any(|def| def.name == *name) {
+                        return Err(error(&format!("there already is a piece called {}", name)))
+                    }
+                    defs.push(PieceDef {name: name.to_string(), colour, states: vec![Vec::new()], spawn: None});
+                }
+                _ if defs.is_empty() => return Err(error("expected \"piece <name> <colour>\"")),
+                ["spawn", x] | ["spawn", x, _] => {
+                    let x = x.parse().map_err(|_| error("the spawn column has to be a number"))?;
+                    let y = words.get(2).map_or(Ok(0), |y| y.parse()).map_err(|_| error("the spawn line has to be a number"))?;
+                    defs.last_mut().unwrap().spawn = Some((x, y));
+                }
+                ["state"] => defs.last_mut().unwrap().states.push(Vec::new()),
+                [cells] if cells.chars().all(|c| c == '#' || c == '.') => {
+                    defs.last_mut().unwrap().states.last_mut().unwrap().push(cells.chars().map(|c| c == '#').collect());
+                }
+                _ => return Err(error(&format!("can't make sense of \"{}\"", line)))
+            }
+        }
+
+        if defs.is_empty() {
+            return Err("there are no pieces".to_string())
+        }
+        if defs.len() >= u8::MAX as usize {
+            return Err(format!("there can be at most {} pieces", u8::MAX - 1))
+        }
+        Ok(PieceSet {kinds: defs.into_iter().map(PieceDef::finish).collect::<Result<_, _>>()?})
+    }
+
+    // the set written out the way it is read, with every state given
+    pub fn to_text(&self) -> String {
+        let mut content = String::new();
+        for kind in &self.kinds {
+            content += &format!("piece {} {}\nspawn {} {}\n", kind.name, kind.colour, kind.spawn_x, kind.spawn_y);
+            for (state_num, state) in kind.states.iter().enumerate() {
+                if state_num > 0 {
+                    content += "state\n";
+                }
+                for line in state {
+                    content += &line.iter().map(|case| if *case != 0 { '#' } else { '.' }).collect::<String>();
+                    content += "\n";
+                }
+            }
+        }
+        content
+    }
+
+    pub fn is_standard(&self) -> bool {
+        *self == PieceSet::standard()
+    }
+
+    pub fn num_kinds(&self) -> usize {
+        self.kinds.len()
+    }
+
+    pub fn name(&self, kind: u8) -> &str {
+        &self.kinds[kind as usize - 1].name
+    }
+
+    pub fn kind(&self, name: &str) -> Option<u8> {
+        self.kinds.iter().position(|kind| kind.name == name).map(|index| index as u8 + 1)
+    }
+
+    // a piece of the given kind, counted from 1, in its first state at the top of a board 10 wide
+    pub fn create(&self, kind: u8) -> Tetrimino {
+        let piece = &self.kinds[kind as usize - 1];
+        Tetrimino::from_states(kind, piece.states.clone(), piece.spawn_x)
+    }
+
+    // a new piece where it comes in: centred, with its lowest line just above the shown ones, and moved down a
+    // line straight away if it came in out of view and nothing is in the way
+    pub fn spawn(&self, settings: &BoardSettings, game_map: &Board, kind: u8) -> Tetrimino {
+        let mut piece = self.create(kind);
+        let (left, right) = piece.columns(0);
+        let width = settings.width as isize;
+        piece.x = (piece.x + (width - SPAWN_WIDTH) / 2).min(width - 1 - right).max(-left);
+        piece.y = settings.hidden.saturating_sub(piece.lines(0).1 + 1) + self.kinds[kind as usize - 1].spawn_y;
+        if settings.hidden > 0 && piece.test_current_position(game_map) {
+            let (x, y) = (piece.x, piece.y + 1);
+            piece.change_position(game_map, x, y);
+        }
+        piece
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(state: &Piece) -> Vec<String> {
+        state.iter().map(|line| line.iter().map(|case| if *case != 0 { '#' } else { '.' }).collect()).collect()
+    }
+
+    #[test]
+    fn standard() {
+        let pieces = PieceSet::standard();
+        assert_eq!(pieces.num_kinds(), 7);
+        let states = pieces.kinds.iter().map(|kind| (kind.name.as_str(), kind.states.len())).collect::<Vec<_>>();
+        assert_eq!(states, vec![("I", 2), ("L", 4), ("J", 4), ("O", 1), ("S", 2), ("Z", 2), ("T", 4)]);
+        assert_eq!(PieceSet::parse(&pieces.to_text()).unwrap(), pieces);
+    }
+
+    #[test]
+    fn turned_by_itself() {
+        let pieces = PieceSet::parse("piece L 2\n#.\n##\n\npiece I 1\n...\n###\n...\n").unwrap();
+        let states = pieces.kinds[0].states.iter().map(cells).collect::<Vec<_>>();
+        assert_eq!(states, vec![vec!["#.", "##"], vec!["##", "#."], vec!["##", ".#"], vec![".#", "##"]]);
+        // it comes back to the way it started after two turns
+        let states = pieces.kinds[1].states.iter().map(cells).collect::<Vec<_>>();
+        assert_eq!(states, vec![vec!["...", "###", "..."], vec![".#.", ".#.", ".#."]]);
+        assert!(pieces.kinds[1].states.iter().flat_map(|state| state.iter().flatten()).all(|case| [0, 1].contains(case)));
+    }
+
+    #[test]
+    fn states_given() {
+        let pieces = PieceSet::parse("piece S 3\n.##\n##.\nstate\n#.\n##\n.#\n").unwrap();
+        let states = pieces.kinds[0].states.iter().map(cells).collect::<Vec<_>>();
+        // squared off to the widest state
+        assert_eq!(states, vec![vec![".##", "##.", "..."], vec!["#..", "##.", ".#."]]);
+    }
+
+    #[test]
+    fn spawn() {
+        let pieces = PieceSet::parse("piece A 1\nspawn 2\n##\npiece B 2\nspawn -1 3\n##\npiece C 3\n###\n").unwrap();
+        let spawns = pieces.kinds.iter().map(|kind| (kind.spawn_x, kind.spawn_y)).collect::<Vec<_>>();
+        // centred on a board 10 wide when it isn't given
+        assert_eq!(spawns, vec![(2, 0), (-1, 3), (3, 0)]);
+        assert!(PieceSet::parse("piece A 1\nspawn left\n##\n").is_err());
+        assert!(PieceSet::parse("piece A 1\nspawn 2 down\n##\n").is_err());
+    }
+
+    #[test]
+    fn colours() {
+        for colour in 1..=NUM_COLOURS {
+            assert_eq!(PieceSet::parse(&format!("piece A {}\n#\n", colour)).unwrap().kinds[0].colour, colour);
+        }
+        for colour in &["0", "8", "255", "-1", "red"] {
+            assert!(PieceSet::parse(&format!("piece A {}\n#\n", colour)).is_err(), "colour {}", colour);
+        }
+    }
+
+    #[test]
+    fn rejected() {
+        let invalid = [
+            "",
+            "// only a comment\n",
+            "##\n",
+            "piece A 1\n",
+            "piece A 1\n..\n..\n",
+            "piece A 1\n#\npiece A 2\n#\n",
+            "piece A 1\n##\n#\n",
+            "piece A 1\n#.\n###\n",
+            "piece A 1\n##\nstate\n#\n",
+            "piece A 1\n##\nstate\n",
+            "piece A 1\n#x\n",
+            "piece A 1\nspawn\n#\n",
+        ];
+        for content in invalid.iter() {
+            assert!(PieceSet::parse(content).is_err(), "{:?}", content);
+        }
+        assert!(PieceSet::parse(&format!("piece A 1\n{}\n", "#".repeat(MAX_WIDTH + 1))).is_err());
+    }
+}
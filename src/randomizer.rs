@@ -0,0 +1,52 @@
+extern crate rand;
+
+// what the previous piece is before there was one
+const NO_PIECE: u8 = u8::MAX;
+
+// xorshift64*, kept by hand rather than using rand's generators so the whole state can be saved and restored
+#[derive(Clone)]
+pub struct Randomizer {
+    pub state: u64,
+    pub previous: u8,
+}
+
+pub fn random_seed() -> u64 {
+    rand::random::<u64>()
+}
+
+impl Randomizer {
+    pub fn new(seed: u64) -> Randomizer {
+        Randomizer {
+            // xorshift never leaves the all-zero state
+            state: if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed },
+            previous: NO_PIECE,
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // the seed of another game that follows on from this one
+    pub fn next_seed(&mut self) -> u64 {
+        self.next()
+    }
+
+    // a number below `n`, for the column of the hole in a garbage line
+    pub fn next_below(&mut self, n: usize) -> usize {
+        ((self.next() >> 32) % n as u64) as usize
+    }
+
+    // returns the index of the next piece out of `num_pieces`, rerolling once if it is the same as the last one
+    pub fn next_piece(&mut self, num_pieces: u8) -> u8 {
+        let mut piece = (self.next() >> 32) % num_pieces as u64;
+        if piece as u8 == self.previous {
+            piece = (self.next() >> 32) % num_pieces as u64;
+        }
+        self.previous = piece as u8;
+        piece as u8
+    }
+}
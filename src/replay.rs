@@ -0,0 +1,525 @@
+use input::Action;
+use pieces::PieceSet;
+use tetris::{BoardSettings, Tetris, FPS};
+
+use std::io::{self, Read, Write};
+use std::fs::{self, File};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// version 1 had no board settings, its games were all played on the classic board, and versions 1 and 2 were
+// all played with the standard pieces
+pub const REPLAY_VERSION: u8 = 3;
+const REPLAY_MAGIC: &[u8; 3] = b"TTR";
+const REPLAY_DIR: &str = "replays";
+// more than any set of pieces should need, so a broken file can't ask for all the memory there is
+const MAX_PIECES_LENGTH: usize = 1 << 20;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    Marathon,
+}
+
+impl Mode {
+    fn code(&self) -> u8 {
+        match *self {
+            Mode::Marathon => 0,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Mode> {
+        match code {
+            0 => Some(Mode::Marathon),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Mode::Marathon => "marathon",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Mode> {
+        match name {
+            "marathon" => Some(Mode::Marathon),
+            _ => None
+        }
+    }
+}
+
+// every action the player made and the frame it was applied on, which together with the seed, the mode, the
+// board and the pieces is enough to play the game again
+//
+// The file starts with a header:
+//     "TTR", version: u8, mode: u8, board width: u8, board height: u8, hidden lines: u8,
+//     partial lock out: u8, pieces length: u32, pieces, seed: u64, player name length: u8, player name,
+//     score: u32, lines: u32, level: u32, frames: u32, number of events: u32
+// followed by the events, each one the number of frames since the previous event as a LEB128 varint
+// and the action code as a byte. Integers are little endian. The pieces are written out like a piece file,
+// or left empty for the standard ones.
+#[derive(Clone)]
+pub struct Replay {
+    pub mode: Mode,
+    pub settings: BoardSettings,
+    pub pieces: Arc<PieceSet>,
+    pub seed: u64,
+    pub player: String,
+    pub score: u32,
+    pub lines: u32,
+    pub level: u32,
+    pub frames: u32,
+    pub events: Vec<(u32, Action)>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_u32(w: &mut dyn Write, value: u32) -> io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+fn read_u8(r: &mut dyn Read) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32(r: &mut dyn Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(r: &mut dyn Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn write_varint(w: &mut dyn Write, mut value: u32) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return w.write_all(&[byte])
+        }
+        w.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint(r: &mut dyn Read) -> io::Result<u32> {
+    let mut value = 0u32;
+    for shift in (0..35).step_by(7) {
+        let byte = read_u8(r)?;
+        // the fifth byte only has room for the top 4 bits
+        if shift == 28 && byte & 0x7f > 0x0f {
+            return Err(invalid("varint is too big"))
+        }
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value)
+        }
+    }
+    Err(invalid("varint is too long"))
+}
+
+// the name cut to the 255 bytes its length has room for, without splitting a character
+fn short_name(name: &str) -> &str {
+    let mut end = name.len().min(u8::MAX as usize);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    &name[..end]
+}
+
+impl Replay {
+    pub fn new(mode: Mode, settings: BoardSettings, pieces: Arc<PieceSet>, seed: u64, player: &str) -> Replay {
+        Replay {
+            mode,
+            settings,
+            pieces,
+            seed,
+            player: short_name(player).to_string(),
+            score: 0,
+            lines: 0,
+            level: 1,
+            frames: 0,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, frame: u32, action: Action) {
+        self.events.push((frame, action));
+    }
+
+    // stores the final result of the game in the header
+    pub fn finish(&mut self, tetris: &Tetris) {
+        self.score = tetris.score;
+        self.lines = tetris.num_lines;
+        self.level = tetris.current_level;
+        self.frames = tetris.frame;
+    }
+
+    pub fn write(&self, w: &mut dyn Write) -> io::Result<()> {
+        let player = short_name(&self.player).as_bytes();
+
+        w.write_all(REPLAY_MAGIC)?;
+        w.write_all(&[REPLAY_VERSION, self.mode.code()])?;
+        let settings = &self.settings;
+        w.write_all(&[settings.width as u8, settings.height as u8, settings.hidden as u8,
+            settings.partial_lock_out as u8])?;
+        let pieces = if self.pieces.is_standard() { String::new() } else { self.pieces.to_text() };
+        write_u32(w, pieces.len() as u32)?;
+        w.write_all(pieces.as_bytes())?;
+        w.write_all(&self.seed.to_le_bytes())?;
+        w.write_all(&[player.len() as u8])?;
+        w.write_all(player)?;
+        write_u32(w, self.score)?;
+        write_u32(w, self.lines)?;
+        write_u32(w, self.level)?;
+        write_u32(w, self.frames)?;
+        write_u32(w, self.events.len() as u32)?;
+
+        let mut previous_frame = 0;
+        for &(frame, action) in &self.events {
+            write_varint(w, frame - previous_frame)?;
+            w.write_all(&[action.code()])?;
+            previous_frame = frame;
+        }
+        Ok(())
+    }
+
+    pub fn read(r: &mut dyn Read) -> io::Result<Replay> {
+        let mut magic = [0; 3];
+        r.read_exact(&mut magic)?;
+        if &magic != REPLAY_MAGIC {
+            return Err(invalid("not a replay file"))
+        }
+        let version = read_u8(r)?;
+        if version == 0 || version > REPLAY_VERSION {
+            return Err(invalid(&format!("unsupported replay version {}", version)))
+        }
+        let mode = Mode::from_code(read_u8(r)?).ok_or_else(|| invalid("unknown game mode"))?;
+        let settings = if version >= 2 {
+            let mut board = [0; 4];
+            r.read_exact(&mut board)?;
+            let spec = format!("{}x{}+{}", board[0], board[1], board[2]);
+            let mut settings = BoardSettings::parse(&spec).ok_or_else(|| invalid("unsupported board size"))?;
+            settings.partial_lock_out = board[3] != 0;
+            settings
+        } else {
+            BoardSettings::CLASSIC
+        };
+        let mut pieces = Vec::new();
+        if version >= 3 {
+            let length = read_u32(r)? as usize;
+            if length > MAX_PIECES_LENGTH {
+                return Err(invalid("the pieces are too long"))
+            }
+            pieces.resize(length, 0);
+            r.read_exact(&mut pieces)?;
+        }
+        let pieces = if pieces.is_empty() {
+            PieceSet::standard()
+        } else {
+            PieceSet::parse(&String::from_utf8_lossy(&pieces)).map_err(|e| invalid(&format!("invalid pieces, {}", e)))?
+        };
+        let seed = read_u64(r)?;
+        let mut player = vec![0; read_u8(r)? as usize];
+        r.read_exact(&mut player)?;
+
+        let mut replay = Replay::new(mode, settings, Arc::new(pieces), seed, &String::from_utf8_lossy(&player));
+        replay.score = read_u32(r)?;
+        replay.lines = read_u32(r)?;
+        replay.level = read_u32(r)?;
+        replay.frames = read_u32(r)?;
+
+        let num_events = read_u32(r)?;
+        let mut frame = 0u32;
+        for _ in 0..num_events {
+            frame = frame.checked_add(read_varint(r)?).ok_or_else(|| invalid("frame number overflows"))?;
+            let action = Action::from_code(read_u8(r)?).ok_or_else(|| invalid("unknown action"))?;
+            replay.record(frame, action);
+        }
+        Ok(replay)
+    }
+
+    pub fn save(&self, file_name: &str) -> io::Result<()> {
+        let mut buf = Vec::new();
+        self.write(&mut buf)?;
+        File::create(file_name)?.write_all(&buf)
+    }
+
+    pub fn load(file_name: &str) -> io::Result<Replay> {
+        let mut buf = Vec::new();
+        File::open(file_name)?.read_to_end(&mut buf)?;
+        Replay::read(&mut buf.as_slice())
+    }
+}
+
+// writes a finished game's replay to the replays directory, returning where it was saved
+pub fn save_replay(replay: &Replay) -> io::Result<String> {
+    fs::create_dir_all(REPLAY_DIR)?;
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let file_name = format!("{}/{}-{}.ttr", REPLAY_DIR, timestamp, replay.score);
+    replay.save(&file_name)?;
+    Ok(file_name)
+}
+
+// how often the player keeps a copy of the game, so seeking only re-simulates a few seconds
+const SNAPSHOT_INTERVAL: u32 = 5 * FPS;
+
+// re-simulates a replay frame by frame through the game logic
+pub struct ReplayPlayer {
+    pub replay: Replay,
+    pub tetris: Tetris,
+    next_event: usize,
+    // the game and the index of the next event at every multiple of SNAPSHOT_INTERVAL reached so far
+    snapshots: Vec<(Tetris, usize)>,
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay) -> ReplayPlayer {
+        let tetris = Tetris::with_settings(replay.seed, replay.settings, replay.pieces.clone());
+        ReplayPlayer {
+            snapshots: vec![(tetris.clone(), 0)],
+            replay,
+            tetris,
+            next_event: 0,
+        }
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.tetris.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.tetris.game_over || self.tetris.frame > self.replay.frames
+    }
+
+    // the actions that were applied on the given frame
+    pub fn actions_at(&self, frame: u32) -> Vec<Action> {
+        let start = self.replay.events.iter().position(|&(f, _)| f >= frame).unwrap_or(self.replay.events.len());
+        self.replay.events[start..].iter().take_while(|&&(f, _)| f == frame).map(|&(_, action)| action).collect()
+    }
+
+    // advances by one frame, returning false once the game is over
+    pub fn step(&mut self) -> bool {
+        if self.is_finished() {
+            return false
+        }
+
+        let frame = self.tetris.frame;
+        if frame.is_multiple_of(SNAPSHOT_INTERVAL) && self.snapshots.len() as u32 <= frame / SNAPSHOT_INTERVAL {
+            self.snapshots.push((self.tetris.clone(), self.next_event));
+        }
+
+        let mut actions = Vec::new();
+        while self.next_event < self.replay.events.len() && self.replay.events[self.next_event].0 == frame {
+            actions.push(self.replay.events[self.next_event].1);
+            self.next_event += 1;
+        }
+        self.tetris.step(&actions);
+        // a game that is over doesn't advance its frame counter
+        !self.tetris.game_over
+    }
+
+    pub fn seek(&mut self, frame: u32) {
+        let index = ((frame / SNAPSHOT_INTERVAL) as usize).min(self.snapshots.len() - 1);
+        let (ref tetris, next_event) = self.snapshots[index];
+        self.tetris = tetris.clone();
+        self.next_event = next_event;
+        while self.tetris.frame < frame && self.step() {}
+    }
+}
+
+// re-simulates the replay and checks the result matches the one stored in its header
+pub fn verify_replay(replay: &Replay) -> Result<(), String> {
+    let mut player = ReplayPlayer::new(replay.clone());
+    while player.step() {}
+
+    let tetris = &player.tetris;
+    if !tetris.game_over {
+        return Err(format!("the game is still going after the recorded {} frames", replay.frames))
+    }
+    if tetris.frame != replay.frames {
+        return Err(format!("the game ended on frame {} instead of frame {}", tetris.frame, replay.frames))
+    }
+    if replay.events.last().is_some_and(|&(frame, _)| frame >= replay.frames) {
+        return Err("there are inputs after the end of the game".to_string())
+    }
+
+    let mut mismatches = Vec::new();
+    if tetris.score != replay.score {
+        mismatches.push(format!("score is {} instead of {}", tetris.score, replay.score));
+    }
+    if tetris.num_lines != replay.lines {
+        mismatches.push(format!("lines are {} instead of {}", tetris.num_lines, replay.lines));
+    }
+    if tetris.current_level != replay.level {
+        mismatches.push(format!("level is {} instead of {}", tetris.current_level, replay.level));
+    }
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(mismatches.join(", "))
+    }
+}
+
+// loads the replay in the file and checks it, for the `verify` commands of the front-ends: the line they print and the
+// status they exit with, 1 if the replay doesn't match its header and 2 if it can't be loaded
+pub fn verify_file(file_name: &str) -> (String, i32) {
+    let replay = match Replay::load(file_name) {
+        Ok(replay) => replay,
+        Err(e) => return (format!("Failed to load {}: {}", file_name, e), 2)
+    };
+    match verify_replay(&replay) {
+        Ok(()) => (format!("{}: OK ({}, score {}, {} lines, level {})", file_name, replay.player, replay.score,
+            replay.lines, replay.level), 0),
+        Err(e) => (format!("{}: MISMATCH, {}", file_name, e), 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_replay(pieces: PieceSet) -> Replay {
+        let mut settings = BoardSettings::GUIDELINE;
+        settings.partial_lock_out = true;
+        let mut replay = Replay::new(Mode::Marathon, settings, Arc::new(pieces), 0x0123_4567_89ab_cdef, "player 1");
+        // gaps of every varint length, and several actions on the same frame
+        let events = [(0, Action::MoveLeft), (0, Action::RotateClockwise), (127, Action::HardDrop), (128, Action::Hold),
+            (16_511, Action::SoftDrop), (2_113_663, Action::RotateCounterClockwise), (u32::MAX, Action::MoveRight)];
+        for &(frame, action) in events.iter() {
+            replay.record(frame, action);
+        }
+        replay.score = 12_345;
+        replay.lines = 67;
+        replay.level = 4;
+        replay.frames = 89_012;
+        replay
+    }
+
+    fn to_bytes(replay: &Replay) -> Vec<u8> {
+        let mut buf = Vec::new();
+        replay.write(&mut buf).unwrap();
+        buf
+    }
+
+    fn assert_same(read: &Replay, written: &Replay) {
+        assert_eq!(read.mode, written.mode);
+        assert_eq!(read.settings, written.settings);
+        assert_eq!(read.pieces, written.pieces);
+        assert_eq!(read.seed, written.seed);
+        assert_eq!(read.player, written.player);
+        assert_eq!((read.score, read.lines, read.level, read.frames),
+            (written.score, written.lines, written.level, written.frames));
+        assert_eq!(read.events, written.events);
+    }
+
+    #[test]
+    fn round_trip() {
+        let replay = sample_replay(PieceSet::standard());
+        let buf = to_bytes(&replay);
+        assert_eq!(&buf[..3], REPLAY_MAGIC);
+        assert_eq!(buf[3], REPLAY_VERSION);
+        assert_same(&Replay::read(&mut buf.as_slice()).unwrap(), &replay);
+    }
+
+    #[test]
+    fn round_trip_with_other_pieces() {
+        let pieces = PieceSet::parse(include_str!("../pieces/triominoes.txt")).unwrap();
+        let replay = sample_replay(pieces);
+        let read = Replay::read(&mut to_bytes(&replay).as_slice()).unwrap();
+        assert!(!read.pieces.is_standard());
+        assert_same(&read, &replay);
+    }
+
+    #[test]
+    fn varints() {
+        // each value with the bytes it takes, seven bits to a byte
+        let values = [(0, 1), (1, 1), (127, 1), (128, 2), (16_383, 2), (16_384, 3), (2_097_151, 3), (2_097_152, 4),
+            (u32::MAX, 5)];
+        for &(value, length) in values.iter() {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, value).unwrap();
+            assert_eq!(buf.len(), length, "{} is written in {} bytes", value, length);
+            assert_eq!(read_varint(&mut buf.as_slice()).unwrap(), value);
+        }
+        // a sixth byte would hold more than 32 bits, and so would more than 4 bits in the fifth
+        assert!(read_varint(&mut [0x80u8, 0x80, 0x80, 0x80, 0x80, 0x00].as_ref()).is_err());
+        assert!(read_varint(&mut [0xffu8, 0xff, 0xff, 0xff, 0x1f].as_ref()).is_err());
+        assert!(read_varint(&mut [0x80u8, 0x80, 0x80, 0x80, 0x10].as_ref()).is_err());
+        assert_eq!(read_varint(&mut [0xffu8, 0xff, 0xff, 0xff, 0x0f].as_ref()).unwrap(), u32::MAX);
+    }
+
+    #[test]
+    fn long_names() {
+        // 'é' takes two bytes, so 255 bytes would end halfway through one
+        let name = "é".repeat(200);
+        let replay = Replay::new(Mode::Marathon, BoardSettings::default(), Arc::new(PieceSet::standard()), 1, &name);
+        assert_eq!(replay.player, "é".repeat(127));
+        let mut longer = replay.clone();
+        longer.player += " + AI";
+        let read = Replay::read(&mut to_bytes(&replay).as_slice()).unwrap();
+        assert_eq!(read.player, "é".repeat(127));
+        let read = Replay::read(&mut to_bytes(&longer).as_slice()).unwrap();
+        assert_eq!(read.player, "é".repeat(127) + " ");
+        assert_eq!(short_name("player"), "player");
+    }
+
+    #[test]
+    fn truncated() {
+        let buf = to_bytes(&sample_replay(PieceSet::standard()));
+        for length in 0..buf.len() {
+            assert!(Replay::read(&mut &buf[..length]).is_err(), "read a replay cut at {} of {} bytes", length, buf.len());
+        }
+    }
+
+    #[test]
+    fn invalid_header() {
+        let buf = to_bytes(&sample_replay(PieceSet::standard()));
+        let mut magic = buf.clone();
+        magic[0] = b'X';
+        assert!(Replay::read(&mut magic.as_slice()).is_err());
+        let mut version = buf.clone();
+        version[3] = REPLAY_VERSION + 1;
+        assert!(Replay::read(&mut version.as_slice()).is_err());
+        let mut mode = buf;
+        mode[4] = 0xff;
+        assert!(Replay::read(&mut mode.as_slice()).is_err());
+    }
+
+    #[test]
+    fn verify_files() {
+        // a game of hard drops, which tops out soon enough
+        let pieces = Arc::new(PieceSet::standard());
+        let mut tetris = Tetris::with_settings(7, BoardSettings::default(), pieces.clone());
+        let mut replay = Replay::new(Mode::Marathon, BoardSettings::default(), pieces, 7, "player");
+        loop {
+            let frame = tetris.frame;
+            tetris.step(&[Action::HardDrop]);
+            if tetris.frame == frame {
+                break
+            }
+            replay.record(frame, Action::HardDrop);
+        }
+        replay.finish(&tetris);
+
+        let file_name = std::env::temp_dir().join(format!("tetris-verify-{}.ttr", std::process::id()));
+        let file_name = file_name.to_str().unwrap();
+        let status = |replay: &Replay| {
+            let mut file = File::create(file_name).unwrap();
+            replay.write(&mut file).unwrap();
+            verify_file(file_name).1
+        };
+        let matched = status(&replay);
+        replay.score += 1;
+        let mismatched = status(&replay);
+        fs::remove_file(file_name).unwrap();
+        assert_eq!((matched, mismatched, verify_file(file_name).1), (0, 1, 2));
+    }
+}
@@ -0,0 +1,388 @@
+// A battle royale between up to eight players: the lines each player's clears send go to the opponents picked by the
+// way they target, and the last player left standing wins. Whoever sent a player the garbage they topped out under
+// knocks them out and takes a badge for it, along with the badges the player had, and badges make attacks bigger.
+// Once a match has gone on for a while every attack keeps getting bigger, so players who can keep up with each other
+// still come to an end.
+use randomizer::Randomizer;
+
+pub const MAX_PLAYERS: usize = 8;
+// the badges needed for each quarter added to a player's attacks
+const BADGE_STEPS: [u32; 4] = [2, 6, 14, 30];
+// lines added to an attack on the players targeting the attacker, by how many of them there are
+const ATTACKER_BONUS: [u32; 8] = [0, 0, 1, 3, 5, 7, 9, 9];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Targeting {
+    // a player picked at random, another one after every attack
+    Random,
+    // every player targeting this one, each of them getting the whole attack
+    Attackers,
+    // the player closest to topping out
+    KOs,
+    // the player with the most badges
+    Badges,
+}
+
+pub const TARGETINGS: [Targeting; 4] = [Targeting::Random, Targeting::Attackers, Targeting::KOs, Targeting::Badges];
+
+impl Targeting {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Targeting::Random => "random",
+            Targeting::Attackers => "attackers",
+            Targeting::KOs => "kos",
+            Targeting::Badges => "badges",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Targeting> {
+        TARGETINGS.iter().find(|targeting| targeting.name() == name).cloned()
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Player {
+    pub alive: bool,
+    pub targeting: Targeting,
+    // the opponent the player's attacks go to, or go to when nobody is targeting them with `Attackers`
+    pub target: Option<usize>,
+    pub badges: u32,
+    pub kos: u32,
+    // how close the player is to topping out: the height of their stack and the garbage on its way to it
+    pub danger: u32,
+    // where the player finished, 1 for the winner, once they have
+    pub place: Option<usize>,
+    // lines the player has sent
+    pub sent: u32,
+    // the last opponent to send the player garbage, who knocks them out if they top out
+    pub last_attacker: Option<usize>,
+}
+
+impl Player {
+    pub fn new() -> Player {
+        Player {alive: true, targeting: Targeting::Random, target: None, badges: 0, kos: 0, danger: 0, place: None, sent: 0,
+            last_attacker: None}
+    }
+}
+
+impl Default for Player {
+    fn default() -> Player {
+        Player::new()
+    }
+}
+
+// an attack on one opponent, with the column of the gap in its lines
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Attack {
+    pub to: usize,
+    pub lines: u32,
+    pub hole: usize,
+}
+
+// the fraction added to the attacks of a player with the badges, in quarters
+pub fn badge_bonus(badges: u32) -> u32 {
+    BADGE_STEPS.iter().filter(|step| badges >= **step).count() as u32
+}
+
+pub struct Royale {
+    pub players: Vec<Player>,
+    // how big every attack is made, in percent
+    pub multiplier: u32,
+    // the width of the boards, for the holes in the garbage
+    width: usize,
+    // random targets and the holes of the garbage come from here
+    randomizer: Randomizer,
+}
+
+impl Royale {
+    pub fn new(seed: u64, num_players: usize, width: usize) -> Royale {
+        let mut royale = Royale {players: vec![Player::new(); num_players], multiplier: 100, width,
+            randomizer: Randomizer::new(seed)};
+        royale.retarget_all();
+        royale
+    }
+
+    pub fn num_alive(&self) -> usize {
+        self.players.iter().filter(|player| player.alive).count()
+    }
+
+    // the players whose attacks are going to the player
+    pub fn attackers(&self, player: usize) -> Vec<usize> {
+        (0..self.players.len()).filter(|&other| self.players[other].alive && self.players[other].target == Some(player)).collect()
+    }
+
+    pub fn set_targeting(&mut self, player: usize, targeting: Targeting) {
+        self.players[player].targeting = targeting;
+        self.retarget(player, true);
+    }
+
+    pub fn set_danger(&mut self, player: usize, danger: u32) {
+        if self.players[player].danger != danger {
+            self.players[player].danger = danger;
+            self.retarget_all();
+        }
+    }
+
+    // picks the target of every player again, keeping random ones while their targets are still in
+    fn retarget_all(&mut self) {
+        for player in 0..self.players.len() {
+            self.retarget(player, false);
+        }
+    }
+
+    fn retarget(&mut self, player: usize, reroll: bool) {
+        if !self.players[player].alive {
+            self.players[player].target = None;
+            return
+        }
+        let opponents = (0..self.players.len()).filter(|&other| other != player && self.players[other].alive).collect::<Vec<_>>();
+        if opponents.is_empty() {
+            self.players[player].target = None;
+            return
+        }
+        let target = match self.players[player].targeting {
+            Targeting::Random | Targeting::Attackers => match self.players[player].target {
+                Some(target) if !reroll && opponents.contains(&target) => target,
+                _ => opponents[self.randomizer.next_below(opponents.len())]
+            },
+            // the earliest player wins a tie, so every side agrees on it
+            Targeting::KOs => *opponents.iter().rev().max_by_key(|&&other| self.players[other].danger).unwrap(),
+            Targeting::Badges => *opponents.iter().rev()
+                .max_by_key(|&&other| (self.players[other].badges, self.players[other].danger)).unwrap(),
+        };
+        self.players[player].target = Some(target);
+    }
+
+    // the lines a player's clears sent, after cancelling their own garbage, made bigger by their badges and sent on
+    pub fn attack(&mut self, from: usize, lines: u32) -> Vec<Attack> {
+        if lines == 0 || !self.players[from].alive {
+            return Vec::new()
+        }
+        // the lines come from the network, so a silly number mustn't overflow
+        let lines = lines.saturating_mul(4 + badge_bonus(self.players[from].badges)).saturating_mul(self.multiplier) / 400;
+        if lines == 0 {
+            return Vec::new()
+        }
+        let attackers = self.attackers(from);
+        let targets = match self.players[from].targeting {
+            Targeting::Attackers if !attackers.is_empty() => {
+                let bonus = ATTACKER_BONUS[attackers.len().min(ATTACKER_BONUS.len() - 1)];
+                attackers.into_iter().map(|to| (to, lines.saturating_add(bonus))).collect()
+            }
+            _ => self.players[from].target.map(|to| (to, lines)).into_iter().collect::<Vec<_>>()
+        };
+
+        let mut attacks = Vec::new();
+        for (to, lines) in targets {
+            let hole = self.randomizer.next_below(self.width);
+            self.players[to].last_attacker = Some(from);
+            self.players[from].sent = self.players[from].sent.saturating_add(lines);
+            attacks.push(Attack {to, lines, hole});
+        }
+        if self.players[from].targeting == Targeting::Random {
+            self.retarget(from, true);
+        }
+        attacks
+    }
+
+    // takes the player out of the game, returning who knocked them out, if anyone did
+    pub fn knock_out(&mut self, victim: usize) -> Option<usize> {
+        if !self.players[victim].alive {
+            return None
+        }
+        self.players[victim].place = Some(self.num_alive());
+        self.players[victim].alive = false;
+        let by = self.players[victim].last_attacker.filter(|&by| self.players[by].alive);
+        if let Some(by) = by {
+            let badges = self.players[victim].badges;
+            self.players[by].kos += 1;
+            self.players[by].badges += 1 + badges;
+        }
+        if let Some(winner) = self.winner() {
+            self.players[winner].place = Some(1);
+        }
+        self.retarget_all();
+        by
+    }
+
+    // the last player standing, once there is only one
+    pub fn winner(&self) -> Option<usize> {
+        match self.num_alive() {
+            1 => self.players.iter().position(|player| player.alive),
+            _ => None
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.num_alive() <= 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a match of four on boards 10 wide, everyone targeting the same way
+    fn royale(targeting: Targeting) -> Royale {
+        let mut royale = Royale::new(7, 4, 10);
+        for player in 0..4 {
+            royale.set_targeting(player, targeting);
+        }
+        royale
+    }
+
+    fn targets(royale: &Royale) -> Vec<Option<usize>> {
+        royale.players.iter().map(|player| player.target).collect()
+    }
+
+    #[test]
+    fn badge_steps() {
+        let table = [(0, 0), (1, 0), (2, 1), (5, 1), (6, 2), (13, 2), (14, 3), (29, 3), (30, 4), (1000, 4)];
+        for &(badges, bonus) in &table {
+            assert_eq!(badge_bonus(badges), bonus, "{} badges", badges);
+        }
+    }
+
+    #[test]
+    fn random_targets() {
+        let mut royale = royale(Targeting::Random);
+        for _ in 0..20 {
+            for (player, target) in targets(&royale).into_iter().enumerate() {
+                let target = target.unwrap();
+                assert!(target != player && target < 4);
+            }
+            royale.attack(0, 2);
+        }
+        // the same seed picks the same targets
+        assert_eq!(targets(&Royale::new(7, 4, 10)), targets(&Royale::new(7, 4, 10)));
+
+        // a target that is knocked out is replaced, and the last player standing has nobody to target
+        let target = royale.players[1].target.unwrap();
+        royale.knock_out(target);
+        assert!(royale.players[1].target != Some(target));
+        assert_eq!(royale.players[target].target, None);
+        for player in 0..4 {
+            if player != 1 && player != target {
+                royale.knock_out(player);
+            }
+        }
+        assert_eq!(royale.players[1].target, None);
+        assert!(royale.attack(1, 4).is_empty());
+    }
+
+    #[test]
+    fn ko_targets() {
+        let mut royale = royale(Targeting::KOs);
+        // nobody is in danger, so everyone goes after the earliest player they can
+        assert_eq!(targets(&royale), vec![Some(1), Some(0), Some(0), Some(0)]);
+        royale.set_danger(2, 10);
+        assert_eq!(targets(&royale), vec![Some(2), Some(2), Some(0), Some(2)]);
+        // a tie goes to the earliest player
+        royale.set_danger(3, 10);
+        assert_eq!(targets(&royale), vec![Some(2), Some(2), Some(3), Some(2)]);
+        royale.set_danger(3, 11);
+        assert_eq!(targets(&royale), vec![Some(3), Some(3), Some(3), Some(2)]);
+    }
+
+    #[test]
+    fn badge_targets() {
+        let mut royale = royale(Targeting::Badges);
+        assert_eq!(targets(&royale), vec![Some(1), Some(0), Some(0), Some(0)]);
+        royale.players[3].badges = 2;
+        royale.set_danger(2, 10);
+        assert_eq!(targets(&royale), vec![Some(3), Some(3), Some(3), Some(2)]);
+        // with the badges tied, the player closer to topping out goes first
+        royale.players[2].badges = 2;
+        royale.set_danger(1, 5);
+        assert_eq!(targets(&royale), vec![Some(2), Some(2), Some(3), Some(2)]);
+    }
+
+    #[test]
+    fn attacker_bonus() {
+        let mut royale = royale(Targeting::KOs);
+        royale.set_targeting(0, Targeting::Attackers);
+        royale.set_danger(0, 10);
+        assert_eq!(royale.attackers(0), vec![1, 2, 3]);
+
+        // every attacker gets the whole attack, with lines added for there being three of them
+        let attacks = royale.attack(0, 4);
+        assert_eq!(attacks.iter().map(|attack| (attack.to, attack.lines)).collect::<Vec<_>>(), vec![(1, 7), (2, 7), (3, 7)]);
+        assert!(attacks.iter().all(|attack| attack.hole < 10));
+        assert_eq!(royale.players[0].sent, 21);
+        assert!((1..4).all(|player| royale.players[player].last_attacker == Some(0)));
+
+        // with one attacker there is nothing added
+        royale.set_targeting(2, Targeting::Random);
+        royale.set_targeting(3, Targeting::Random);
+        royale.players[2].target = Some(1);
+        royale.players[3].target = Some(1);
+        let attacks = royale.attack(0, 4);
+        assert_eq!(attacks.iter().map(|attack| (attack.to, attack.lines)).collect::<Vec<_>>(), vec![(1, 4)]);
+
+        // with none the attack goes to the player's own target
+        royale.players[1].target = Some(2);
+        let target = royale.players[0].target.unwrap();
+        let attacks = royale.attack(0, 4);
+        assert_eq!(attacks.iter().map(|attack| (attack.to, attack.lines)).collect::<Vec<_>>(), vec![(target, 4)]);
+    }
+
+    #[test]
+    fn attack_sizes() {
+        let mut royale = royale(Targeting::KOs);
+        let lines = |royale: &mut Royale, lines| royale.attack(0, lines).iter().map(|attack| attack.lines).sum::<u32>();
+        assert_eq!(lines(&mut royale, 4), 4);
+        assert_eq!(lines(&mut royale, 0), 0);
+        // badges add a quarter for each step reached, rounding down
+        royale.players[0].badges = 2;
+        assert_eq!(lines(&mut royale, 4), 5);
+        assert_eq!(lines(&mut royale, 3), 3);
+        royale.players[0].badges = 30;
+        assert_eq!(lines(&mut royale, 4), 8);
+        royale.players[0].badges = 0;
+        royale.multiplier = 150;
+        assert_eq!(lines(&mut royale, 4), 6);
+        royale.multiplier = 50;
+        assert!(royale.attack(0, 1).is_empty());
+        // lines out of a bad client don't overflow
+        royale.multiplier = 1000;
+        royale.players[0].badges = 30;
+        royale.players[0].sent = u32::MAX - 1;
+        assert_eq!(lines(&mut royale, u32::MAX), u32::MAX / 400);
+        assert_eq!(royale.players[0].sent, u32::MAX);
+        // the knocked out don't attack
+        royale.knock_out(0);
+        assert!(royale.attack(0, 4).is_empty());
+    }
+
+    #[test]
+    fn ko_credit() {
+        let mut royale = royale(Targeting::KOs);
+        royale.players[2].badges = 3;
+        royale.players[1].target = Some(2);
+        royale.attack(1, 2);
+        assert_eq!(royale.players[2].last_attacker, Some(1));
+
+        // the last attacker takes the victim's badges along with one for the KO
+        assert_eq!(royale.knock_out(2), Some(1));
+        assert_eq!((royale.players[1].kos, royale.players[1].badges), (1, 4));
+        assert_eq!((royale.players[2].alive, royale.players[2].place, royale.players[2].target), (false, Some(4), None));
+        assert_eq!(royale.knock_out(2), None);
+        assert_eq!(royale.players[2].place, Some(4));
+
+        // nobody gets the KO when the last attacker is already out, or there never was one
+        royale.attack(3, 2);
+        assert_eq!(royale.players[3].target, Some(0));
+        assert_eq!(royale.winner(), None);
+        assert_eq!(royale.knock_out(3), None);
+        assert_eq!(royale.players[3].place, Some(3));
+        assert!(!royale.is_over());
+        assert_eq!(royale.knock_out(0), None);
+        assert_eq!((royale.players[3].kos, royale.players[0].place), (0, Some(2)));
+
+        // the last player standing wins
+        assert!(royale.is_over());
+        assert_eq!(royale.winner(), Some(1));
+        assert_eq!(royale.players[1].place, Some(1));
+        assert_eq!(royale.players[1].target, None);
+    }
+}
@@ -0,0 +1,571 @@
+// Battle royale over the network. A server the players all connect to keeps the rules of the match: it picks who
+// each attack goes to, passes the garbage on, and keeps track of who knocked out whom. Every player plays their own
+// game on their own machine, sending the server the lines their clears send and their board whenever it changes, and
+// gets back the garbage sent to them and the boards and badges of everyone else.
+//
+// The protocol is lines of text over TCP:
+//     royale <version>                    both, first
+//     rules <seed> <garbage delay> <players> <you> <width> <height> <hidden> <partial lock out> <lines>
+//                                         the server, followed by that many lines of the piece set, none for the
+//                                         standard one; every player plays the same pieces
+//     joined <players>                    the server, whenever a player joins before the match starts
+//     start                               the server, once every player has joined
+//     state <danger> <board>              a player, when their board has changed, with the shown lines of it as the
+//                                         colours of their cells separated by "/", and their stack's height and the
+//                                         garbage on its way added together, for how close they are to topping out
+//     attack <lines>                      a player, for the lines their clears sent after cancelling their garbage
+//     target <targeting>                  a player, to change how they target: random, attackers, kos or badges
+//     over                                a player, once they have topped out
+//     board <player> <board>              the server, passing on the board of another player
+//     garbage <lines> <hole> <from>       the server, for garbage sent to the player
+//     player <player> <alive> <targeting> <target> <badges> <kos> <sent> <place>
+//                                         the server, whenever any of them change for a player, "-" for none
+//     ko <player> <by>                    the server, when a player is knocked out, "-" if nobody did it
+//     multiplier <percent>                the server, when attacks are made bigger
+//     end                                 the server, once there is a winner
+use board::MAX_WIDTH;
+use garbage::MAX_ATTACK;
+use input::Action;
+use net::{board_rules, handshake_reader, read_board_rules, read_handshake_line, send_line};
+use pieces::PieceSet;
+use randomizer::Randomizer;
+use royale::{Player, Royale, Targeting, MAX_PLAYERS};
+use tetris::{BoardSettings, Tetris};
+
+use std::io::{BufRead, BufReader};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const ROYALE_PROTOCOL_VERSION: u32 = 1;
+pub const DEFAULT_ROYALE_PORT: u16 = 7476;
+// a player that can't be written to for this long is taken out rather than holding up everyone else
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+pub const DEFAULT_MARGIN: Duration = Duration::from_secs(120);
+// once the margin is over, attacks grow by this much more, in percent, every interval
+const MARGIN_STEP: u32 = 25;
+const MARGIN_INTERVAL: Duration = Duration::from_secs(15);
+
+// reads the lines the other side sends on a thread of its own, tagged with who sent them, until the connection ends
+fn read_lines<T: Clone + Send + 'static>(mut reader: BufReader<TcpStream>, tag: T, sender: Sender<(T, Result<String, String>)>) {
+    thread::spawn(move || loop {
+        let mut line = String::new();
+        let message = match reader.read_line(&mut line) {
+            Ok(0) => Err("the connection was closed".to_string()),
+            Ok(_) => Ok(line.trim().to_string()),
+            Err(e) => Err(format!("the connection broke: {}", e))
+        };
+        let closed = message.is_err();
+        if sender.send((tag.clone(), message)).is_err() || closed {
+            return
+        }
+    });
+}
+
+fn exchange_hello(stream: &mut TcpStream, reader: &mut BufReader<TcpStream>) -> Result<(), String> {
+    send_line(stream, &format!("royale {}", ROYALE_PROTOCOL_VERSION))?;
+    let line = read_handshake_line(reader)?;
+    match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["royale", version] if *version == ROYALE_PROTOCOL_VERSION.to_string() => Ok(()),
+        ["royale", version] => Err(format!("the other side speaks version {} of the protocol, not {}", version,
+            ROYALE_PROTOCOL_VERSION)),
+        _ => Err(format!("the other side isn't a battle royale, it said \"{}\"", line))
+    }
+}
+
+// the shown lines of a board, the colours of each line's cells as digits
+pub fn board_to_text(tetris: &Tetris) -> String {
+    tetris.game_map.iter().skip(tetris.settings.hidden)
+        .map(|line| line.iter().map(|case| ::std::char::from_digit(*case as u32, 36).unwrap_or('0')).collect::<String>())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub fn board_from_text(text: &str) -> Option<Vec<Vec<u8>>> {
+    let lines = text.split('/')
+        .map(|line| line.chars().map(|case| case.to_digit(36).map(|case| case as u8)).collect::<Option<Vec<_>>>())
+        .collect::<Option<Vec<_>>>()?;
+    if lines.iter().any(|line| line.is_empty() || line.len() > MAX_WIDTH) {
+        return None
+    }
+    Some(lines)
+}
+
+// how close the game is to topping out
+fn danger(tetris: &Tetris) -> u32 {
+    tetris.game_map.bits.column_heights().into_iter().max().unwrap_or(0) + tetris.garbage.total()
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map_or("-".to_string(), |value| value.to_string())
+}
+
+fn parse_optional(word: &str) -> Option<Option<usize>> {
+    match word {
+        "-" => Some(None),
+        word => word.parse().ok().map(Some)
+    }
+}
+
+fn player_line(index: usize, player: &Player) -> String {
+    format!("player {} {} {} {} {} {} {} {}", index, player.alive as u8, player.targeting.name(), optional(player.target),
+        player.badges, player.kos, player.sent, optional(player.place))
+}
+
+fn parse_player(words: &[&str]) -> Option<Player> {
+    if words.len() != 7 {
+        return None
+    }
+    let number = |word: &str| word.parse::<u32>().ok();
+    Some(Player {
+        alive: words[0] == "1",
+        targeting: Targeting::from_name(words[1])?,
+        target: parse_optional(words[2])?,
+        badges: number(words[3])?,
+        kos: number(words[4])?,
+        danger: 0,
+        place: parse_optional(words[6])?,
+        sent: number(words[5])?,
+        last_attacker: None,
+    })
+}
+
+pub struct RoyaleServer {
+    pub royale: Royale,
+    streams: Vec<TcpStream>,
+    messages: Receiver<(usize, Result<String, String>)>,
+    // what every player was last told about each player
+    told: Vec<String>,
+    // how long the match goes on before attacks start to grow
+    pub margin: Duration,
+    started: Instant,
+    // the most lines a player can say their clears sent, more than that being nonsense
+    max_attack: u32,
+}
+
+impl RoyaleServer {
+    // waits for the players to join on the port, telling each of them the rules as they come, and starts the match
+    pub fn host(port: u16, num_players: usize, seed: u64, garbage_delay: u32, settings: BoardSettings, pieces: &PieceSet,
+        log: &mut dyn FnMut(String)) -> Result<RoyaleServer, String> {
+        if !(2..=MAX_PLAYERS).contains(&num_players) {
+            return Err(format!("a battle royale is for 2 to {} players", MAX_PLAYERS))
+        }
+        let listener = TcpListener::bind(("0.0.0.0", port)).map_err(|e| format!("failed to listen on port {}: {}", port, e))?;
+        let (sender, receiver) = channel();
+        let (board, piece_lines) = board_rules(&settings, pieces);
+        let mut streams: Vec<TcpStream> = Vec::new();
+        while streams.len() < num_players {
+            let (mut stream, address) = listener.accept().map_err(|e| format!("failed to accept a player: {}", e))?;
+            let player = streams.len();
+            // a player that fails to join doesn't stop the others from playing
+            let joined = handshake_reader(&stream).and_then(|mut reader| {
+                exchange_hello(&mut stream, &mut reader)?;
+                send_line(&mut stream, &format!("rules {} {} {} {} {}", seed, garbage_delay, num_players, player, board))?;
+                for line in &piece_lines {
+                    send_line(&mut stream, line)?;
+                }
+                stream.set_read_timeout(None).map_err(|e| e.to_string())?;
+                stream.set_write_timeout(Some(WRITE_TIMEOUT)).map_err(|e| e.to_string())?;
+                stream.set_nodelay(true).map_err(|e| e.to_string())?;
+                Ok(reader)
+            });
+            match joined {
+                Ok(reader) => {
+                    read_lines(reader, player, sender.clone());
+                    streams.push(stream);
+                    log(format!("Player {} joined from {} ({}/{})", player + 1, address, streams.len(), num_players));
+                    for stream in &mut streams {
+                        let _ = send_line(stream, &format!("joined {}", player + 1));
+                    }
+                }
+                Err(e) => log(format!("{} failed to join: {}", address, e))
+            }
+        }
+
+        // the pieces are the same for everyone, the targets and holes are the server's own
+        let royale = Royale::new(Randomizer::new(seed).next_seed(), num_players, settings.width);
+        let mut server = RoyaleServer {royale, streams, messages: receiver, told: vec![String::new(); num_players],
+            margin: DEFAULT_MARGIN, started: Instant::now(), max_attack: MAX_ATTACK.max(settings.total_height() as u32)};
+        server.broadcast("start");
+        server.tell_players();
+        Ok(server)
+    }
+
+    // passes the attacks and boards between the players until only one of them is left
+    pub fn run(&mut self, log: &mut dyn FnMut(String)) {
+        while !self.royale.is_over() {
+            self.update_multiplier(log);
+            // the attacks grow even while nobody is saying anything
+            let (player, message) = match self.messages.recv_timeout(Duration::from_secs(1)) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break
+            };
+            let line = match message {
+                Ok(line) => line,
+                Err(e) => {
+                    self.knock_out(player, &format!("left: {}", e), log);
+                    continue
+                }
+            };
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            match words.as_slice() {
+                ["state", danger, board] => match (danger.parse(), board_from_text(board)) {
+                    (Ok(danger), Some(_)) => {
+                        self.royale.set_danger(player, danger);
+                        let line = format!("board {} {}", player, board);
+                        self.broadcast_others(player, &line);
+                    }
+                    _ => self.knock_out(player, &format!("sent a board that makes no sense: \"{}\"", line), log)
+                },
+                ["attack", lines] => match lines.parse::<u32>().ok().filter(|lines| *lines <= self.max_attack) {
+                    Some(lines) => {
+                        for attack in self.royale.attack(player, lines) {
+                            let line = format!("garbage {} {} {}", attack.lines, attack.hole, player);
+                            self.send(attack.to, &line);
+                        }
+                    }
+                    None => self.knock_out(player, &format!("sent an attack that makes no sense: \"{}\"", line), log)
+                },
+                ["target", targeting] => match Targeting::from_name(targeting) {
+                    Some(targeting) => self.royale.set_targeting(player, targeting),
+                    None => self.knock_out(player, &format!("can't target by \"{}\"", targeting), log)
+                },
+                ["over"] => self.knock_out(player, "topped out", log),
+                _ => self.knock_out(player, &format!("sent something that makes no sense: \"{}\"", line), log)
+            }
+            self.tell_players();
+        }
+
+        self.tell_players();
+        if let Some(winner) = self.royale.winner() {
+            log(format!("Player {} won", winner + 1));
+        }
+        self.broadcast("end");
+    }
+
+    fn update_multiplier(&mut self, log: &mut dyn FnMut(String)) {
+        let overtime = match self.started.elapsed().checked_sub(self.margin) {
+            Some(overtime) => overtime,
+            None => return
+        };
+        let multiplier = 100 + MARGIN_STEP * (overtime.as_secs() / MARGIN_INTERVAL.as_secs() + 1) as u32;
+        if multiplier != self.royale.multiplier {
+            self.royale.multiplier = multiplier;
+            log(format!("Attacks are {}% as big", multiplier));
+            self.broadcast(&format!("multiplier {}", multiplier));
+        }
+    }
+
+    fn knock_out(&mut self, victim: usize, reason: &str, log: &mut dyn FnMut(String)) {
+        if !self.royale.players[victim].alive {
+            return
+        }
+        let by = self.royale.knock_out(victim);
+        let place = self.royale.players[victim].place.unwrap_or(0);
+        match by {
+            Some(by) => log(format!("Player {} {} in place {}, knocked out by player {}", victim + 1, reason, place, by + 1)),
+            None => log(format!("Player {} {} in place {}", victim + 1, reason, place))
+        }
+        self.broadcast(&format!("ko {} {}", victim, optional(by)));
+    }
+
+    // sends the players everything about the others that has changed since they were last told
+    fn tell_players(&mut self) {
+        for player in 0..self.royale.players.len() {
+            let line = player_line(player, &self.royale.players[player]);
+            if line != self.told[player] {
+                self.broadcast(&line);
+                self.told[player] = line;
+            }
+        }
+    }
+
+    fn broadcast(&mut self, line: &str) {
+        for player in 0..self.streams.len() {
+            self.send(player, line);
+        }
+    }
+
+    fn broadcast_others(&mut self, from: usize, line: &str) {
+        for player in (0..self.streams.len()).filter(|player| *player != from) {
+            self.send(player, line);
+        }
+    }
+
+    // a player that can't be written to has left, which their reader finds out as well
+    fn send(&mut self, player: usize, line: &str) {
+        if send_line(&mut self.streams[player], line).is_err() {
+            let _ = self.streams[player].shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl Drop for RoyaleServer {
+    fn drop(&mut self) {
+        for stream in &self.streams {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+// a player's end of the match, with their own game and what they have been told about everyone's
+pub struct RoyaleClient {
+    pub game: Tetris,
+    // which of the players this one is
+    pub local: usize,
+    pub players: Vec<Player>,
+    // the shown lines of every player's board, as they last sent them
+    pub boards: Vec<Vec<Vec<u8>>>,
+    // players that have joined while waiting for the match to start
+    pub joined: usize,
+    // how big attacks are made, in percent
+    pub multiplier: u32,
+    pub started: bool,
+    // the server has declared a winner
+    pub finished: bool,
+    // the players knocked out so far and who knocked them out, the latest last
+    pub knock_outs: Vec<(usize, Option<usize>)>,
+    garbage_delay: u32,
+    stream: TcpStream,
+    messages: Receiver<((), Result<String, String>)>,
+    // the board as it was last sent, so it is only sent again when it changes
+    last_state: String,
+    over_sent: bool,
+}
+
+impl RoyaleClient {
+    // joins the match at the address, playing by its rules once every player is in
+    pub fn join(address: &str, targeting: Targeting) -> Result<RoyaleClient, String> {
+        let mut stream = TcpStream::connect(address).map_err(|e| format!("failed to connect to {}: {}", address, e))?;
+        let mut reader = handshake_reader(&stream)?;
+        exchange_hello(&mut stream, &mut reader)?;
+
+        let line = read_handshake_line(&mut reader)?;
+        let numbers = match line.split_whitespace().collect::<Vec<_>>().split_first() {
+            Some((&"rules", numbers)) if numbers.len() == 9 => numbers.iter().map(|number| number.parse::<u64>().ok())
+                .collect::<Option<Vec<_>>>(),
+            _ => None
+        }.ok_or(format!("expected the rules of the match, the server said \"{}\"", line))?;
+        let (seed, garbage_delay, num_players, local) = (numbers[0], numbers[1] as u32, numbers[2] as usize, numbers[3] as usize);
+        if !(2..=MAX_PLAYERS).contains(&num_players) || local >= num_players {
+            return Err(format!("the rules make no sense: \"{}\"", line))
+        }
+        let (settings, pieces) = read_board_rules(&numbers[4..], &mut reader)?;
+        stream.set_read_timeout(None).map_err(|e| e.to_string())?;
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+
+        let (sender, receiver) = channel();
+        read_lines(reader, (), sender);
+        let game = Tetris::with_settings(seed, settings, Arc::new(pieces));
+        let mut client = RoyaleClient {
+            boards: vec![vec![vec![0; settings.width]; settings.height]; num_players],
+            game,
+            local,
+            players: vec![Player::new(); num_players],
+            joined: 0,
+            multiplier: 100,
+            started: false,
+            finished: false,
+            knock_outs: Vec::new(),
+            garbage_delay,
+            stream,
+            messages: receiver,
+            last_state: String::new(),
+            over_sent: false,
+        };
+        client.set_targeting(targeting)?;
+        Ok(client)
+    }
+
+    pub fn set_targeting(&mut self, targeting: Targeting) -> Result<(), String> {
+        self.players[self.local].targeting = targeting;
+        send_line(&mut self.stream, &format!("target {}", targeting.name()))
+    }
+
+    // handles what the server has sent, returning an error if the match can't go on
+    pub fn poll(&mut self) -> Result<(), String> {
+        loop {
+            let line = match self.messages.try_recv() {
+                Ok((_, Ok(line))) => line,
+                Ok((_, Err(_))) | Err(TryRecvError::Disconnected) if self.finished => return Ok(()),
+                Ok((_, Err(e))) => return Err(e),
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => return Err("the connection was closed".to_string())
+            };
+            self.handle(&line).ok_or(format!("can't make sense of \"{}\"", line))?;
+        }
+    }
+
+    fn handle(&mut self, line: &str) -> Option<()> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let num_players = self.players.len();
+        let player = |word: &str| word.parse::<usize>().ok().filter(|player| *player < num_players);
+        match words.as_slice() {
+            ["joined", count] => self.joined = count.parse().ok()?,
+            ["start"] => self.started = true,
+            ["board", from, board] => self.boards[player(from)?] = board_from_text(board)?,
+            ["garbage", lines, hole, _] => {
+                let hole = hole.parse::<usize>().ok().filter(|hole| *hole < self.game.settings.width)?;
+                self.game.receive_garbage(lines.parse().ok()?, hole, self.garbage_delay);
+            }
+            ["player", index, rest @ ..] => {
+                let index = player(index)?;
+                let mut status = parse_player(rest)?;
+                status.danger = self.players[index].danger;
+                self.players[index] = status;
+            }
+            ["ko", victim, by] => {
+                let by = parse_optional(by)?;
+                if by.is_some_and(|by| by >= num_players) {
+                    return None
+                }
+                self.knock_outs.push((player(victim)?, by));
+            }
+            ["multiplier", percent] => self.multiplier = percent.parse().ok()?,
+            ["end"] => self.finished = true,
+            _ => return None
+        }
+        Some(())
+    }
+
+    // plays a frame of this player's game once the match has started, and tells the server what came of it
+    pub fn step(&mut self, actions: &[Action]) -> Result<(), String> {
+        if !self.started || self.finished || self.over_sent {
+            return Ok(())
+        }
+        self.game.step(actions);
+        let attack = self.game.take_attack();
+        if attack > 0 {
+            send_line(&mut self.stream, &format!("attack {}", attack))?;
+        }
+        let board = board_to_text(&self.game);
+        let state = format!("{} {}", danger(&self.game), board);
+        if state != self.last_state {
+            send_line(&mut self.stream, &format!("state {}", state))?;
+            self.boards[self.local] = board_from_text(&board).unwrap_or_default();
+            self.last_state = state;
+        }
+        if self.game.game_over {
+            send_line(&mut self.stream, "over")?;
+            self.over_sent = true;
+        }
+        Ok(())
+    }
+
+    // the players attacking this one
+    pub fn attackers(&self) -> Vec<usize> {
+        (0..self.players.len()).filter(|&player| self.players[player].alive && self.players[player].target == Some(self.local))
+            .collect()
+    }
+}
+
+impl Drop for RoyaleClient {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    // joins the match on the port, waiting for it to be hosted
+    fn join(port: u16) -> RoyaleClient {
+        let started = Instant::now();
+        loop {
+            match RoyaleClient::join(&format!("127.0.0.1:{}", port), Targeting::Random) {
+                Ok(client) => return client,
+                Err(e) if started.elapsed() > Duration::from_secs(10) => panic!("{}", e),
+                Err(_) => thread::sleep(Duration::from_millis(10))
+            }
+        }
+    }
+
+    // plays the client's game without inputs until it has heard what it was waiting for
+    fn wait_for(client: &mut RoyaleClient, heard: &dyn Fn(&RoyaleClient) -> bool) {
+        let started = Instant::now();
+        while !heard(client) {
+            assert!(started.elapsed() < Duration::from_secs(10), "the server went quiet");
+            client.poll().unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn board_text() {
+        let mut tetris = Tetris::with_seed(2);
+        tetris.game_map.add_garbage(2, 3);
+        let board = board_from_text(&board_to_text(&tetris)).unwrap();
+        assert_eq!(board.len(), tetris.settings.height);
+        assert_eq!(board[board.len() - 1], vec![8, 8, 8, 0, 8, 8, 8, 8, 8, 8]);
+        assert!(board_from_text("").is_none());
+        assert!(board_from_text("00/0?").is_none());
+        assert!(board_from_text(&"0".repeat(MAX_WIDTH + 1)).is_none());
+    }
+
+    #[test]
+    fn loopback() {
+        let port = free_port();
+        let server = thread::spawn(move || {
+            let pieces = PieceSet::standard();
+            let mut server = RoyaleServer::host(port, 2, 7, 0, BoardSettings::default(), &pieces, &mut |_| ()).unwrap();
+            server.run(&mut |_| ());
+            server.royale.winner()
+        });
+        let mut first = join(port);
+        let mut second = join(port);
+        assert_eq!((first.local, second.local), (0, 1));
+        wait_for(&mut first, &|client| client.started);
+        wait_for(&mut second, &|client| client.started);
+        assert_eq!(first.joined, 2);
+
+        // the boards get passed on
+        first.step(&[Action::HardDrop]).unwrap();
+        let board = first.boards[0].clone();
+        assert!(board.iter().flatten().any(|case| *case != 0));
+        wait_for(&mut second, &|client| client.boards[0] == board);
+
+        // and so do the attacks, to the only other player there is
+        send_line(&mut first.stream, "attack 3").unwrap();
+        wait_for(&mut second, &|client| client.game.garbage.total() == 3);
+        wait_for(&mut first, &|client| client.players[0].sent == 3);
+
+        // leaving is a knock-out, going to whoever attacked last, and it ends the match
+        drop(second);
+        wait_for(&mut first, &|client| client.finished);
+        assert_eq!(first.knock_outs, vec![(1, Some(0))]);
+        assert_eq!(first.players[0].kos, 1);
+        assert!(!first.players[1].alive);
+        assert_eq!(first.players[1].place, Some(2));
+        assert_eq!(server.join().unwrap(), Some(0));
+    }
+
+    #[test]
+    fn nonsense_attack() {
+        let port = free_port();
+        let server = thread::spawn(move || {
+            let pieces = PieceSet::standard();
+            let mut server = RoyaleServer::host(port, 2, 7, 0, BoardSettings::default(), &pieces, &mut |_| ()).unwrap();
+            server.run(&mut |_| ());
+            server.royale.winner()
+        });
+        let mut first = join(port);
+        let mut second = join(port);
+        wait_for(&mut first, &|client| client.started);
+        wait_for(&mut second, &|client| client.started);
+
+        // the server takes the player out rather than going down with everyone else's match
+        send_line(&mut second.stream, "attack 20000000").unwrap();
+        wait_for(&mut first, &|client| client.finished);
+        assert_eq!(first.knock_outs, vec![(1, None)]);
+        assert_eq!(first.players[0].sent, 0);
+        assert_eq!(server.join().unwrap(), Some(0));
+    }
+}
@@ -0,0 +1,306 @@
+use tetris::{BoardSettings, Tetris, FPS, MAX_LEVEL};
+use tetrimino::Tetrimino;
+use board::{Board, GARBAGE, MAX_WIDTH};
+use garbage::Pending;
+use pieces::PieceSet;
+use randomizer::Randomizer;
+use highscore::{read_from_file, write_to_file};
+use replay::Replay;
+
+use std::fs;
+use std::sync::Arc;
+
+const SAVE_FILE: &str = "save.txt";
+// the replay of the suspended game so far, so it can still be recorded in full once it ends
+const SAVE_REPLAY_FILE: &str = "save.ttr";
+// version 1 stored the gravity timer in milliseconds rather than frames and had no seed,
+// version 2 had no queue of next pieces, version 3 was always on the classic board, and up to version 4 the
+// pieces were always the standard ones, their colours standing for their kinds, and up to version 5 whether the
+// piece had just been turned and the garbage on its way weren't kept
+const SAVE_VERSION: u32 = 6;
+
+// everything needed to pick a suspended game back up
+pub struct SavedGame {
+    pub tetris: Tetris,
+    pub replay: Option<Replay>,
+}
+
+fn line_to_string(line: &[u8]) -> String {
+    line.iter().map(|case| case.to_string()).collect()
+}
+
+fn string_to_line(s: &str) -> Option<Vec<u8>> {
+    s.chars().map(|c| c.to_digit(10).map(|case| case as u8)).collect()
+}
+
+// a piece is written as its kind, position and rotation followed by every one of its states,
+// with the lines of a state separated by slashes
+fn piece_to_string(piece: &Tetrimino) -> String {
+    let states = piece.states.iter()
+        .map(|state| state.iter().map(|line| line_to_string(line)).collect::<Vec<_>>().join("/"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!("{} {} {} {} {}", piece.kind, piece.x, piece.y, piece.current_state, states)
+}
+
+fn string_to_piece(s: &str, version: u32) -> Option<Tetrimino> {
+    let mut words = s.split_whitespace();
+    let kind = if version >= 5 { Some(words.next()?.parse().ok()?) } else { None };
+    let x = words.next()?.parse().ok()?;
+    let y = words.next()?.parse().ok()?;
+    let current_state = words.next()?.parse().ok()?;
+    let states = words.map(|state| state.split('/').map(string_to_line).collect::<Option<Vec<_>>>())
+        .collect::<Option<Vec<_>>>()?;
+    let lines = states.iter().flatten();
+    if current_state as usize >= states.len() || lines.clone().any(|line| line.len() > MAX_WIDTH)
+        || lines.flatten().any(|case| *case > GARBAGE) {
+        return None
+    }
+    let mut piece = Tetrimino::from_states(kind.unwrap_or(0), states, x);
+    if kind.is_none() {
+        piece.kind = piece.colour();
+    }
+    piece.y = y;
+    piece.current_state = current_state;
+    Some(piece)
+}
+
+fn optional_piece_to_string(piece: &Option<Tetrimino>) -> String {
+    match *piece {
+        Some(ref piece) => piece_to_string(piece),
+        None => "none".to_string()
+    }
+}
+
+fn string_to_optional_piece(s: &str, version: u32) -> Option<Option<Tetrimino>> {
+    if s == "none" {
+        Some(None)
+    } else {
+        string_to_piece(s, version).map(Some)
+    }
+}
+
+fn game_to_string(tetris: &Tetris) -> String {
+    let mut content = format!("version {}\n", SAVE_VERSION);
+    content += &format!("level {}\n", tetris.current_level);
+    content += &format!("score {}\n", tetris.score);
+    content += &format!("lines {}\n", tetris.num_lines);
+    let settings = &tetris.settings;
+    content += &format!("board {} {} {} {}\n", settings.width, settings.height, settings.hidden,
+        settings.partial_lock_out as u8);
+    content += &format!("seed {}\n", tetris.seed);
+    content += &format!("frame {}\n", tetris.frame);
+    content += &format!("gravity {}\n", tetris.gravity_frames);
+    content += &format!("randomizer {} {}\n", tetris.randomizer.state, tetris.randomizer.previous);
+    content += &format!("can_hold {}\n", tetris.can_hold as u8);
+    content += &format!("pieces {}\n", tetris.num_pieces);
+    content += &format!("sent {}\n", tetris.sent);
+    content += &format!("combo {} {}\n", tetris.combo, tetris.back_to_back as u8);
+    content += &format!("spun {}\n", tetris.spun as u8);
+    for pending in &tetris.garbage.pending {
+        content += &format!("garbage {} {} {}\n", pending.lines, pending.hole, pending.delay);
+    }
+    let next = tetris.next_pieces.iter().map(|kind| kind.to_string()).collect::<Vec<_>>();
+    content += &format!("next {}\n", next.join(" "));
+    content += &format!("current {}\n", optional_piece_to_string(&tetris.current_piece));
+    content += &format!("held {}\n", optional_piece_to_string(&tetris.held_piece));
+    for line in tetris.game_map.iter() {
+        content += &format!("map {}\n", line_to_string(line));
+    }
+    if !tetris.pieces.is_standard() {
+        for line in tetris.pieces.to_text().lines() {
+            content += &format!("piece_set {}\n", line);
+        }
+    }
+    content
+}
+
+fn string_to_game(content: &str) -> Option<Tetris> {
+    let mut tetris = Tetris::new();
+    let mut game_map = Vec::new();
+    let mut settings = BoardSettings::CLASSIC;
+    let mut piece_set = String::new();
+
+    let mut lines = content.lines();
+    let version = lines.next()?.trim_start_matches("version ").parse::<u32>().ok()?;
+    if version == 0 || version > SAVE_VERSION {
+        eprintln!("Ignoring save file with unsupported version {}", version);
+        return None
+    }
+
+    for line in lines {
+        let (key, value) = match line.find(' ') {
+            Some(i) => (&line[..i], &line[i + 1..]),
+            None => continue
+        };
+        match key {
+            "level" => tetris.current_level = value.parse().ok()?,
+            "score" => tetris.score = value.parse().ok()?,
+            "lines" => tetris.num_lines = value.parse().ok()?,
+            "board" => {
+                let words = value.split_whitespace().collect::<Vec<_>>();
+                if words.len() != 4 {
+                    return None
+                }
+                settings = BoardSettings::parse(&format!("{}x{}+{}", words[0], words[1], words[2]))?;
+                settings.partial_lock_out = words[3] == "1";
+            }
+            "seed" => tetris.seed = value.parse().ok()?,
+            "frame" => tetris.frame = value.parse().ok()?,
+            "gravity" if version == 1 => tetris.gravity_frames = value.parse::<u32>().ok()? * FPS / 1000,
+            "gravity" => tetris.gravity_frames = value.parse().ok()?,
+            "randomizer" => {
+                let mut words = value.split_whitespace();
+                let mut randomizer = Randomizer::new(words.next()?.parse().ok()?);
+                randomizer.previous = words.next()?.parse().ok()?;
+                tetris.randomizer = randomizer;
+            }
+            "can_hold" => tetris.can_hold = value == "1",
+            "pieces" => tetris.num_pieces = value.parse().ok()?,
+            "sent" => tetris.sent = value.parse().ok()?,
+            "combo" => {
+                let mut words = value.split_whitespace();
+                tetris.combo = words.next()?.parse().ok()?;
+                tetris.back_to_back = words.next()? == "1";
+            }
+            "spun" => tetris.spun = value == "1",
+            "garbage" => {
+                let numbers = value.split_whitespace().map(|number| number.parse().ok()).collect::<Option<Vec<u32>>>()?;
+                if numbers.len() != 3 || numbers[0] == 0 {
+                    return None
+                }
+                tetris.garbage.pending.push_back(Pending {lines: numbers[0], hole: numbers[1] as usize, delay: numbers[2]});
+            }
+            "next" if version < 5 => tetris.next_pieces = string_to_line(value)?.into_iter().collect(),
+            "next" => {
+                tetris.next_pieces = value.split_whitespace().map(|kind| kind.parse().ok()).collect::<Option<_>>()?
+            }
+            "current" => tetris.current_piece = string_to_optional_piece(value, version)?,
+            "held" => tetris.held_piece = string_to_optional_piece(value, version)?,
+            "piece_set" => piece_set += &format!("{}\n", value),
+            "map" => game_map.push(string_to_line(value)?),
+            _ => {}
+        }
+    }
+
+    if game_map.len() != settings.total_height() || game_map[0].len() != settings.width
+        || tetris.current_level == 0 || tetris.current_level > MAX_LEVEL
+        || game_map.iter().flatten().any(|case| *case > GARBAGE)
+        || tetris.garbage.pending.iter().any(|pending| pending.hole >= settings.width) {
+        return None
+    }
+    if version < 3 {
+        // older games hadn't drawn any pieces ahead yet
+        tetris.next_pieces.clear();
+        tetris.fill_next_pieces();
+    }
+    tetris.settings = settings;
+    if !piece_set.is_empty() {
+        tetris.pieces = Arc::new(PieceSet::parse(&piece_set).ok()?);
+    }
+    let num_kinds = tetris.pieces.num_kinds() as u8;
+    let pieces = tetris.current_piece.iter().chain(tetris.held_piece.iter()).map(|piece| piece.kind);
+    if pieces.chain(tetris.next_pieces.iter().cloned()).any(|kind| kind == 0 || kind > num_kinds) {
+        return None
+    }
+    tetris.game_map = Board::from_colours(game_map)?;
+    // the piece in play is placed on the map as it is once it locks
+    if tetris.current_piece.as_ref().is_some_and(|piece| !piece.test_current_position(&tetris.game_map)) {
+        return None
+    }
+    Some(tetris)
+}
+
+pub fn save_game(tetris: &Tetris, replay: Option<&Replay>) -> bool {
+    let _ = fs::remove_file(SAVE_REPLAY_FILE);
+    if let Some(replay) = replay {
+        if replay.save(SAVE_REPLAY_FILE).is_err() {
+            return false
+        }
+    }
+    write_to_file(&game_to_string(tetris), SAVE_FILE).is_ok()
+}
+
+pub fn load_game() -> Option<SavedGame> {
+    let tetris = read_from_file(SAVE_FILE).ok().and_then(|content| string_to_game(&content))?;
+    // the replay only carries on if it was recorded from the same game
+    let replay = Replay::load(SAVE_REPLAY_FILE).ok().filter(|replay| replay.seed == tetris.seed);
+    Some(SavedGame {tetris, replay})
+}
+
+pub fn delete_save() {
+    let _ = fs::remove_file(SAVE_FILE);
+    let _ = fs::remove_file(SAVE_REPLAY_FILE);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use input::Action;
+
+    // a game some way in, with garbage on its way and the piece in play just turned
+    fn sample_game(settings: BoardSettings, pieces: PieceSet) -> Tetris {
+        let mut tetris = Tetris::with_settings(11, settings, Arc::new(pieces));
+        let actions = [Action::MoveLeft, Action::RotateClockwise, Action::Hold, Action::MoveRight, Action::HardDrop];
+        for frame in 0..150 {
+            let action = if frame % 7 == 0 { vec![actions[(frame / 7) % actions.len()]] } else { Vec::new() };
+            tetris.step(&action);
+        }
+        tetris.receive_garbage(3, 2, 20);
+        tetris.receive_garbage(1, 0, 0);
+        // whatever the piece is, as if it had just been turned into place
+        tetris.spun = true;
+        assert!(tetris.num_pieces > 0 && !tetris.game_over);
+        tetris
+    }
+
+    fn round_trip(tetris: &Tetris) -> Tetris {
+        let content = game_to_string(tetris);
+        let loaded = string_to_game(&content).unwrap();
+        assert_eq!(game_to_string(&loaded), content);
+        loaded
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut tetris = sample_game(BoardSettings::CLASSIC, PieceSet::standard());
+        let mut loaded = round_trip(&tetris);
+        assert!(loaded.spun);
+        assert_eq!(loaded.garbage.pending, tetris.garbage.pending);
+        assert_eq!(loaded.game_map, tetris.game_map);
+        // and it plays on the same way
+        for frame in 0..300 {
+            let actions = if frame % 5 == 0 { vec![Action::HardDrop] } else { Vec::new() };
+            tetris.step(&actions);
+            loaded.step(&actions);
+        }
+        assert_eq!(game_to_string(&loaded), game_to_string(&tetris));
+    }
+
+    #[test]
+    fn save_and_load_other_rules() {
+        let pieces = PieceSet::parse(include_str!("../pieces/triominoes.txt")).unwrap();
+        let tetris = sample_game(BoardSettings::GUIDELINE, pieces);
+        let loaded = round_trip(&tetris);
+        assert_eq!(loaded.settings, BoardSettings::GUIDELINE);
+        assert_eq!(loaded.pieces, tetris.pieces);
+    }
+
+    #[test]
+    fn rejected() {
+        let content = game_to_string(&sample_game(BoardSettings::CLASSIC, PieceSet::standard()));
+        let broken = [
+            content.replace("level 1\n", "level 0\n"),
+            content.replace("level 1\n", &format!("level {}\n", MAX_LEVEL + 1)),
+            content.replace("garbage 3 2 ", "garbage 3 10 "),
+            content.replace("garbage 3 2 ", "garbage 0 2 "),
+            content.replacen("map 0000000000", "map 0000000009", 1),
+            content.replace(&format!("version {}", SAVE_VERSION), &format!("version {}", SAVE_VERSION + 1)),
+        ];
+        assert!(string_to_game(&content).is_some());
+        for broken in broken.iter() {
+            assert_ne!(*broken, content);
+            assert!(string_to_game(broken).is_none(), "{}", broken);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+// Plays whole games with a bot and no one watching, as fast as they can go, to see how well the bot does
+use ai::{Bot, BotPlayer};
+use pieces::PieceSet;
+use replay::Mode;
+use tetris::{BoardSettings, Tetris};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+#[derive(Clone, Debug)]
+pub struct GameStats {
+    pub seed: u64,
+    pub score: u32,
+    pub lines: u32,
+    pub pieces: u32,
+    // the height of the highest column after any piece locked
+    pub max_height: u32,
+    pub tetrises: u32,
+    pub frames: u32,
+    pub game_over: bool,
+    // what the bot had to say during the game, like why the built-in AI played for it
+    pub bot_messages: Vec<String>,
+}
+
+impl GameStats {
+    // the share of the lines that were cleared four at a time
+    pub fn tetris_rate(&self) -> f64 {
+        if self.lines == 0 {
+            0.0
+        } else {
+            (self.tetrises * 4) as f64 / self.lines as f64
+        }
+    }
+}
+
+pub struct Summary {
+    pub mean: f64,
+    pub median: f64,
+    pub p10: f64,
+    pub p90: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+// plays one game until it is lost or `max_pieces` have been placed
+pub fn simulate(mode: Mode, settings: BoardSettings, pieces: &Arc<PieceSet>, seed: u64, player: &mut BotPlayer,
+    max_pieces: u32) -> GameStats {
+    let mut tetris = match mode {
+        Mode::Marathon => Tetris::with_settings(seed, settings, pieces.clone()),
+    };
+    player.reset();
+    let mut max_height = 0;
+    let mut tetrises = 0;
+    let mut bot_messages = Vec::new();
+
+    while !tetris.game_over && tetris.num_pieces < max_pieces {
+        let (pieces, lines) = (tetris.num_pieces, tetris.num_lines);
+        let actions = player.next_actions(&tetris);
+        bot_messages.extend(player.take_messages());
+        tetris.step(&actions);
+        if tetris.num_pieces != pieces {
+            max_height = max_height.max(tetris.game_map.bits.column_heights().into_iter().max().unwrap_or(0));
+            if tetris.num_lines - lines == 4 {
+                tetrises += 1;
+            }
+        }
+    }
+
+    GameStats {
+        seed,
+        score: tetris.score,
+        lines: tetris.num_lines,
+        pieces: tetris.num_pieces,
+        max_height,
+        tetrises,
+        frames: tetris.frame,
+        game_over: tetris.game_over,
+        bot_messages,
+    }
+}
+
+// plays a game for each seed, spread over `threads` threads which each get their own bot from `make_bot`,
+// and returns the stats in the same order as the seeds
+pub fn run_games<F>(mode: Mode, settings: BoardSettings, pieces: &Arc<PieceSet>, seeds: &[u64], threads: usize,
+    max_pieces: u32, make_bot: F) -> Vec<GameStats>
+    where F: Fn() -> Box<dyn Bot> + Sync {
+    let next_game = AtomicUsize::new(0);
+    let results = Mutex::new(vec![None; seeds.len()]);
+
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| {
+                let mut player = BotPlayer::new(make_bot());
+                player.input_delay = 0;
+                loop {
+                    let index = next_game.fetch_add(1, Ordering::SeqCst);
+                    if index >= seeds.len() {
+                        break
+                    }
+                    let stats = simulate(mode, settings, pieces, seeds[index], &mut player, max_pieces);
+                    results.lock().unwrap()[index] = Some(stats);
+                }
+            });
+        }
+    });
+    results.into_inner().unwrap().into_iter().map(|stats| stats.unwrap()).collect()
+}
+
+pub fn summarize(values: &[f64]) -> Summary {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    // nearest rank
+    let percentile = |p: f64| {
+        if sorted.is_empty() {
+            return 0.0
+        }
+        let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+        sorted[rank.clamp(1, sorted.len()) - 1]
+    };
+    Summary {
+        mean: if sorted.is_empty() { 0.0 } else { sorted.iter().sum::<f64>() / sorted.len() as f64 },
+        median: percentile(50.0),
+        p10: percentile(10.0),
+        p90: percentile(90.0),
+        min: percentile(0.0),
+        max: percentile(100.0),
+    }
+}
@@ -0,0 +1,309 @@
+// Streams the games being played to spectators, so they can be watched from another window or machine. Each
+// spectator that connects over TCP is sent JSON messages, one per line:
+//     {"type": "pieces", "pieces": "<the piece set, written out the way it is read>"}
+//         first, and again whenever the games change to another set
+//     {"type": "state", "games": [{"width", "height", "hidden", "partial_lock_out", "board", "piece", "hold", "queue",
+//         "score", "lines", "level", "pieces", "game_over", "garbage", "garbage_ready"}, ...]}
+//         whenever something in the games has changed, with the board as lines of colours from the top, the piece as
+//         its kind, x, y and state, and the hold and queue as kinds
+use board::{Board, GARBAGE, MAX_WIDTH};
+use pieces::PieceSet;
+use tetris::{BoardSettings, Tetris, MAX_LEVEL};
+
+use serde_json::Value;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, sync_channel, Receiver, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+pub const DEFAULT_SPECTATE_PORT: u16 = 7475;
+// lines waiting to be sent to a spectator before it is thought to have stopped reading and is let go
+const SPECTATOR_BACKLOG: usize = 600;
+
+// the port alone is on this machine only
+pub fn spectate_address(spec: &str) -> String {
+    if spec.contains(':') { spec.to_string() } else { format!("127.0.0.1:{}", spec) }
+}
+
+fn game_to_json(tetris: &Tetris) -> Value {
+    json!({
+        "width": tetris.settings.width,
+        "height": tetris.settings.height,
+        "hidden": tetris.settings.hidden,
+        "partial_lock_out": tetris.settings.partial_lock_out,
+        "board": tetris.game_map.iter().collect::<Vec<_>>(),
+        "piece": tetris.current_piece.as_ref()
+            .map(|piece| json!({"kind": piece.kind, "x": piece.x, "y": piece.y, "state": piece.current_state})),
+        "hold": tetris.held_piece.as_ref().map(|piece| piece.kind),
+        "queue": tetris.next_pieces,
+        "score": tetris.score,
+        "lines": tetris.num_lines,
+        "level": tetris.current_level,
+        "pieces": tetris.num_pieces,
+        "game_over": tetris.game_over,
+        "garbage": tetris.garbage.total(),
+        "garbage_ready": tetris.garbage.ready(),
+    })
+}
+
+// what spectators that connect are sent first, and who is watching
+struct Shared {
+    pieces_line: Option<String>,
+    state_line: Option<String>,
+    spectators: Vec<SyncSender<String>>,
+}
+
+impl Shared {
+    // sends the line to every spectator, letting go of the ones that have left or stopped keeping up
+    fn send(&mut self, line: &str) {
+        self.spectators.retain(|spectator| spectator.try_send(line.to_string()).is_ok());
+    }
+}
+
+pub struct SpectatorServer {
+    shared: Arc<Mutex<Shared>>,
+    // the set the games were last played with
+    pieces: Option<Arc<PieceSet>>,
+}
+
+impl SpectatorServer {
+    pub fn start(address: &str) -> Result<SpectatorServer, String> {
+        let listener = TcpListener::bind(address).map_err(|e| format!("failed to listen on {}: {}", address, e))?;
+        let shared = Arc::new(Mutex::new(Shared {pieces_line: None, state_line: None, spectators: Vec::new()}));
+        let accepting = shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue
+                };
+                // every spectator is written to on a thread of its own, so a slow one never holds the game up
+                let (sender, receiver) = sync_channel::<String>(SPECTATOR_BACKLOG);
+                thread::spawn(move || {
+                    for line in receiver {
+                        if writeln!(stream, "{}", line).is_err() {
+                            return
+                        }
+                    }
+                });
+                let mut shared = accepting.lock().unwrap();
+                for line in shared.pieces_line.iter().chain(shared.state_line.iter()) {
+                    let _ = sender.try_send(line.clone());
+                }
+                shared.spectators.push(sender);
+            }
+        });
+        Ok(SpectatorServer {shared, pieces: None})
+    }
+
+    pub fn num_spectators(&self) -> usize {
+        self.shared.lock().unwrap().spectators.len()
+    }
+
+    // sends the games to the spectators if they have changed since they were last sent
+    pub fn broadcast(&mut self, games: &[Tetris]) {
+        let mut shared = self.shared.lock().unwrap();
+        if let Some(game) = games.first() {
+            if !self.pieces.as_ref().is_some_and(|pieces| Arc::ptr_eq(pieces, &game.pieces)) {
+                self.pieces = Some(game.pieces.clone());
+                let line = json!({"type": "pieces", "pieces": game.pieces.to_text()}).to_string();
+                shared.send(&line);
+                shared.pieces_line = Some(line);
+            }
+        }
+        let line = json!({"type": "state", "games": games.iter().map(game_to_json).collect::<Vec<_>>()}).to_string();
+        if shared.state_line.as_ref() != Some(&line) {
+            shared.send(&line);
+            shared.state_line = Some(line);
+        }
+    }
+}
+
+fn game_from_json(value: &Value, pieces: &Arc<PieceSet>) -> Option<Tetris> {
+    let number = |key: &str| value[key].as_u64();
+    // the board is checked the same way as one given on the command line
+    let spec = format!("{}x{}+{}", number("width")?, number("height")?, number("hidden")?);
+    let mut settings = BoardSettings::parse(&spec)?;
+    settings.partial_lock_out = value["partial_lock_out"].as_bool()?;
+    let colours = value["board"].as_array()?.iter()
+        .map(|line| line.as_array()?.iter()
+            .map(|case| case.as_u64().filter(|case| *case <= GARBAGE as u64).map(|case| case as u8))
+            .collect::<Option<Vec<_>>>())
+        .collect::<Option<Vec<_>>>()?;
+    let board = Board::from_colours(colours)?;
+    if board.width() != settings.width || board.height() != settings.total_height() {
+        return None
+    }
+
+    let num_kinds = pieces.num_kinds() as u64;
+    let kind = |value: &Value| value.as_u64().filter(|kind| (1..=num_kinds).contains(kind)).map(|kind| kind as u8);
+    let mut tetris = Tetris::with_settings(0, settings, pieces.clone());
+    tetris.game_map = board;
+    tetris.current_piece = match value["piece"] {
+        Value::Null => None,
+        ref piece => {
+            let mut current = pieces.create(kind(&piece["kind"])?);
+            // its box can't be further off the board than it is wide
+            current.x = piece["x"].as_i64().filter(|x| (-(MAX_WIDTH as i64)..=MAX_WIDTH as i64).contains(x))? as isize;
+            current.y = piece["y"].as_u64().filter(|y| *y < settings.total_height() as u64)? as usize;
+            current.current_state = piece["state"].as_u64().filter(|state| (*state as usize) < current.states.len())? as u8;
+            // it can overlap the stack once the game is lost, but its cells have to be on the board to be drawn
+            let state = current.current_state as usize;
+            let ((left, right), (_, bottom)) = (current.columns(state), current.lines(state));
+            if current.x + left < 0 || current.x + right >= settings.width as isize
+                || current.y + bottom >= settings.total_height() {
+                return None
+            }
+            Some(current)
+        }
+    };
+    tetris.held_piece = match value["hold"] {
+        Value::Null => None,
+        ref held => Some(pieces.create(kind(held)?))
+    };
+    tetris.next_pieces = value["queue"].as_array()?.iter().map(kind).collect::<Option<_>>()?;
+    tetris.score = number("score")? as u32;
+    tetris.num_lines = number("lines")? as u32;
+    tetris.current_level = number("level").filter(|level| (1..=MAX_LEVEL as u64).contains(level))? as u32;
+    tetris.num_pieces = number("pieces")? as u32;
+    tetris.game_over = value["game_over"].as_bool()?;
+    // only how much garbage is on its way, and how much of it is ready, is known
+    let (garbage, ready) = (number("garbage")? as u32, number("garbage_ready")? as u32);
+    tetris.garbage.push(ready, 0, 0);
+    tetris.garbage.push(garbage.saturating_sub(ready), 0, 1);
+    Some(tetris)
+}
+
+// the watching end, which puts the games back together from what the server sends
+pub struct Spectator {
+    messages: Receiver<Result<Value, String>>,
+    pieces: Arc<PieceSet>,
+    pub games: Vec<Tetris>,
+}
+
+impl Spectator {
+    pub fn connect(address: &str) -> Result<Spectator, String> {
+        let stream = TcpStream::connect(address).map_err(|e| format!("failed to connect to {}: {}", address, e))?;
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stream).lines() {
+                let message = line.map_err(|e| format!("the connection broke: {}", e))
+                    .and_then(|line| serde_json::from_str(&line).map_err(|e| format!("can't make sense of \"{}\": {}", line, e)));
+                let failed = message.is_err();
+                if sender.send(message).is_err() || failed {
+                    return
+                }
+            }
+            let _ = sender.send(Err("the game has ended".to_string()));
+        });
+        Ok(Spectator {messages: receiver, pieces: Arc::new(PieceSet::standard()), games: Vec::new()})
+    }
+
+    // takes in what the server has sent, returning whether the games have changed
+    pub fn poll(&mut self) -> Result<bool, String> {
+        let mut changed = false;
+        loop {
+            let message = match self.messages.try_recv() {
+                Ok(message) => message?,
+                Err(TryRecvError::Empty) => return Ok(changed),
+                Err(TryRecvError::Disconnected) => return Err("the game has ended".to_string())
+            };
+            match message["type"].as_str() {
+                Some("pieces") => {
+                    let text = message["pieces"].as_str().ok_or("the pieces are missing")?;
+                    self.pieces = Arc::new(PieceSet::parse(text).map_err(|e| format!("the pieces are invalid: {}", e))?);
+                }
+                Some("state") => {
+                    let pieces = &self.pieces;
+                    self.games = message["games"].as_array().ok_or("the games are missing")?.iter()
+                        .map(|game| game_from_json(game, pieces))
+                        .collect::<Option<_>>()
+                        .ok_or("the games are invalid")?;
+                    changed = true;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a classic game with a T in play
+    fn game() -> Tetris {
+        let mut tetris = Tetris::with_seed(3);
+        tetris.step(&[]);
+        tetris.current_piece = Some(tetris.spawn_tetrimino(tetris.pieces.kind("T").unwrap()));
+        tetris
+    }
+
+    #[test]
+    fn round_trip() {
+        let tetris = game();
+        let back = game_from_json(&game_to_json(&tetris), &tetris.pieces).unwrap();
+        assert_eq!(game_to_json(&back), game_to_json(&tetris));
+    }
+
+    #[test]
+    fn colours() {
+        let tetris = game();
+        let mut value = game_to_json(&tetris);
+        value["board"][0][0] = json!(GARBAGE);
+        assert!(game_from_json(&value, &tetris.pieces).is_some());
+        for colour in &[GARBAGE as u64 + 1, 255, 256 + 1] {
+            value["board"][0][0] = json!(colour);
+            assert!(game_from_json(&value, &tetris.pieces).is_none(), "colour {}", colour);
+        }
+    }
+
+    #[test]
+    fn board_size() {
+        let tetris = game();
+        let mut value = game_to_json(&tetris);
+        for &(key, size) in [("width", 3), ("width", 17), ("height", 2), ("hidden", 256), ("height", u64::MAX)].iter() {
+            let mut wrong = value.clone();
+            wrong[key] = json!(size);
+            assert!(game_from_json(&wrong, &tetris.pieces).is_none(), "{} {}", key, size);
+        }
+        value["hidden"] = json!(u64::MAX);
+        assert!(game_from_json(&value, &tetris.pieces).is_none());
+    }
+
+    #[test]
+    fn piece_position() {
+        let tetris = game();
+        let value = game_to_json(&tetris);
+        // the T comes in with its box in column 4, covering its first three columns
+        for &(x, y) in [(-1, 0), (8, 0), (i64::MIN, 0), (i64::MAX, 0), (0, 15), (0, 1_000), (0, u64::MAX)].iter() {
+            let mut wrong = value.clone();
+            wrong["piece"]["x"] = json!(x);
+            wrong["piece"]["y"] = json!(y);
+            assert!(game_from_json(&wrong, &tetris.pieces).is_none(), "{} {}", x, y);
+        }
+        for &(x, y) in [(0, 0), (7, 14)].iter() {
+            let mut right = value.clone();
+            right["piece"]["x"] = json!(x);
+            right["piece"]["y"] = json!(y);
+            let piece = game_from_json(&right, &tetris.pieces).unwrap().current_piece.unwrap();
+            assert_eq!((piece.x, piece.y), (x, y));
+        }
+    }
+
+    #[test]
+    fn levels() {
+        let tetris = game();
+        let mut value = game_to_json(&tetris);
+        for level in 1..=MAX_LEVEL {
+            value["level"] = json!(level);
+            assert_eq!(game_from_json(&value, &tetris.pieces).unwrap().current_level, level);
+        }
+        for level in &[0, MAX_LEVEL + 1, u32::MAX] {
+            value["level"] = json!(level);
+            assert!(game_from_json(&value, &tetris.pieces).is_none(), "level {}", level);
+        }
+    }
+}
@@ -0,0 +1,557 @@
+// Front-end side of the Tetris Bot Protocol (https://github.com/tetris-bot-protocol/tbp-spec), which lets
+// an external bot process play the game through JSON messages, one per line, on its stdin and stdout.
+
+use ai::{best_placement, piece_cells, place_piece, placements, playable_pieces, Bot, HeuristicBot, Weights, WEIGHTS_FILE};
+use board::{Board, GARBAGE};
+use input::Action;
+use tetris::Tetris;
+
+use serde_json::Value;
+
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// how long the bot gets to start up, and to come up with a move
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_MOVE_TIMEOUT: Duration = Duration::from_secs(1);
+// the protocol's board is always 10 columns wide and 40 lines high
+const TBP_BOARD_WIDTH: usize = 10;
+const TBP_BOARD_HEIGHT: usize = 40;
+
+// cells of each piece facing north around its rotation centre, x to the right and y upwards
+fn north_cells(name: &str) -> Option<[(i32, i32); 4]> {
+    Some(match name {
+        "I" => [(-1, 0), (0, 0), (1, 0), (2, 0)],
+        "J" => [(-1, 1), (-1, 0), (0, 0), (1, 0)],
+        "L" => [(-1, 0), (0, 0), (1, 0), (1, 1)],
+        "O" => [(0, 0), (1, 0), (0, 1), (1, 1)],
+        "S" => [(-1, 0), (0, 0), (0, 1), (1, 1)],
+        "Z" => [(-1, 1), (0, 1), (0, 0), (1, 0)],
+        "T" => [(-1, 0), (0, 0), (1, 0), (0, 1)],
+        _ => return None
+    })
+}
+
+// the cells, as (column, line) on the game map, that a protocol location covers
+fn location_cells(location: &Value, height: usize) -> Option<HashSet<(isize, usize)>> {
+    let cells = north_cells(location["type"].as_str()?)?;
+    let x = location["x"].as_i64()? as i32;
+    let y = location["y"].as_i64()? as i32;
+    let rotate = |(cx, cy): (i32, i32)| match location["orientation"].as_str() {
+        Some("north") => Some((cx, cy)),
+        Some("east") => Some((cy, -cx)),
+        Some("south") => Some((-cx, -cy)),
+        Some("west") => Some((-cy, cx)),
+        _ => None
+    };
+
+    let mut result = HashSet::new();
+    for cell in cells.iter() {
+        let (cx, cy) = rotate(*cell)?;
+        let line = height as i32 - 1 - (y + cy);
+        if line < 0 {
+            return None
+        }
+        result.insert(((x + cx) as isize, line as usize));
+    }
+    Some(result)
+}
+
+fn board_to_json(tetris: &Tetris) -> Value {
+    let width = tetris.game_map[0].len();
+    // the bottom line comes first
+    let mut board = tetris.game_map.iter().rev()
+        .map(|line| Value::Array(line.iter().map(|case| match *case {
+            0 => Value::Null,
+            GARBAGE => Value::from("G"),
+            // each of the standard pieces has its own colour, in the same order
+            colour => Value::from(tetris.pieces.name(colour)),
+        }).collect()))
+        .collect::<Vec<_>>();
+    while board.len() < TBP_BOARD_HEIGHT {
+        board.push(Value::Array(vec![Value::Null; width]));
+    }
+    Value::Array(board)
+}
+
+pub struct TbpBot {
+    pub name: String,
+    pub move_timeout: Duration,
+    // whether `plan` waits for the bot's move, rather than leaving the piece to fall while the bot thinks and
+    // asking again on the next frame; only for games that don't run in real time
+    pub wait_for_moves: bool,
+    child: Child,
+    stdin: ChildStdin,
+    messages: Receiver<String>,
+    // whether the bot knows the current state of the game
+    started: bool,
+    // cleared once the bot process can't be talked to any more
+    alive: bool,
+    // how many pieces the bot has been told about, counting the current one and the queue
+    pieces_sent: u32,
+    // pieces taken from the queue so far, including the ones brought in by holding
+    pieces_drawn: u32,
+    // the map the bot expects once its last move has been played
+    expected_map: Option<Board>,
+    // the piece, counted by `Tetris::num_pieces`, that a suggestion has been asked for and when, until it comes
+    waiting: Option<(u32, Instant)>,
+    // suggestions that were given up on and are still to come, to be skipped when they do
+    stale_suggestions: u32,
+    fallback: Weights,
+    // what went wrong since the front-end last asked, for it to report
+    reports: Vec<String>,
+}
+
+impl TbpBot {
+    // starts the bot and goes through the protocol's handshake
+    pub fn spawn(command: &str) -> Result<TbpBot, String> {
+        TbpBot::launch(command, Stdio::inherit())
+    }
+
+    // the same, with what the bot writes to stderr thrown away, for front-ends that draw on the terminal
+    pub fn spawn_quiet(command: &str) -> Result<TbpBot, String> {
+        TbpBot::launch(command, Stdio::null())
+    }
+
+    fn launch(command: &str, stderr: Stdio) -> Result<TbpBot, String> {
+        let mut words = command.split_whitespace();
+        let program = words.next().ok_or("no bot command given")?;
+        let mut child = Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(stderr)
+            .spawn()
+            .map_err(|e| format!("failed to start {}: {}", program, e))?;
+        let stdin = child.stdin.take().ok_or("no stdin for the bot")?;
+        let stdout = child.stdout.take().ok_or("no stdout for the bot")?;
+
+        // reading happens on its own thread so a silent bot can be timed out
+        let (sender, messages) = channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                match line {
+                    Ok(line) => if sender.send(line).is_err() { break },
+                    Err(_) => break
+                }
+            }
+        });
+
+        let mut bot = TbpBot {
+            name: program.to_string(),
+            move_timeout: DEFAULT_MOVE_TIMEOUT,
+            wait_for_moves: false,
+            child,
+            stdin,
+            messages,
+            started: false,
+            alive: true,
+            pieces_sent: 0,
+            pieces_drawn: 0,
+            expected_map: None,
+            waiting: None,
+            stale_suggestions: 0,
+            fallback: Weights::default(),
+            reports: Vec::new(),
+        };
+
+        let info = bot.receive("info", STARTUP_TIMEOUT)?;
+        if let Some(name) = info["name"].as_str() {
+            bot.name = format!("{} {}", name, info["version"].as_str().unwrap_or(""));
+        }
+        bot.send(&json!({"type": "rules"}))?;
+        bot.receive("ready", STARTUP_TIMEOUT)?;
+        Ok(bot)
+    }
+
+    fn send(&mut self, message: &Value) -> Result<(), String> {
+        let result = writeln!(self.stdin, "{}", message).and_then(|_| self.stdin.flush());
+        if let Err(e) = result {
+            self.alive = false;
+            return Err(format!("failed to write to the bot: {}", e))
+        }
+        Ok(())
+    }
+
+    // waits for a message of the given type, skipping any others
+    fn receive(&mut self, message_type: &str, timeout: Duration) -> Result<Value, String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let line = match self.messages.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => return Err(format!("no {} message from the bot in time", message_type)),
+                Err(RecvTimeoutError::Disconnected) => return Err(self.exited())
+            };
+            if let Some(message) = self.read(&line, message_type)? {
+                return Ok(message)
+            }
+        }
+    }
+
+    // the message of the given type if the bot has sent it, skipping any others, without waiting for it
+    fn poll(&mut self, message_type: &str) -> Result<Option<Value>, String> {
+        loop {
+            let line = match self.messages.try_recv() {
+                Ok(line) => line,
+                Err(TryRecvError::Empty) => return Ok(None),
+                Err(TryRecvError::Disconnected) => return Err(self.exited())
+            };
+            if let Some(message) = self.read(&line, message_type)? {
+                return Ok(Some(message))
+            }
+        }
+    }
+
+    fn exited(&mut self) -> String {
+        self.alive = false;
+        "the bot has exited, the built-in AI takes over".to_string()
+    }
+
+    // the message on the line if it is of the given type; the suggestions given up on are skipped, and an error
+    // answers whatever the bot was asked
+    fn read(&mut self, line: &str, message_type: &str) -> Result<Option<Value>, String> {
+        let message: Value = serde_json::from_str(line).map_err(|e| format!("the bot sent invalid JSON ({}): {}", e, line))?;
+        match message["type"].as_str() {
+            Some("suggestion") if self.stale_suggestions > 0 => self.stale_suggestions -= 1,
+            Some(t) if t == message_type => return Ok(Some(message)),
+            Some("error") => {
+                self.waiting = None;
+                return Err(format!("the bot reported an error: {}", message["reason"]))
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn start(&mut self, tetris: &Tetris) -> Result<(), String> {
+        if self.started {
+            self.send(&json!({"type": "stop"}))?;
+        }
+        let mut queue = Vec::new();
+        if let Some(ref piece) = tetris.current_piece {
+            queue.push(tetris.pieces.name(piece.kind));
+        }
+        queue.extend(tetris.next_pieces.iter().map(|kind| tetris.pieces.name(*kind)));
+        let message = json!({
+            "type": "start",
+            "hold": tetris.held_piece.as_ref().map(|piece| tetris.pieces.name(piece.kind)),
+            "queue": queue,
+            "combo": tetris.combo,
+            "back_to_back": tetris.back_to_back,
+            "board": board_to_json(tetris),
+        });
+        self.send(&message)?;
+        self.started = true;
+        self.pieces_drawn = 0;
+        self.pieces_sent = tetris.next_pieces.len() as u32;
+        Ok(())
+    }
+
+    // tells the bot about the pieces that have appeared at the end of the queue since it last heard
+    fn send_new_pieces(&mut self, tetris: &Tetris) -> Result<(), String> {
+        let known = self.pieces_sent - self.pieces_drawn;
+        let new = (tetris.next_pieces.len() as u32).saturating_sub(known) as usize;
+        let skip = tetris.next_pieces.len() - new;
+        for kind in tetris.next_pieces.iter().skip(skip).cloned().collect::<Vec<_>>() {
+            self.send(&json!({"type": "new_piece", "piece": tetris.pieces.name(kind)}))?;
+            self.pieces_sent += 1;
+        }
+        Ok(())
+    }
+
+    fn ask(&mut self, tetris: &Tetris) -> Result<(), String> {
+        self.send(&json!({"type": "suggest"}))?;
+        self.waiting = Some((tetris.num_pieces, Instant::now()));
+        Ok(())
+    }
+
+    // stops waiting for the suggestion that was asked for, which is skipped if it still comes
+    fn give_up(&mut self) {
+        if self.waiting.take().is_some() {
+            self.stale_suggestions += 1;
+        }
+    }
+
+    // whether the suggestion that was asked for has come, or has been given up on, so that it can be ignored
+    fn skip_suggestion(&mut self) -> Result<bool, String> {
+        let asked = match self.waiting {
+            Some((_, asked)) => asked,
+            None => return Ok(true)
+        };
+        if self.poll("suggestion")?.is_some() {
+            self.waiting = None;
+        } else if asked.elapsed() >= self.move_timeout {
+            self.give_up();
+        }
+        Ok(self.waiting.is_none())
+    }
+
+    fn report(&mut self, message: &str) {
+        let message = format!("Bot {}: {}", self.name, message);
+        self.reports.push(message);
+    }
+
+    // plays this piece with the built-in AI, giving the bot the whole board again next time
+    fn fall_back(&mut self, tetris: &Tetris, error: &str) -> Vec<Action> {
+        self.give_up();
+        self.report(error);
+        self.expected_map = None;
+        best_placement(tetris, &self.fallback).map(|placement| placement.actions).unwrap_or_default()
+    }
+
+    // the inputs for the first suggested move that can actually be played, or None while the bot is still thinking
+    fn suggestion(&mut self, tetris: &Tetris) -> Result<Option<Vec<Action>>, String> {
+        let asked = match self.waiting {
+            Some((_, asked)) => asked,
+            None => return Ok(None)
+        };
+        let suggestion = if self.wait_for_moves {
+            let timeout = self.move_timeout.saturating_sub(asked.elapsed());
+            self.receive("suggestion", timeout)?
+        } else {
+            match self.poll("suggestion")? {
+                Some(suggestion) => suggestion,
+                None if asked.elapsed() >= self.move_timeout => return Err("no suggestion from the bot in time".to_string()),
+                None => return Ok(None)
+            }
+        };
+        self.waiting = None;
+        let moves = suggestion["moves"].as_array().ok_or("the suggestion has no moves")?;
+
+        for suggested in moves {
+            let cells = match location_cells(&suggested["location"], tetris.game_map.len()) {
+                Some(cells) => cells,
+                None => continue
+            };
+            for (piece, prefix) in playable_pieces(tetris) {
+                if tetris.pieces.kind(suggested["location"]["type"].as_str().unwrap_or("")) != Some(piece.kind) {
+                    continue
+                }
+                let found = placements(&tetris.game_map, &piece).into_iter()
+                    .find(|(placed, _)| piece_cells(placed) == cells);
+                if let Some((placed, actions)) = found {
+                    self.send(&json!({"type": "play", "move": suggested}))?;
+                    self.expected_map = Some(place_piece(&tetris.game_map, &placed).0);
+                    // holding into an empty hold brings in the next piece as well
+                    self.pieces_drawn += if !prefix.is_empty() && tetris.held_piece.is_none() { 2 } else { 1 };
+                    let mut path = prefix;
+                    path.extend(actions);
+                    return Ok(Some(path))
+                }
+            }
+        }
+        Err("none of the suggested moves can be played".to_string())
+    }
+}
+
+impl Bot for TbpBot {
+    fn plan(&mut self, tetris: &Tetris) -> Vec<Action> {
+        if self.alive && (tetris.game_map.width() != TBP_BOARD_WIDTH || tetris.game_map.height() > TBP_BOARD_HEIGHT) {
+            self.report(&format!("the protocol has no way to tell it about a {} board, the built-in AI plays instead",
+                tetris.settings.name()));
+            self.alive = false;
+        }
+        // only checked before the bot first hears about the game, rather than for every piece
+        if self.alive && !self.started && !tetris.pieces.is_standard() {
+            self.report("the protocol only has the seven tetriminos, the built-in AI plays instead");
+            self.alive = false;
+        }
+        if !self.alive {
+            return best_placement(tetris, &self.fallback).map(|placement| placement.actions).unwrap_or_default()
+        }
+
+        // the piece locked before the bot came up with a move for it, so its answer is no use when it comes; the
+        // built-in AI plays until then, so the bot is only ever asked one thing at a time, and it is told the game again
+        if self.waiting.is_some_and(|(piece, _)| piece != tetris.num_pieces) {
+            match self.skip_suggestion() {
+                Ok(true) => self.expected_map = None,
+                Ok(false) => return best_placement(tetris, &self.fallback).map(|placement| placement.actions).unwrap_or_default(),
+                Err(e) => return self.fall_back(tetris, &e)
+            }
+        }
+        let asked = if self.waiting.is_some() {
+            Ok(())
+        } else if self.started && self.expected_map.as_ref() == Some(&tetris.game_map) {
+            self.send_new_pieces(tetris).and_then(|_| self.ask(tetris))
+        } else {
+            // if the last move didn't end up where the bot meant it to, its idea of the board is stale
+            self.start(tetris).and_then(|_| self.ask(tetris))
+        };
+
+        match asked.and_then(|_| self.suggestion(tetris)) {
+            Ok(Some(actions)) => actions,
+            // the piece carries on falling while the bot thinks, and it is asked again on the next frame
+            Ok(None) => Vec::new(),
+            Err(e) => self.fall_back(tetris, &e)
+        }
+    }
+
+    fn take_messages(&mut self) -> Vec<String> {
+        self.reports.drain(..).collect()
+    }
+}
+
+impl Drop for TbpBot {
+    fn drop(&mut self) {
+        let _ = self.send(&json!({"type": "quit"}));
+        thread::sleep(Duration::from_millis(100));
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// the external bot if one was given, otherwise the built-in AI, along with what became of starting the external one;
+// a quiet bot's stderr is thrown away, for front-ends that draw on the terminal
+pub fn create_bot(bot_command: Option<&str>, quiet: bool) -> (Box<dyn Bot>, Option<String>) {
+    let mut message = None;
+    if let Some(command) = bot_command {
+        let bot = if quiet {TbpBot::spawn_quiet(command)} else {TbpBot::spawn(command)};
+        match bot {
+            Ok(bot) => {
+                let message = format!("Bot {} is playing", bot.name);
+                return (Box::new(bot), Some(message))
+            }
+            Err(e) => message = Some(format!("Bot {}: {}, the built-in AI plays instead", command, e))
+        }
+    }
+    (Box::new(HeuristicBot::new(Weights::load(WEIGHTS_FILE).unwrap_or_default())), message)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process;
+
+    // a bot written as a shell script, which goes through the handshake and then answers every request for a
+    // suggestion with `answer`, a shell command
+    fn scripted_bot(name: &str, answer: &str) -> TbpBot {
+        let script = format!("echo '{{\"type\":\"info\",\"name\":\"{}\",\"version\":\"1\",\"author\":\"\",\"features\":[]}}'
+while read -r line; do
+    case \"$line\" in
+        *'\"type\":\"rules\"'*) echo '{{\"type\":\"ready\"}}' ;;
+        *'\"type\":\"suggest\"'*) {} ;;
+        *'\"type\":\"quit\"'*) exit 0 ;;
+    esac
+done
+", name, answer);
+        let file_name = ::std::env::temp_dir().join(format!("tetris-tbp-{}-{}.sh", name, process::id()));
+        fs::write(&file_name, script).unwrap();
+        let bot = TbpBot::spawn(&format!("sh {}", file_name.display()));
+        // the shell has it open by the time the bot has said who it is
+        let _ = fs::remove_file(&file_name);
+        let mut bot = bot.unwrap();
+        bot.wait_for_moves = true;
+        bot.move_timeout = Duration::from_millis(300);
+        bot
+    }
+
+    fn suggest(x: i32, y: i32) -> String {
+        format!("echo '{{\"type\":\"suggestion\",\"moves\":[{{\"location\":{{\"type\":\"T\",\"orientation\":\"north\",\
+            \"x\":{},\"y\":{}}},\"spin\":\"none\"}}]}}'", x, y)
+    }
+
+    // an empty classic board with a T in play
+    fn game() -> Tetris {
+        let mut tetris = Tetris::with_seed(4);
+        tetris.game_map = Board::new(10, 16);
+        let kind = tetris.pieces.kind("T").unwrap();
+        tetris.current_piece = Some(tetris.spawn_tetrimino(kind));
+        tetris
+    }
+
+    // where the inputs lock the piece, played a frame each without gravity
+    fn locked_cells(tetris: &Tetris, actions: &[Action]) -> HashSet<(isize, usize)> {
+        let mut game = tetris.clone();
+        for action in actions {
+            game.gravity_frames = 0;
+            game.step(&[*action]);
+        }
+        assert_eq!(game.num_pieces, tetris.num_pieces + 1, "the inputs don't lock the piece");
+        piece_cells(game.locked_piece.as_ref().unwrap())
+    }
+
+    // what the built-in AI would play instead
+    fn fallback(tetris: &Tetris) -> Vec<Action> {
+        best_placement(tetris, &Weights::default()).unwrap().actions
+    }
+
+    #[test]
+    fn answers() {
+        let tetris = game();
+        let mut bot = scripted_bot("answers", &suggest(4, 0));
+        assert_eq!(bot.name, "answers 1");
+        let actions = bot.plan(&tetris);
+        assert!(bot.take_messages().is_empty());
+        assert_eq!(actions.last(), Some(&Action::HardDrop));
+        // pointing up on the bottom line, in the middle of the board
+        let cells = [(3, 15), (4, 15), (5, 15), (4, 14)].iter().cloned().collect::<HashSet<_>>();
+        assert_eq!(locked_cells(&tetris, &actions), cells);
+    }
+
+    #[test]
+    fn answers_while_the_game_goes_on() {
+        let tetris = game();
+        let mut bot = scripted_bot("answers-later", &suggest(1, 0));
+        bot.wait_for_moves = false;
+        let started = Instant::now();
+        let actions = loop {
+            let actions = bot.plan(&tetris);
+            if !actions.is_empty() {
+                break actions
+            }
+            assert!(started.elapsed() < Duration::from_secs(5), "no suggestion came");
+            thread::sleep(Duration::from_millis(5));
+        };
+        assert!(bot.take_messages().is_empty());
+        let cells = [(0, 15), (1, 15), (2, 15), (1, 14)].iter().cloned().collect::<HashSet<_>>();
+        assert_eq!(locked_cells(&tetris, &actions), cells);
+    }
+
+    #[test]
+    fn never_answers() {
+        let tetris = game();
+        let mut bot = scripted_bot("silent", ":");
+        let asked = Instant::now();
+        assert_eq!(bot.plan(&tetris), fallback(&tetris));
+        assert!(asked.elapsed() >= bot.move_timeout);
+        let messages = bot.take_messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("in time"), "{}", messages[0]);
+        // it is asked again for the next piece
+        assert_eq!(bot.plan(&tetris), fallback(&tetris));
+        assert_eq!(bot.take_messages().len(), 1);
+    }
+
+    #[test]
+    fn illegal_move() {
+        let tetris = game();
+        // floating in the middle of the board, and off its side
+        let answer = "echo '{\"type\":\"suggestion\",\"moves\":[\
+            {\"location\":{\"type\":\"T\",\"orientation\":\"north\",\"x\":4,\"y\":8},\"spin\":\"none\"},\
+            {\"location\":{\"type\":\"T\",\"orientation\":\"north\",\"x\":12,\"y\":0},\"spin\":\"none\"},\
+            {\"location\":{\"type\":\"I\",\"orientation\":\"north\",\"x\":4,\"y\":0},\"spin\":\"none\"}]}'";
+        let mut bot = scripted_bot("illegal", answer);
+        assert_eq!(bot.plan(&tetris), fallback(&tetris));
+        let messages = bot.take_messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("none of the suggested moves can be played"), "{}", messages[0]);
+    }
+
+    #[test]
+    fn exits() {
+        let tetris = game();
+        let mut bot = scripted_bot("exits", "exit 0");
+        assert_eq!(bot.plan(&tetris), fallback(&tetris));
+        let messages = bot.take_messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("exited"), "{}", messages[0]);
+        // the built-in AI plays from then on without trying the bot again
+        assert_eq!(bot.plan(&tetris), fallback(&tetris));
+        assert!(bot.take_messages().is_empty());
+    }
+}
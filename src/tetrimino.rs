@@ -1,34 +1,58 @@
-type Piece = Vec<Vec<u8>>;
-type States = Vec<Piece>;
+use board::Board;
 
+pub type Piece = Vec<Vec<u8>>;
+pub type States = Vec<Piece>;
+
+#[derive(Clone)]
 pub struct Tetrimino {
+    // which piece of the set it is, counted from 1
+    pub kind: u8,
     pub states: States,
+    // the cells of each line of each state as bits, column x in bit x, for testing against the board
+    pub masks: Vec<Vec<u16>>,
     pub x: isize,
     pub y: usize,
     pub current_state: u8,
 }
 
 impl Tetrimino {
-    pub fn rotate(&mut self, game_map: &[Vec<u8>]) {
-        let mut tmp_state = self.current_state + 1;
-        if tmp_state as usize >= self.states.len() {
-            tmp_state = 0;
+    // a piece in its first state, at the top of the board
+    pub fn from_states(kind: u8, states: States, x: isize) -> Tetrimino {
+        let masks = states.iter().map(|state| state.iter().map(|line| {
+            line.iter().enumerate().filter(|&(_, case)| *case != 0).fold(0, |mask, (x, _)| mask | 1 << x)
+        }).collect()).collect();
+        Tetrimino {kind, states, masks, x, y: 0, current_state: 0}
+    }
+
+    // returns whether the piece could turn
+    pub fn rotate(&mut self, game_map: &Board, clockwise: bool) -> bool {
+        if let Some((x, state)) = self.rotation(game_map, self.x, self.y, self.current_state, clockwise) {
+            self.x = x;
+            self.current_state = state;
+            true
+        } else {
+            false
         }
+    }
+
+    // where the piece ends up, as its column and state, when turned from the given position, if it can turn at all
+    pub fn rotation(&self, game_map: &Board, x: isize, y: usize, state: u8, clockwise: bool) -> Option<(isize, u8)> {
+        let num_states = self.states.len() as u8;
+        let tmp_state = if clockwise {
+            (state + 1) % num_states
+        } else {
+            (state + num_states - 1) % num_states
+        };
 
         // tests if piece will fit if translated along the x-axis by up to 3 blocks in either direction
         let x_pos = [0, -1, 1, -2, 2, -3];
-        for x in x_pos.iter() {
-            if self.test_position(game_map, tmp_state as usize, self.x + x, self.y) {
-                self.current_state = tmp_state;
-                self.x += *x;
-                break;
-            }
-        }
+        x_pos.iter().map(|shift| x + shift).find(|x| self.test_position(game_map, tmp_state as usize, *x, y))
+            .map(|x| (x, tmp_state))
     }
 
-    pub fn change_position(&mut self, game_map: &[Vec<u8>], new_x: isize, new_y: usize) -> bool {
+    pub fn change_position(&mut self, game_map: &Board, new_x: isize, new_y: usize) -> bool {
         if self.test_position(game_map, self.current_state as usize, new_x, new_y) {
-            self.x = new_x as isize;
+            self.x = new_x;
             self.y = new_y;
             true
         } else {
@@ -36,185 +60,28 @@ impl Tetrimino {
         }
     }
 
-    pub fn test_position(&self, game_map: &[Vec<u8>], tmp_state: usize, x:isize, y:usize) -> bool {
-        for decal_y in 0..4 {
-            for decal_x in 0..4 {
-                let x = x + decal_x;
-                if self.states[tmp_state][decal_y][decal_x as usize] != 0 
-                    && (y + decal_y >= game_map.len() || x < 0 ||  x as usize >= game_map[y + decal_y].len() ||
-                        game_map[y + decal_y][x as usize] != 0) {
-                    return false;
-                }
-            }
-        }
-        return true;
+    pub fn test_position(&self, game_map: &Board, tmp_state: usize, x:isize, y:usize) -> bool {
+        game_map.bits.fits(&self.masks[tmp_state], x, y)
     }
 
-    pub fn test_current_position(&self, game_map: &[Vec<u8>]) -> bool {
+    pub fn test_current_position(&self, game_map: &Board) -> bool {
         self.test_position(game_map, self.current_state as usize, self.x, self.y)
     }
-}
-
-pub trait TetriminoGenerator {
-    fn new() -> Tetrimino;
-}
 
-pub struct TetriminoI;
-
-impl TetriminoGenerator for TetriminoI {
-    fn new() -> Tetrimino {
-        Tetrimino {
-            states: vec![vec![vec![1, 1, 1, 1],
-                        vec![0, 0, 0, 0],
-                        vec![0, 0, 0, 0],
-                        vec![0, 0, 0, 0]],
-                        vec![vec![0, 1, 0, 0],
-                        vec![0, 1, 0, 0],
-                        vec![0, 1, 0, 0],
-                        vec![0, 1, 0, 0]]],
-            x: 4,
-            y: 0,
-            current_state: 0
-        }
+    // the leftmost and rightmost columns of its box the piece covers in the given state
+    pub fn columns(&self, state: usize) -> (isize, isize) {
+        let bits = self.masks[state].iter().fold(0, |bits, mask| bits | mask);
+        (bits.trailing_zeros() as isize, 15 - bits.leading_zeros() as isize)
     }
-}
-
-pub struct TetriminoJ;
 
-impl TetriminoGenerator for TetriminoJ {
-    fn new() -> Tetrimino {
-        Tetrimino {
-            states: vec![vec![vec![2, 2, 2, 0],
-                            vec![2, 0, 0, 0],
-                            vec![0, 0, 0, 0],
-                            vec![0, 0, 0, 0]],
-                    vec![vec![2, 2, 0, 0],
-                        vec![0, 2, 0, 0],
-                        vec![0, 2, 0, 0],
-                        vec![0, 0, 0, 0]],
-                    vec![vec![0, 0, 2, 0],
-                        vec![2, 2, 2, 0],
-                        vec![0, 0, 0, 0],
-                        vec![0, 0, 0, 0]],
-                    vec![vec![2, 0, 0, 0],
-                        vec![2, 0, 0, 0],
-                        vec![2, 2, 0, 0],
-                        vec![0, 0, 0, 0]]],
-            x: 4,
-            y: 0,
-            current_state: 0,
-        }
+    // the top and bottom lines of its box the piece covers in the given state
+    pub fn lines(&self, state: usize) -> (usize, usize) {
+        let masks = &self.masks[state];
+        (masks.iter().position(|mask| *mask != 0).unwrap_or(0), masks.iter().rposition(|mask| *mask != 0).unwrap_or(0))
     }
-}
-
-pub struct TetriminoL;
 
-impl TetriminoGenerator for TetriminoL {
-    fn new() -> Tetrimino {
-        Tetrimino {
-            states: vec![vec![vec![3, 3, 3, 0],
-                            vec![0, 0, 3, 0],
-                            vec![0, 0, 0, 0],
-                            vec![0, 0, 0, 0]],
-                        vec![vec![0, 3, 0, 0],
-                            vec![0, 3, 0, 0],
-                            vec![3, 3, 0, 0],
-                            vec![0, 0, 0, 0]],
-                        vec![vec![3, 0, 0, 0],
-                            vec![3, 3, 3, 0],
-                            vec![0, 0, 0, 0],
-                            vec![0, 0, 0, 0]],
-                        vec![vec![3, 3, 0, 0],
-                            vec![3, 0, 0, 0],
-                            vec![3, 0, 0, 0],
-                            vec![0, 0, 0, 0]]],
-            x: 4,
-            y: 0,
-            current_state: 0,
-        }
-    }
-}
-pub struct TetriminoO;
-
-impl TetriminoGenerator for TetriminoO {
-
-    fn new() -> Tetrimino {
-        Tetrimino {
-            states: vec![vec![vec![4, 4, 0, 0],
-                            vec![4, 4, 0, 0],
-                            vec![0, 0, 0, 0],
-                            vec![0, 0, 0, 0]]],
-            x: 5,
-            y: 0,
-            current_state: 0,
-        }
-    }
-}
-
-pub struct TetriminoS;
-
-impl TetriminoGenerator for TetriminoS {
-    fn new() -> Tetrimino {
-        Tetrimino {
-            states: vec![vec![vec![0, 5, 5, 0],
-                            vec![5, 5, 0, 0],
-                            vec![0, 0, 0, 0],
-                            vec![0, 0, 0, 0]],
-                        vec![vec![0, 5, 0, 0],
-                            vec![0, 5, 5, 0],
-                            vec![0, 0, 5, 0],
-                            vec![0, 0, 0, 0]]],
-            x: 4,
-            y: 0,
-            current_state: 0,
-        }
-    }
-}
-
-pub struct TetriminoZ;
-
-impl TetriminoGenerator for TetriminoZ {
-    fn new() -> Tetrimino {
-        Tetrimino {
-            states: vec![vec![vec![6, 6, 0, 0],
-                            vec![0, 6, 6, 0],
-                            vec![0, 0, 0, 0],
-                            vec![0, 0, 0, 0]],
-                        vec![vec![0, 0, 6, 0],
-                            vec![0, 6, 6, 0],
-                            vec![0, 6, 0, 0],
-                            vec![0, 0, 0, 0]]],
-            x: 4,
-            y: 0,
-            current_state: 0,
-        }
+    // the texture its cells are drawn with
+    pub fn colour(&self) -> u8 {
+        self.states[0].iter().flat_map(|line| line.iter()).cloned().find(|case| *case != 0).unwrap_or(0)
     }
 }
-
-pub struct TetriminoT;
-
-impl TetriminoGenerator for TetriminoT {
-    fn new() -> Tetrimino {
-        Tetrimino {
-            states: vec![vec![vec![7, 7, 7, 0],
-                            vec![0, 7, 0, 0],
-                            vec![0, 0, 0, 0],
-                            vec![0, 0, 0, 0]],
-                        vec![vec![0, 7, 0, 0],
-                            vec![7, 7, 0, 0],
-                            vec![0, 7, 0, 0],
-                            vec![0, 0, 0, 0]],
-                        vec![vec![0, 7, 0, 0],
-                            vec![7, 7, 7, 0],
-                            vec![0, 0, 0, 0],
-                            vec![0, 0, 0, 0]],
-                        vec![vec![0, 7, 0, 0],
-                            vec![0, 7, 7, 0],
-                            vec![0, 7, 0, 0],
-                            vec![0, 0, 0, 0]]],
-            x: 4,
-            y: 0,
-            current_state: 0,
-        }
-    }
-}
\ No newline at end of file
@@ -1,113 +1,397 @@
-extern crate rand;
-use tetrimino::*; 
+use tetrimino::Tetrimino;
+use board::{Board, MAX_WIDTH};
+use pieces::PieceSet;
+use randomizer::{random_seed, Randomizer};
+use input::Action;
+use garbage::{attack, t_spin, GarbageQueue, LineClear, TSpin};
 
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+pub const FPS: u32 = 60;
+// number of upcoming pieces that are known in advance
+pub const NUM_NEXT_PIECES: usize = 5;
+const LEVEL_TIMES: [u32; 10] = [1000, 850, 700, 600, 500, 400, 300, 250, 221, 190];
 const LEVEL_LINES: [u32; 10] = [20,   40,  60,  80,  100, 120, 140, 160, 180, 200];
+// the levels run from 1 up to this one
+pub const MAX_LEVEL: u32 = LEVEL_TIMES.len() as u32;
+
+// the size of the board and the rules for when the game is lost
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BoardSettings {
+    pub width: usize,
+    // the lines that are shown
+    pub height: usize,
+    // lines above the shown ones, which pieces come in on and can stick out into
+    pub hidden: usize,
+    // whether locking a piece that is partly out of view loses the game, not only one that is all out of view
+    pub partial_lock_out: bool,
+}
+
+impl BoardSettings {
+    // the board the game has always had, with pieces coming in on the top lines of it
+    pub const CLASSIC: BoardSettings = BoardSettings {width: 10, height: 16, hidden: 0, partial_lock_out: false};
+    pub const GUIDELINE: BoardSettings = BoardSettings {width: 10, height: 20, hidden: 20, partial_lock_out: false};
+
+    // "classic", "guideline", or "<width>x<height>" optionally followed by "+<hidden lines>"
+    pub fn parse(spec: &str) -> Option<BoardSettings> {
+        match spec {
+            "classic" => return Some(BoardSettings::CLASSIC),
+            "guideline" => return Some(BoardSettings::GUIDELINE),
+            _ => {}
+        }
+        let (size, hidden) = match spec.find('+') {
+            Some(i) => (&spec[..i], spec[i + 1..].parse::<u8>().ok()?),
+            None => (spec, 0)
+        };
+        let mut size = size.split('x');
+        let width = size.next()?.parse::<u8>().ok()? as usize;
+        let height = size.next()?.parse::<u8>().ok()? as usize;
+        // the tetriminos have to fit on the board whichever way they are turned
+        if size.next().is_some() || !(4..=MAX_WIDTH).contains(&width) || height < 4 {
+            return None
+        }
+        Some(BoardSettings {width, height, hidden: hidden as usize, partial_lock_out: false})
+    }
+
+    pub fn name(&self) -> String {
+        match *self {
+            BoardSettings::CLASSIC => "classic".to_string(),
+            BoardSettings::GUIDELINE => "guideline".to_string(),
+            _ if self.hidden == 0 => format!("{}x{}", self.width, self.height),
+            _ => format!("{}x{}+{}", self.width, self.height, self.hidden)
+        }
+    }
 
+    // the lines of the game map, the hidden ones at the top
+    pub fn total_height(&self) -> usize {
+        self.hidden + self.height
+    }
+}
+
+impl Default for BoardSettings {
+    fn default() -> BoardSettings {
+        BoardSettings::CLASSIC
+    }
+}
+
+#[derive(Clone)]
 pub struct Tetris {
-    pub game_map: Vec<Vec<u8>>,
+    pub settings: BoardSettings,
+    pub pieces: Arc<PieceSet>,
+    pub game_map: Board,
     pub current_level: u32,
     pub score: u32,
     pub num_lines: u32,
-    pub current_piece: Option<Tetrimino>
+    pub current_piece: Option<Tetrimino>,
+    // kinds of the pieces that come after the current one
+    pub next_pieces: VecDeque<u8>,
+    pub held_piece: Option<Tetrimino>,
+    pub can_hold: bool,
+    pub seed: u64,
+    pub randomizer: Randomizer,
+    // number of steps the game has been simulated for
+    pub frame: u32,
+    // steps since the piece last fell on its own
+    pub gravity_frames: u32,
+    pub game_over: bool,
+    pub num_pieces: u32,
+    // the last piece that was locked in place, where it ended up
+    pub locked_piece: Option<Tetrimino>,
+    // what locking the last piece did
+    pub last_clear: Option<LineClear>,
+    // pieces in a row that have cleared lines
+    pub combo: u32,
+    // whether the last clear was a tetris or a T-spin, so that another one in a row sends more
+    pub back_to_back: bool,
+    // whether the current piece was last moved by turning it, which a T-spin has to be
+    pub spun: bool,
+    // lines sent by the game's clears that an opponent hasn't taken yet
+    pub attack: u32,
+    // every line the game's clears have sent, including the ones that cancelled garbage
+    pub sent: u32,
+    // lines sent by an opponent waiting to come up the board
+    pub garbage: GarbageQueue,
 }
 
 impl Tetris {
     pub fn new() -> Tetris {
-        let mut game_map = Vec::new();
-        for _ in 0..16 {
-            game_map.push(vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
-        }
-        Tetris {
-            game_map: game_map,
+        Tetris::with_seed(random_seed())
+    }
+
+    // the game only depends on the seed and the actions passed to `step`, so it can be replayed exactly
+    pub fn with_seed(seed: u64) -> Tetris {
+        Tetris::with_settings(seed, BoardSettings::default(), Arc::new(PieceSet::standard()))
+    }
+
+    pub fn with_settings(seed: u64, settings: BoardSettings, pieces: Arc<PieceSet>) -> Tetris {
+        let mut tetris = Tetris {
+            settings,
+            pieces,
+            game_map: Board::new(settings.width, settings.total_height()),
             current_level: 1,
             score: 0,
             num_lines: 0,
-            current_piece: None
+            current_piece: None,
+            next_pieces: VecDeque::new(),
+            held_piece: None,
+            can_hold: true,
+            seed,
+            randomizer: Randomizer::new(seed),
+            frame: 0,
+            gravity_frames: 0,
+            game_over: false,
+            num_pieces: 0,
+            locked_piece: None,
+            last_clear: None,
+            combo: 0,
+            back_to_back: false,
+            spun: false,
+            attack: 0,
+            sent: 0,
+            garbage: GarbageQueue::default(),
+        };
+        tetris.fill_next_pieces();
+        tetris
+    }
+
+    // advances the game by one frame, applying the actions the player made during it
+    pub fn step(&mut self, actions: &[Action]) {
+        if self.game_over {
+            return
+        }
+
+        if self.current_piece.is_none() {
+            let current_piece = self.create_new_tetrimino();
+            // block out
+            if !current_piece.test_current_position(&self.game_map) {
+                self.game_over = true;
+                return
+            }
+            self.current_piece = Some(current_piece);
+            self.spun = false;
+        }
+        self.garbage.step();
+
+        for action in actions {
+            if self.apply_action(*action) {
+                break
+            }
+        }
+
+        self.gravity_frames += 1;
+        if self.gravity_frames > self.gravity_delay() {
+            let mut make_permanent = false;
+            if let Some(ref mut piece) = self.current_piece {
+                let x = piece.x;
+                let y = piece.y + 1;
+                make_permanent = !piece.change_position(&self.game_map, x, y);
+                self.spun &= make_permanent;
+            }
+            if make_permanent {
+                self.make_permanent();
+            }
+            self.gravity_frames = 0;
         }
+        self.frame += 1;
+    }
+
+    // number of frames a piece waits before falling by one line at the current level
+    pub fn gravity_delay(&self) -> u32 {
+        LEVEL_TIMES[self.current_level as usize - 1] * FPS / 1000
     }
 
-    pub fn create_new_tetrimino(&self) -> Tetrimino {
-        static mut PREV: u8 = 7;
-        let mut rand_num = rand::random::<u8>() % 7;
-        if unsafe {PREV} == rand_num {
-            rand_num = rand::random::<u8>() % 7;
+    // returns whether the action locked the current piece in place
+    pub fn apply_action(&mut self, action: Action) -> bool {
+        let mut make_permanent = false;
+
+        if action == Action::Hold {
+            self.hold();
+            self.gravity_frames = 0;
+            return make_permanent
+        }
+
+        if let Some(ref mut piece) = self.current_piece {
+            let x = piece.x;
+            let y = piece.y;
+
+            match action {
+                Action::MoveLeft => self.spun &= !piece.change_position(&self.game_map, x - 1, y),
+                Action::MoveRight => self.spun &= !piece.change_position(&self.game_map, x + 1, y),
+                Action::SoftDrop => {
+                    self.gravity_frames = 0;
+                    make_permanent = !piece.change_position(&self.game_map, x, y + 1);
+                    self.spun &= make_permanent;
+                },
+                Action::RotateClockwise => self.spun |= piece.rotate(&self.game_map, true),
+                Action::RotateCounterClockwise => self.spun |= piece.rotate(&self.game_map, false),
+                Action::HardDrop => {
+                    let mut y = y;
+                    while piece.change_position(&self.game_map, x, y + 1) {
+                        y += 1;
+                        self.spun = false;
+                    }
+                    make_permanent = true
+                },
+                _ => {}
+            }
         }
-        unsafe { PREV = rand_num; }
-        match rand_num {
-            0 => TetriminoI::new(),
-            1 => TetriminoJ::new(),
-            2 => TetriminoL::new(),
-            3 => TetriminoO::new(),
-            4 => TetriminoS::new(),
-            5 => TetriminoZ::new(),
-            6 => TetriminoT::new(),
-            _ => unreachable!()
+        if make_permanent {
+            self.make_permanent();
         }
+        make_permanent
     }
 
-    pub fn check_lines(&mut self) {
-        let mut y = 0;
-        let mut score_add = 0;
+    pub fn create_new_tetrimino(&mut self) -> Tetrimino {
+        self.fill_next_pieces();
+        let kind = self.next_pieces.pop_front().unwrap();
+        self.fill_next_pieces();
+        self.spawn_tetrimino(kind)
+    }
 
-        while y < self.game_map.len() {
-            let mut complete = true;
+    pub fn spawn_tetrimino(&self, kind: u8) -> Tetrimino {
+        self.pieces.spawn(&self.settings, &self.game_map, kind)
+    }
 
-            for x in &self.game_map[y] {
-                if *x == 0 {
-                    complete = false;
-                    break
+    pub fn fill_next_pieces(&mut self) {
+        while self.next_pieces.len() < NUM_NEXT_PIECES {
+            let piece = self.randomizer.next_piece(self.pieces.num_kinds() as u8);
+            self.next_pieces.push_back(piece + 1);
+        }
+    }
+
+    // swaps the current piece with the held one, at most once per piece
+    pub fn hold(&mut self) {
+        if !self.can_hold {
+            return
+        }
+        if let Some(piece) = self.current_piece.take() {
+            // without a held piece the next one comes in on the following step
+            if let Some(kind) = self.held_piece.as_ref().map(|held| held.kind) {
+                let held = self.spawn_tetrimino(kind);
+                if !held.test_current_position(&self.game_map) {
+                    self.current_piece = Some(piece);
+                    return
                 }
+                self.current_piece = Some(held);
             }
-            if complete {
-                score_add += self.current_level;
-                self.game_map.remove(y);
-                y -= 1;
-            }
-            y += 1;
+            self.held_piece = Some(self.pieces.create(piece.kind));
+            self.can_hold = false;
+            self.spun = false;
         }
-        if self.game_map.len() == 0 {
+    }
+
+    // clears the full lines, working out what they send to an opponent; with nothing cleared, the garbage that is
+    // ready comes up instead
+    pub fn check_lines(&mut self, t_spin: TSpin) {
+        let cleared = self.game_map.clear_lines();
+        let mut score_add = self.current_level * cleared;
+        if cleared as usize == self.game_map.height() {
             score_add += 1000;
         }
         self.update_score(score_add);
-        while self.game_map.len() < 16 {
+        for _ in 0..cleared {
             self.increase_line();
-            self.game_map.insert(0, vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
         }
+
+        let clear = LineClear {lines: cleared, t_spin, perfect: cleared > 0 && self.game_map.bits.rows.iter().all(|row| *row == 0)};
+        if cleared > 0 {
+            self.combo += 1;
+            let lines = attack(&clear, self.combo, self.back_to_back);
+            self.sent += lines;
+            self.back_to_back = clear.is_difficult();
+            // the garbage on its way is cancelled first
+            self.attack += self.garbage.cancel(lines);
+        } else {
+            self.combo = 0;
+            for pending in self.garbage.take_ready() {
+                self.add_garbage(pending.lines, pending.hole);
+            }
+        }
+        self.last_clear = Some(clear);
     }
 
-    pub fn make_permanent(&mut self) {
-        let mut to_add = 0;
-        if let Some(ref mut piece) = self.current_piece {
-            let mut shift_y = 0;
+    // garbage from an opponent, which comes up the board after `delay` frames
+    pub fn receive_garbage(&mut self, lines: u32, hole: usize, delay: u32) {
+        self.garbage.push(lines, hole, delay);
+    }
 
-            while shift_y < piece.states[piece.current_state as usize].len() && piece.y + shift_y < self.game_map.len() {
-                let mut shift_x = 0;
+    // the lines the game has sent since this was last called
+    pub fn take_attack(&mut self) -> u32 {
+        let attack = self.attack;
+        self.attack = 0;
+        attack
+    }
 
-                while shift_x < piece.states[piece.current_state as usize][shift_y].len() && (piece.x + shift_x as isize) < 
-                    self.game_map[piece.y + shift_y].len() as isize {
-                    if piece.states[piece.current_state as usize][shift_y][shift_x] != 0 {
-                        let x = piece.x + shift_x as isize;
-                        self.game_map[piece.y + shift_y][x as usize] = piece.states[piece.current_state as usize][shift_y][shift_x];
-                    }
-                    shift_x += 1;
-                }
-                shift_y += 1;
+    pub fn make_permanent(&mut self) {
+        let mut to_add = 0;
+        let mut locked_out = false;
+        let mut spin = TSpin::None;
+        if let Some(ref piece) = self.current_piece {
+            if self.spun {
+                spin = t_spin(&self.game_map, piece);
             }
+            // lock out, when the piece is left above the shown lines
+            let (top, bottom) = piece.lines(piece.current_state as usize);
+            let out_of_view = if self.settings.partial_lock_out { piece.y + top } else { piece.y + bottom };
+            locked_out = out_of_view < self.settings.hidden;
+            self.game_map.place(piece);
             to_add += self.current_level;
+            self.num_pieces += 1;
         }
         self.update_score(to_add);
-        self.check_lines();
-        self.current_piece = None;
+        self.check_lines(spin);
+        self.locked_piece = self.current_piece.take();
+        self.can_hold = true;
+        self.gravity_frames = 0;
+        if locked_out {
+            self.game_over = true;
+        }
+    }
+
+    // lines sent by an opponent, coming in under the stack with a gap at `hole`; the falling piece is pushed up
+    // with it, and the game is lost if the stack goes over the top or the piece has nowhere to go
+    pub fn add_garbage(&mut self, lines: u32, hole: usize) {
+        if lines == 0 || self.game_over {
+            return
+        }
+        if !self.game_map.add_garbage(lines as usize, hole) {
+            self.game_over = true;
+        }
+        if let Some(ref mut piece) = self.current_piece {
+            while !piece.test_current_position(&self.game_map) {
+                if piece.y == 0 {
+                    self.game_over = true;
+                    break
+                }
+                piece.y -= 1;
+            }
+        }
     }
 
     pub fn update_score(&mut self, to_add: u32) {
         self.score += to_add;
     }
 
+    // the lines still to clear to get to the next level, unless this is the last one
+    pub fn lines_to_next_level(&self) -> Option<u32> {
+        match LEVEL_LINES.get(self.current_level as usize - 1) {
+            Some(lines) if (self.current_level as usize) < LEVEL_LINES.len() => Some((lines + 1).saturating_sub(self.num_lines)),
+            _ => None
+        }
+    }
+
     pub fn increase_line(&mut self) {
         self.num_lines += 1;
-        if self.num_lines > LEVEL_LINES[self.current_level as usize - 1] {
+        // the last level goes on forever
+        if self.num_lines > LEVEL_LINES[self.current_level as usize - 1] && (self.current_level as usize) < LEVEL_LINES.len() {
             self.current_level += 1;
         }
     }
-}
\ No newline at end of file
+}
+
+impl Default for Tetris {
+    fn default() -> Tetris {
+        Tetris::new()
+    }
+}
@@ -0,0 +1,185 @@
+// Tunes the weights of the heuristic bot with a genetic algorithm, the fitness of a set of weights being the
+// score it reaches on average over simulated games, which rewards both clearing lines and staying alive
+use ai::{Bot, HeuristicBot, Weights};
+use highscore::{read_from_file, write_to_file};
+use replay::Mode;
+use sim::run_games;
+use pieces::PieceSet;
+use tetris::BoardSettings;
+
+use rand::distributions::{IndependentSample, Normal};
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use std::io;
+use std::sync::Arc;
+
+const CHECKPOINT_VERSION: u32 = 1;
+// share of the population, the best of it, that goes on to the next generation unchanged
+const ELITE_SHARE: f64 = 0.1;
+// share of the population taking part in each tournament that picks a parent
+const TOURNAMENT_SHARE: f64 = 0.1;
+const MUTATION_CHANCE: f64 = 0.05;
+const MUTATION_SIZE: f64 = 0.2;
+
+#[derive(Clone)]
+pub struct TuneSettings {
+    pub population: usize,
+    pub games: u32,
+    pub max_pieces: u32,
+    pub threads: usize,
+    pub seed: u64,
+    pub board: BoardSettings,
+    pub pieces: Arc<PieceSet>,
+}
+
+#[derive(Clone)]
+pub struct Individual {
+    pub weights: Weights,
+    pub fitness: f64,
+}
+
+pub struct Tuner {
+    pub settings: TuneSettings,
+    pub generation: u32,
+    // sorted from the fittest down
+    pub population: Vec<Individual>,
+}
+
+// the bot only compares placements with each other, so only the direction of the weights matters
+fn normalize(values: [f64; 8]) -> [f64; 8] {
+    let length = values.iter().map(|value| value * value).sum::<f64>().sqrt();
+    if length == 0.0 {
+        return values
+    }
+    let mut normalized = values;
+    for value in normalized.iter_mut() {
+        *value /= length;
+    }
+    normalized
+}
+
+// every generation draws its random numbers from its own generator, so a resumed run goes on as it would have
+fn generation_rng(seed: u64, generation: u32) -> XorShiftRng {
+    let mixed = seed ^ (generation as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    XorShiftRng::from_seed([mixed as u32, (mixed >> 32) as u32, generation, 0x2545_f491])
+}
+
+fn sort_population(population: &mut [Individual]) {
+    population.sort_by(|a, b| b.fitness.total_cmp(&a.fitness));
+}
+
+impl Tuner {
+    // starts from random weights
+    pub fn new(settings: TuneSettings) -> Tuner {
+        let mut rng = generation_rng(settings.seed, 0);
+        let weights = (0..settings.population).map(|_| {
+            let mut values = [0.0; 8];
+            for value in values.iter_mut() {
+                *value = rng.gen_range(-1.0, 1.0);
+            }
+            Weights::from_array(normalize(values))
+        }).collect::<Vec<_>>();
+
+        let mut tuner = Tuner {settings, generation: 0, population: Vec::new()};
+        tuner.population = tuner.evaluate_all(weights);
+        tuner
+    }
+
+    pub fn best(&self) -> &Individual {
+        &self.population[0]
+    }
+
+    // the mean score over this generation's games
+    fn evaluate(&self, weights: Weights) -> f64 {
+        let games = self.settings.games as u64;
+        let first_seed = self.settings.seed.wrapping_add(self.generation as u64 * games);
+        let seeds = (0..games).map(|game| first_seed.wrapping_add(game)).collect::<Vec<_>>();
+        let make_bot = || -> Box<dyn Bot> { Box::new(HeuristicBot::new(weights)) };
+        let settings = &self.settings;
+        let results = run_games(Mode::Marathon, settings.board, &settings.pieces, &seeds, settings.threads,
+            settings.max_pieces, make_bot);
+        results.iter().map(|game| game.score as f64).sum::<f64>() / results.len().max(1) as f64
+    }
+
+    fn evaluate_all(&self, weights: Vec<Weights>) -> Vec<Individual> {
+        let mut population = weights.into_iter()
+            .map(|weights| Individual {weights, fitness: self.evaluate(weights)})
+            .collect::<Vec<_>>();
+        sort_population(&mut population);
+        population
+    }
+
+    fn tournament(&self, rng: &mut XorShiftRng) -> &Individual {
+        let size = ((self.population.len() as f64 * TOURNAMENT_SHARE) as usize).max(2);
+        (0..size).map(|_| &self.population[rng.gen_range(0, self.population.len())])
+            .max_by(|a, b| a.fitness.total_cmp(&b.fitness))
+            .unwrap()
+    }
+
+    // keeps the best weights and fills the rest of the population with children of the fittest ones
+    pub fn next_generation(&mut self) {
+        self.generation += 1;
+        let mut rng = generation_rng(self.settings.seed, self.generation);
+        let mutation = Normal::new(0.0, MUTATION_SIZE);
+        let elites = ((self.population.len() as f64 * ELITE_SHARE).ceil() as usize).min(self.population.len());
+
+        let mut weights = self.population[..elites].iter().map(|individual| individual.weights).collect::<Vec<_>>();
+        while weights.len() < self.settings.population {
+            let (first, second) = (self.tournament(&mut rng).clone(), self.tournament(&mut rng).clone());
+            // the child takes more after the fitter parent
+            let total = first.fitness + second.fitness;
+            let share = if total > 0.0 { first.fitness / total } else { 0.5 };
+            let mut values = [0.0; 8];
+            for (index, value) in values.iter_mut().enumerate() {
+                *value = first.weights.to_array()[index] * share + second.weights.to_array()[index] * (1.0 - share);
+            }
+            if rng.gen::<f64>() < MUTATION_CHANCE {
+                values[rng.gen_range(0, values.len())] += mutation.ind_sample(&mut rng);
+            }
+            weights.push(Weights::from_array(normalize(values)));
+        }
+        // the survivors play this generation's games as well, so every fitness is measured on the same seeds
+        self.population = self.evaluate_all(weights);
+    }
+
+    // the settings other than the seed come from the command line, the population from the file
+    pub fn save_checkpoint(&self, file_name: &str) -> io::Result<()> {
+        let mut content = format!("version {}\n", CHECKPOINT_VERSION);
+        content += &format!("seed {}\n", self.settings.seed);
+        content += &format!("generation {}\n", self.generation);
+        for individual in &self.population {
+            let values = individual.weights.to_array().iter().map(|value| value.to_string()).collect::<Vec<_>>();
+            content += &format!("individual {} {}\n", individual.fitness, values.join(" "));
+        }
+        write_to_file(&content, file_name)
+    }
+
+    pub fn load_checkpoint(file_name: &str, settings: TuneSettings) -> Option<Tuner> {
+        let content = read_from_file(file_name).ok()?;
+        let mut tuner = Tuner {settings, generation: 0, population: Vec::new()};
+        let mut version = None;
+        for line in content.lines() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("version") => version = words.next()?.parse::<u32>().ok(),
+                Some("seed") => tuner.settings.seed = words.next()?.parse().ok()?,
+                Some("generation") => tuner.generation = words.next()?.parse().ok()?,
+                Some("individual") => {
+                    // "NaN" and "inf" parse as numbers, but no game scores them
+                    let fitness = words.next()?.parse::<f64>().ok().filter(|fitness| fitness.is_finite())?;
+                    let mut values = [0.0; 8];
+                    for value in values.iter_mut() {
+                        *value = words.next()?.parse::<f64>().ok().filter(|value| value.is_finite())?;
+                    }
+                    tuner.population.push(Individual {weights: Weights::from_array(values), fitness});
+                }
+                _ => {}
+            }
+        }
+        if version != Some(CHECKPOINT_VERSION) || tuner.population.is_empty() {
+            return None
+        }
+        sort_population(&mut tuner.population);
+        Some(tuner)
+    }
+}
@@ -0,0 +1,101 @@
+// Two games played against each other: the lines one player's clears send come up as garbage on the other's board,
+// and the first player to top out loses the round. A match is the best of a number of rounds.
+use garbage::DEFAULT_GARBAGE_DELAY;
+use input::Action;
+use pieces::PieceSet;
+use randomizer::Randomizer;
+use tetris::{BoardSettings, Tetris};
+
+use std::sync::Arc;
+
+pub const NUM_PLAYERS: usize = 2;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RoundResult {
+    Winner(usize),
+    // both players topped out on the same frame
+    Draw,
+}
+
+pub struct Versus {
+    pub settings: BoardSettings,
+    pub pieces: Arc<PieceSet>,
+    pub games: Vec<Tetris>,
+    // rounds won by each player
+    pub wins: Vec<u32>,
+    // rounds needed to win the match
+    pub rounds_to_win: u32,
+    pub round: u32,
+    // lines each player has sent this round
+    pub sent: Vec<u32>,
+    // frames garbage waits before it can come up the board it was sent to
+    pub garbage_delay: u32,
+    // the seeds of the rounds and the holes of the garbage come from here, so a match is deterministic too
+    randomizer: Randomizer,
+}
+
+impl Versus {
+    // a match that is the best of `rounds`
+    pub fn new(seed: u64, rounds: u32, settings: BoardSettings, pieces: Arc<PieceSet>) -> Versus {
+        let mut versus = Versus {
+            settings,
+            pieces,
+            games: Vec::new(),
+            wins: vec![0; NUM_PLAYERS],
+            rounds_to_win: rounds / 2 + 1,
+            round: 0,
+            sent: Vec::new(),
+            garbage_delay: DEFAULT_GARBAGE_DELAY,
+            randomizer: Randomizer::new(seed),
+        };
+        versus.next_round();
+        versus
+    }
+
+    // both players get the same pieces, so neither is luckier than the other
+    pub fn next_round(&mut self) {
+        let seed = self.randomizer.next_seed();
+        self.games = (0..NUM_PLAYERS).map(|_| Tetris::with_settings(seed, self.settings, self.pieces.clone())).collect();
+        self.sent = vec![0; NUM_PLAYERS];
+        self.round += 1;
+    }
+
+    // advances both games by a frame, with the actions of each player, and returns the result once the round is over
+    pub fn step(&mut self, actions: &[Vec<Action>]) -> Option<RoundResult> {
+        let mut attacks = vec![0; NUM_PLAYERS];
+        for (player, game) in self.games.iter_mut().enumerate() {
+            game.step(&actions[player]);
+            attacks[player] = game.take_attack();
+        }
+
+        for (player, attack) in attacks.into_iter().enumerate() {
+            if attack == 0 {
+                continue
+            }
+            let hole = self.randomizer.next_below(self.settings.width);
+            self.games[opponent(player)].receive_garbage(attack, hole, self.garbage_delay);
+            self.sent[player] += attack;
+        }
+
+        if self.games.iter().all(|game| !game.game_over) {
+            return None
+        }
+        let result = match self.games.iter().position(|game| !game.game_over) {
+            Some(player) => RoundResult::Winner(player),
+            None => RoundResult::Draw
+        };
+        if let RoundResult::Winner(player) = result {
+            self.wins[player] += 1;
+        }
+        Some(result)
+    }
+
+    // the player who has won enough rounds, once one has
+    pub fn winner(&self) -> Option<usize> {
+        self.wins.iter().position(|wins| *wins >= self.rounds_to_win)
+    }
+}
+
+pub fn opponent(player: usize) -> usize {
+    (player + 1) % NUM_PLAYERS
+}